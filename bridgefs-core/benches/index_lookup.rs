@@ -0,0 +1,86 @@
+//! Compares the cost of looking up one inode at 1k and 100k inodes, both by
+//! decoding the whole index for each lookup, as every operation used to, and
+//! through `BridgeFS`, which keeps the current index decoded.
+//!
+//! Run with `cargo bench -p bridgefs-core --bench index_lookup`.
+
+use std::time::{Duration, Instant};
+
+use bridgefs_core::{
+    bridgefs::BridgeFS,
+    content_store::{InMemoryContentStore, ParsingContentStoreExt},
+    file_record::{CommonAttrs, DirectoryRecord, FileRecord, Record},
+    hash_pointer::{InMemoryHashPointerReference, TypedHashPointer},
+    index::INodeIndex,
+    inode::INode,
+    manifest::ReferenceCounting,
+};
+
+const LOOKUPS: usize = 200;
+
+/// A store holding an index of `inodes` entries; every inode but the root
+/// points at the same file record, since only the index size matters here
+fn populated_store(inodes: u64) -> (InMemoryContentStore, TypedHashPointer<INodeIndex>) {
+    let mut store = InMemoryContentStore::default();
+    let root_hash = store
+        .add_parsed(&Record::Directory(DirectoryRecord::default()))
+        .unwrap();
+    let content_hash = store.add_parsed(&Default::default()).unwrap();
+    let file_hash = store
+        .add_parsed(&Record::File(
+            FileRecord::builder()
+                .content_hash(content_hash)
+                .size(0)
+                .common_attrs(CommonAttrs::default())
+                .build(),
+        ))
+        .unwrap();
+    let mut index = INodeIndex::new(INode::ROOT, root_hash);
+    for _ in 1..inodes {
+        let (inode, _) = index.allocate_inode();
+        index.update_inode(inode, file_hash.clone());
+    }
+    let index_hash = store.add_parsed(&index).unwrap();
+    (store, index_hash)
+}
+
+fn decode_per_lookup(inodes: u64) -> Duration {
+    let (store, index_hash) = populated_store(inodes);
+    let start = Instant::now();
+    for i in 0..LOOKUPS {
+        let index = store.get_parsed(&index_hash);
+        assert!(
+            index
+                .lookup_inode(&INode::new(i as u64 % inodes + 1))
+                .is_some()
+        );
+    }
+    start.elapsed()
+}
+
+fn bridgefs_lookup(inodes: u64) -> Duration {
+    let (store, index_hash) = populated_store(inodes);
+    let pointer = InMemoryHashPointerReference::new(index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Deferred);
+    let start = Instant::now();
+    for i in 0..LOOKUPS {
+        bridgefs
+            .lookup_record_by_inode(INode::new(i as u64 % inodes + 1))
+            .unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    for inodes in [1_000, 100_000] {
+        for (name, elapsed) in [
+            ("decode per lookup", decode_per_lookup(inodes)),
+            ("bridgefs lookup", bridgefs_lookup(inodes)),
+        ] {
+            println!(
+                "{inodes} inodes, {name}: {LOOKUPS} lookups in {elapsed:?} ({:.1?} each)",
+                elapsed / LOOKUPS as u32
+            );
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Compares write throughput with eager and deferred reference counting.
+//!
+//! Run with `cargo bench -p bridgefs-core --bench write_throughput`.
+
+use std::time::{Duration, Instant};
+
+use bridgefs_core::{
+    bridgefs::BridgeFS,
+    content_store::{InMemoryContentStore, ParsingContentStoreExt},
+    file_record::{CommonAttrs, DirectoryRecord, Record},
+    hash_pointer::InMemoryHashPointerReference,
+    index::INodeIndex,
+    inode::INode,
+    manifest::ReferenceCounting,
+};
+
+const WRITES: usize = 5_000;
+const WRITE_SIZE: usize = 4096;
+
+fn write_loop(counting: ReferenceCounting) -> Duration {
+    let mut store = InMemoryContentStore::default();
+    let root_hash = store
+        .add_parsed(&Record::Directory(DirectoryRecord::default()))
+        .unwrap();
+    let index_hash = store
+        .add_parsed(&INodeIndex::new(INode::ROOT, root_hash))
+        .unwrap();
+    let pointer = InMemoryHashPointerReference::new(index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, counting);
+
+    let file = bridgefs
+        .create_file(INode::ROOT, "bench".into(), CommonAttrs::default())
+        .unwrap();
+    let start = Instant::now();
+    for i in 0..WRITES {
+        let data = vec![i as u8; WRITE_SIZE];
+        bridgefs.write_to_file(file.inode, 0, &data).unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    for counting in [ReferenceCounting::Eager, ReferenceCounting::Deferred] {
+        let elapsed = write_loop(counting);
+        println!(
+            "{:?}: {} writes of {} bytes in {:?} ({:.0} writes/s)",
+            counting,
+            WRITES,
+            WRITE_SIZE,
+            elapsed,
+            WRITES as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
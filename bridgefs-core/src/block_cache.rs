@@ -0,0 +1,49 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{data_block::DataBlock, hash_pointer::HashPointer};
+
+/// Decoded data blocks kept by content hash, so content shared by several
+/// files is fetched and decoded once. Blocks never change under a hash, so
+/// nothing needs invalidating; the oldest is dropped once `capacity` blocks
+/// are held.
+#[derive(Debug)]
+pub struct BlockCache {
+    capacity: usize,
+    blocks: HashMap<HashPointer, DataBlock>,
+    order: VecDeque<HashPointer>,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&self, hash: &HashPointer) -> Option<&DataBlock> {
+        self.blocks.get(hash)
+    }
+
+    pub fn insert(&mut self, hash: HashPointer, block: DataBlock) {
+        if self.capacity == 0 || self.blocks.contains_key(&hash) {
+            return;
+        }
+        if self.order.len() >= self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.blocks.remove(&oldest);
+        }
+        self.order.push_back(hash.clone());
+        self.blocks.insert(hash, block);
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
@@ -1,117 +1,820 @@
-use std::time::SystemTime;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use bincode::{Decode, Encode};
 
 use crate::{
-    content_store::ContentStore,
+    block_cache::BlockCache,
+    checkpoint_log::{CheckpointLog, CheckpointLogEntry},
+    compression::{self, CompressedBlock, Compression},
+    content_store::{ContentStore, ContentStoreError, InMemoryContentStore, OverlayContentStore},
     counting_store::CountingStore,
     data_block::DataBlock,
-    file_record::{CommonAttrs, DirectoryRecord, FileRecord, Record},
+    file_reader::FileReader,
+    file_record::{CommonAttrs, DefaultAttrs, DirectoryRecord, FileRecord, Record},
+    file_writer::FileWriter,
     filename::Filename,
-    hash_pointer::{TypedHashPointer, TypedHashPointerReference},
+    hash_pointer::{
+        HashPointer, InMemoryHashPointerReference, TypedHashPointer, TypedHashPointerReference,
+    },
     index::INodeIndex,
     inode::INode,
-    manifest::Manifest,
+    interrupt::InterruptHandle,
+    journal::{Journal, JournalEntry, Operation},
+    manifest::{Manifest, ManifestDiscrepancy, ReferenceCounting, StoreReport},
+    merkle_proof::MerkleProof,
     response::{
-        FileOperationError, INodeResponse, ListDirectoryEntry, ListDirectoryResponse,
-        ReadFileResponse,
+        FSError, FileOperationError, INodeResponse, ListDirectoryEntry, ListDirectoryResponse,
+        ReadFileResponse, WalkEntry,
     },
+    snapshot::Snapshot,
 };
 
+/// What `rename_with_mode` does about an existing destination, after the
+/// flags `renameat2(2)` takes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
+pub enum RenameMode {
+    /// Replace the destination, as plain `rename(2)` does
+    #[default]
+    Replace,
+    /// Fail with `AlreadyExists` rather than replace anything
+    NoReplace,
+    /// Swap the source and destination, which must both exist
+    Exchange,
+}
+
+/// Which times `clone_file_with_times` gives the clone besides `ctime`,
+/// which always records the clone itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Encode, Decode)]
+pub enum CloneTimes {
+    /// Keep the source's access, modification and birth times, as archive
+    /// and backup tools expect
+    #[default]
+    Preserve,
+    /// Stamp all of them with the time of the clone, as a fresh copy has
+    Reset,
+}
+
+/// What a write that would take a file past its size limit, or past what
+/// the store has room for, does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SizeLimitPolicy {
+    /// Fail the whole write with `FileTooLarge`, or `NoSpace`
+    #[default]
+    Strict,
+    /// Write what fits and report the short count, so only a write that
+    /// cannot fit a single byte fails
+    ShortWrite,
+}
+
 #[derive(Debug)]
 pub struct BridgeFS<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> {
     index_hash: IndexHashT,
     store: CountingStore<StoreT>,
+    interrupt: InterruptHandle,
+    journal: Option<Box<dyn Journal + Send>>,
+    checkpoint_log: Option<CheckpointLog>,
+    max_file_size: Option<u64>,
+    size_limit_policy: SizeLimitPolicy,
+    block_cache: Option<Mutex<BlockCache>>,
+    fixed_time: Option<SystemTime>,
+    compression: Option<Compression>,
+    /// The last index decoded, so operations while the root stays put do
+    /// not decode every inode again just to look up one of them
+    index_cache: Option<(HashPointer, Arc<INodeIndex>)>,
 }
 
 impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
     BridgeFS<IndexHashT, StoreT>
 {
-    pub fn new(index_hash: IndexHashT, store: StoreT) -> Self {
-        let store = CountingStore::new(store, Manifest::default());
-        BridgeFS { index_hash, store }
+    /// `counting` picks whether reference counts are maintained on every
+    /// mutation or left to an offline garbage collector
+    pub fn new(index_hash: IndexHashT, store: StoreT, counting: ReferenceCounting) -> Self {
+        let store = CountingStore::new(store, Manifest::default(), counting);
+        BridgeFS {
+            index_hash,
+            store,
+            interrupt: InterruptHandle::default(),
+            journal: None,
+            checkpoint_log: None,
+            max_file_size: None,
+            size_limit_policy: SizeLimitPolicy::default(),
+            block_cache: None,
+            fixed_time: None,
+            compression: None,
+            index_cache: None,
+        }
+    }
+
+    /// Records every mutation in `journal` before it is committed, so that
+    /// `recover` can finish it if the process dies halfway
+    pub fn with_journal(mut self, journal: impl Journal + Send + 'static) -> Self {
+        self.journal = Some(Box::new(journal));
+        self
+    }
+
+    /// Appends every committed root to `log`, so that `history` can list
+    /// them and any of them can be read back through a `Snapshot`
+    pub fn with_checkpoint_log(mut self, log: CheckpointLog) -> Self {
+        self.checkpoint_log = Some(log);
+        self
+    }
+
+    /// Stamps every mutation with `time` instead of the current time.
+    ///
+    /// Inodes are handed out in a fixed order and the index encodes sorted,
+    /// so with this the same operations on the same input always produce
+    /// the same root hash.
+    pub fn with_fixed_time(mut self, time: SystemTime) -> Self {
+        self.fixed_time = Some(time);
+        self
+    }
+
+    /// Keeps up to `capacity` decoded data blocks, so a block shared by
+    /// several files is only fetched once
+    pub fn with_block_cache(mut self, capacity: usize) -> Self {
+        self.block_cache = Some(Mutex::new(BlockCache::new(capacity)));
+        self
+    }
+
+    /// Starts from reference counts kept by an earlier run, such as a saved
+    /// copy of `manifest`, instead of empty ones
+    pub fn with_manifest(mut self, manifest: Manifest) -> Self {
+        self.store.set_manifest(manifest);
+        self
+    }
+
+    /// Stores written file content with `compression` when it looks
+    /// compressible, leaving small or already-compressed content as it is.
+    /// Reads decompress whatever each file was stored with, so this can be
+    /// turned on and off freely.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Refuses writes and truncates that would take a file past
+    /// `max_file_size` bytes; `None` lifts the limit
+    pub fn set_max_file_size(&mut self, max_file_size: Option<u64>) {
+        self.max_file_size = max_file_size;
+    }
+
+    /// How a write that would go past `max_file_size`, or past what the
+    /// store has room for, is handled
+    pub fn set_size_limit_policy(&mut self, size_limit_policy: SizeLimitPolicy) {
+        self.size_limit_policy = size_limit_policy;
+    }
+
+    /// The log of committed roots, if one is being kept
+    pub fn checkpoint_log(&self) -> Option<&CheckpointLog> {
+        self.checkpoint_log.as_ref()
+    }
+
+    /// Every root committed to the checkpoint log, oldest first.
+    ///
+    /// Roots stay readable only while the store still holds their content,
+    /// and a log entry that cannot be read back fails the whole walk.
+    pub fn history(&self) -> Result<Vec<(SystemTime, TypedHashPointer<INodeIndex>)>, FSError> {
+        let mut history = Vec::new();
+        let mut next = self
+            .checkpoint_log
+            .as_ref()
+            .and_then(|log| log.head().cloned());
+        while let Some(entry_hash) = next {
+            let entry = self.store.try_get_parsed(&entry_hash)?;
+            history.push((entry.timestamp, entry.root));
+            next = entry.previous;
+        }
+        history.reverse();
+        Ok(history)
+    }
+
+    /// A writable view of the tree at `lower_root` that leaves it untouched.
+    ///
+    /// The lower tree is read from this instance's store, while new content
+    /// goes to `upper_store`, so files are only copied up once written and
+    /// the rest stays shared with the base. Removing a lower entry only drops
+    /// it from the overlay's own index, which acts as the whiteout.
+    pub fn overlay<UpperT: ContentStore>(
+        self,
+        lower_root: TypedHashPointer<INodeIndex>,
+        upper_store: UpperT,
+    ) -> Result<BridgeFS<InMemoryHashPointerReference, OverlayContentStore<StoreT, UpperT>>, FSError>
+    {
+        let counting = self.store.counting();
+        let store = OverlayContentStore::new(self.store.into_store(), upper_store);
+        let index_hash = InMemoryHashPointerReference::new((&lower_root).into());
+        let mut overlay = BridgeFS::new(index_hash, store, counting);
+        if counting == ReferenceCounting::Eager {
+            overlay.rebuild_manifest()?;
+        }
+        Ok(overlay)
+    }
+
+    /// The directory at `path` beneath `index` as a tree of its own, with
+    /// that directory for its root, like a bind mount of a subdirectory.
+    ///
+    /// Inodes are numbered afresh from the new root down so the subtree is
+    /// self-contained. File records are shared with the original as they
+    /// are, while directories are stored again under the new numbers, and
+    /// changes made through the result leave the original tree untouched.
+    pub fn with_root_at(
+        mut self,
+        index: TypedHashPointer<INodeIndex>,
+        path: &Path,
+    ) -> Result<BridgeFS<InMemoryHashPointerReference, StoreT>, FSError> {
+        let source = self.store.try_get_parsed(&index)?;
+        let mut subtree_root = source.root().ok_or(FileOperationError::Corrupt)?;
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => {
+                    let name = Filename::from(name);
+                    let directory = self.lookup_directory_by_inode_in(&source, subtree_root)?;
+                    subtree_root = *directory.inner.get(&name).ok_or(
+                        FileOperationError::NotFound
+                            .with_inode(subtree_root)
+                            .with_name(&name),
+                    )?;
+                }
+                Component::RootDir | Component::CurDir => {}
+                // The subtree cannot reach anything above its own root
+                _ => return Err(FileOperationError::InvalidArgument.into()),
+            }
+        }
+        let root = self.lookup_directory_by_inode_in(&source, subtree_root)?;
+
+        let mut remapped = BTreeMap::from([(subtree_root, INode::ROOT)]);
+        let mut subtree = INodeIndex::new(INode::ROOT, root.source);
+        let mut pending = vec![subtree_root];
+        while let Some(inode) = pending.pop() {
+            let record = self.lookup_record_by_inode_in(&source, inode)?;
+            let record_hash = match record.inner {
+                Record::File(_) => record.source,
+                Record::Directory(mut directory) => {
+                    for child in directory.children.values_mut() {
+                        let original = *child;
+                        // A file linked twice inside keeps a single inode
+                        *child = *remapped.entry(original).or_insert_with(|| {
+                            pending.push(original);
+                            subtree.allocate_inode().0
+                        });
+                    }
+                    directory.parent = if inode == subtree_root {
+                        INode::ROOT
+                    } else {
+                        remapped[&directory.parent]
+                    };
+                    self.store
+                        .store_new_content(&Record::Directory(directory))?
+                }
+            };
+            subtree.update_inode(remapped[&inode], record_hash);
+        }
+        let subtree_hash = self.store.store_new_content(&subtree)?;
+
+        let counting = self.store.counting();
+        let index_hash = InMemoryHashPointerReference::new((&subtree_hash).into());
+        let mut bridgefs = BridgeFS::new(index_hash, self.store.into_store(), counting);
+        if counting == ReferenceCounting::Eager {
+            bridgefs.rebuild_manifest()?;
+        }
+        Ok(bridgefs)
+    }
+
+    /// Reference counts of everything this instance has stored; content
+    /// missing from it is safe to garbage collect
+    pub fn manifest(&self) -> &Manifest {
+        self.store.manifest()
+    }
+
+    pub fn store_report(&self) -> StoreReport {
+        self.store.report()
+    }
+
+    /// Recounts references from everything the current index points at.
+    ///
+    /// Content written before this instance was created is otherwise missing
+    /// from the manifest, which stores that evict based on it rely on.
+    pub fn rebuild_manifest(&mut self) -> Result<(), FSError> {
+        let manifest = self.live_references()?;
+        self.store.set_manifest(manifest);
+        Ok(())
+    }
+
+    /// Where the manifest disagrees with the reference counts walking the
+    /// live tree gives, pinned content included; empty when reference
+    /// counting has kept up. Fetches every record, so it is meant for
+    /// debugging and CI rather than regular use.
+    pub fn audit_manifest(&mut self) -> Result<Vec<ManifestDiscrepancy>, FSError> {
+        let mut actual = self.live_references()?;
+        actual.extend(self.store.pins());
+        Ok(self.store.manifest().discrepancies(&actual))
+    }
+
+    /// The references the current root and the checkpoint log hold, which
+    /// is what the manifest should count apart from pins
+    fn live_references(&mut self) -> Result<Manifest, FSError> {
+        let mut manifest = Manifest::default();
+        for hash in self.reachable_blocks()? {
+            manifest.add_reference(hash);
+        }
+        let mut next = self
+            .checkpoint_log
+            .as_ref()
+            .and_then(|log| log.head().cloned());
+        while let Some(entry_hash) = next {
+            manifest.add_reference((&entry_hash).into());
+            next = self.store.try_get_parsed(&entry_hash)?.previous;
+        }
+        Ok(manifest)
+    }
+
+    /// Every block the current root depends on, the index first and each
+    /// record followed by its content. Shared content appears once per
+    /// record that refers to it.
+    fn reachable_blocks(&mut self) -> Result<Vec<HashPointer>, FSError> {
+        let (index_hash, index) = self.get_index()?;
+        self.reachable_blocks_from(&index_hash, &index)
+    }
+
+    fn reachable_blocks_from(
+        &self,
+        index_hash: &TypedHashPointer<INodeIndex>,
+        index: &INodeIndex,
+    ) -> Result<Vec<HashPointer>, FSError> {
+        let mut blocks = vec![index_hash.into()];
+        for record_hash in index.records() {
+            blocks.push(record_hash.into());
+            let record = self.store.try_get_parsed(record_hash)?;
+            blocks.extend(record.content_hashes());
+        }
+        Ok(blocks)
+    }
+
+    /// The blocks reachable from the current root that a peer holding
+    /// `have` still needs, each listed once. Fetching them with
+    /// `read_block` is enough to pull the whole tree.
+    pub fn missing_blocks(
+        &mut self,
+        have: &BTreeSet<HashPointer>,
+    ) -> Result<Vec<HashPointer>, FSError> {
+        let mut seen = HashSet::new();
+        Ok(self
+            .reachable_blocks()?
+            .into_iter()
+            .filter(|hash| !have.contains(hash) && seen.insert(hash.clone()))
+            .collect())
+    }
+
+    /// The bytes stored under `hash`, exactly as the store holds them
+    pub fn read_block(&self, hash: &HashPointer) -> Vec<u8> {
+        self.store.get_content(hash)
+    }
+
+    /// How many inodes are currently allocated, the root included
+    pub fn inode_count(&mut self) -> Result<usize, FSError> {
+        let (_, index) = self.get_index()?;
+        Ok(index.records().count())
+    }
+
+    /// Fails with `Corrupt` if the root pointer refers to an index the
+    /// store cannot produce, before any other operation runs into it
+    pub fn check_root(&mut self) -> Result<(), FSError> {
+        let index_hash = self.index_hash.try_get_typed()?;
+        self.store.try_get_parsed(&index_hash)?;
+        Ok(())
+    }
+
+    /// The durability boundary for the whole tree rather than one file.
+    ///
+    /// Flushes a root pointer that holds writes back, then confirms the
+    /// published root can be read back and returns it.
+    pub fn commit(&mut self) -> Result<TypedHashPointer<INodeIndex>, FSError> {
+        self.index_hash.flush();
+        self.check_root()?;
+        Ok(self.index_hash.get_typed())
+    }
+
+    /// Points the root at a fresh index holding only an empty directory at
+    /// `root_inode`, abandoning whatever the old root referred to
+    pub fn reinitialize_root(&mut self, root_inode: INode) -> Result<(), FSError> {
+        let root_directory = DirectoryRecord {
+            parent: root_inode,
+            ..DirectoryRecord::default()
+        };
+        let root_hash = self
+            .store
+            .store_new_content(&Record::Directory(root_directory))?;
+        let index_hash = self
+            .store
+            .store_new_content(&INodeIndex::new(root_inode, root_hash))?;
+        self.index_hash.set_typed(&index_hash);
+        Ok(())
+    }
+
+    /// A handle that can cancel whichever read or write is in progress
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+}
+
+impl<StoreT: ContentStore> BridgeFS<InMemoryHashPointerReference, StoreT> {
+    /// Bootstraps a tree in `store` with nothing but an empty root
+    /// directory, for any frontend to point its root reference at.
+    ///
+    /// Hands the store back along with the new index, since the tree is
+    /// read back from it.
+    pub fn init_empty(
+        mut store: StoreT,
+    ) -> Result<(TypedHashPointer<INodeIndex>, StoreT), ContentStoreError> {
+        let index_hash = INodeIndex::add_empty(&mut store)?;
+        Ok((index_hash, store))
+    }
+}
+
+impl BridgeFS<InMemoryHashPointerReference, InMemoryContentStore> {
+    /// An in-memory tree holding each file at its path with its bytes, for
+    /// tests and demos. Directories along the way are created as needed,
+    /// while `.`, `..` and any leading `/` are not allowed in a path.
+    pub fn from_entries(
+        entries: impl IntoIterator<Item = (PathBuf, Vec<u8>)>,
+    ) -> Result<Self, FSError> {
+        let (index_hash, store) = Self::init_empty(InMemoryContentStore::default())?;
+        let index_hash = InMemoryHashPointerReference::new(index_hash.into());
+        let mut bridgefs = BridgeFS::new(index_hash, store, ReferenceCounting::Eager);
+        for (path, data) in entries {
+            let mut names = Vec::new();
+            for component in path.components() {
+                let Component::Normal(name) = component else {
+                    return Err(FileOperationError::InvalidArgument.into());
+                };
+                names.push(Filename::from(name));
+            }
+            let Some(name) = names.pop() else {
+                return Err(FileOperationError::InvalidArgument.into());
+            };
+            let mut parent = INode::ROOT;
+            for directory in names {
+                parent = match bridgefs.lookup_record_by_name(parent, &directory) {
+                    Ok(existing) => existing.inode,
+                    Err(e) if e == FileOperationError::NotFound => {
+                        let attributes = CommonAttrs::directory();
+                        bridgefs
+                            .create_directory(parent, directory, attributes)?
+                            .inode
+                    }
+                    Err(e) => return Err(e),
+                };
+            }
+            let file = bridgefs.create_file(parent, name, CommonAttrs::default())?;
+            bridgefs.write_to_file(file.inode, 0, &data)?;
+        }
+        Ok(bridgefs)
     }
 }
 
 impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
     BridgeFS<IndexHashT, StoreT>
 {
-    fn get_index(&mut self) -> (TypedHashPointer<INodeIndex>, INodeIndex) {
-        let index_hash = self.index_hash.get_typed();
-        let inode_index = self.store.get_parsed(&index_hash);
-        (index_hash, inode_index)
+    /// The index hash the root pointer holds right now, read without
+    /// decoding anything. Every mutation changes it, so a record read
+    /// while it holds a given value is current until it moves.
+    pub fn current_root(&mut self) -> HashPointer {
+        self.index_hash.get_typed().into()
+    }
+
+    fn get_index(&mut self) -> Result<(TypedHashPointer<INodeIndex>, Arc<INodeIndex>), FSError> {
+        let index_hash = self.index_hash.try_get_typed()?;
+        // Indexes are content addressed, so a hit can never be stale
+        if let Some((cached_hash, index)) = &self.index_cache
+            && *cached_hash == HashPointer::from(&index_hash)
+        {
+            return Ok((index_hash, index.clone()));
+        }
+        let inode_index = Arc::new(self.store.try_get_parsed(&index_hash)?);
+        self.index_cache = Some(((&index_hash).into(), inode_index.clone()));
+        Ok((index_hash, inode_index))
+    }
+
+    /// Runs `operation` against the current index and commits the result.
+    ///
+    /// The root pointer is only swapped if nobody else moved it since it was
+    /// read; otherwise the reference counts are rolled back and the operation
+    /// is retried against the newer index. How reliably a move is noticed is
+    /// up to `HashPointerReference::set_if`, which is best-effort on
+    /// backends without a conditional write.
+    fn mutate<T>(
+        &mut self,
+        describe: impl FnOnce() -> Operation,
+        mut operation: impl FnMut(&mut Self, &mut INodeIndex) -> Result<T, FSError>,
+    ) -> Result<T, FSError> {
+        if !self.store.is_writable() {
+            return Err(FileOperationError::ReadOnly.into());
+        }
+        let intent = self.journal.is_some().then(describe);
+        loop {
+            let (prev_index_hash, index) = self.get_index()?;
+            let mut index = INodeIndex::clone(&index);
+            self.store.checkpoint();
+            let result = match operation(self, &mut index) {
+                Ok(result) => result,
+                Err(e) => {
+                    self.store.rollback();
+                    return Err(e);
+                }
+            };
+
+            let new_index_hash = match self.store.replace_content(&prev_index_hash, &index) {
+                Ok(new_index_hash) => new_index_hash,
+                Err(e) => {
+                    self.store.rollback();
+                    return Err(e.into());
+                }
+            };
+            let log_entry_hash = match self.append_checkpoint_log(&new_index_hash) {
+                Ok(log_entry_hash) => log_entry_hash,
+                Err(e) => {
+                    self.store.rollback();
+                    return Err(e);
+                }
+            };
+            if let (Some(journal), Some(operation)) = (&mut self.journal, &intent) {
+                journal.append(&JournalEntry {
+                    operation: operation.clone(),
+                    target: (&new_index_hash).into(),
+                });
+            }
+            let committed = self
+                .index_hash
+                .set_typed_if(&prev_index_hash, &new_index_hash);
+            if let Some(journal) = &mut self.journal {
+                journal.clear();
+            }
+            if committed {
+                if let (Some(log), Some(log_entry_hash)) =
+                    (&mut self.checkpoint_log, log_entry_hash)
+                {
+                    log.set_head(log_entry_hash);
+                }
+                self.store.commit();
+                self.index_cache = Some((new_index_hash.into(), Arc::new(index)));
+                return Ok(result);
+            }
+            self.store.rollback();
+        }
+    }
+
+    /// Stores the log entry for `root`; it only becomes the head once `root`
+    /// is committed
+    fn append_checkpoint_log(
+        &mut self,
+        root: &TypedHashPointer<INodeIndex>,
+    ) -> Result<Option<TypedHashPointer<CheckpointLogEntry>>, FSError> {
+        let Some(log) = &self.checkpoint_log else {
+            return Ok(None);
+        };
+        let entry = CheckpointLogEntry {
+            timestamp: self.now(),
+            root: root.clone(),
+            previous: log.head().cloned(),
+        };
+        Ok(Some(self.store.store_new_content(&entry)?))
+    }
+
+    fn check_file_size(&self, inode: INode, size: u64) -> Result<(), FSError> {
+        match self.max_file_size {
+            Some(max_file_size) if size > max_file_size => {
+                Err(FileOperationError::FileTooLarge.with_inode(inode))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn now(&self) -> SystemTime {
+        self.fixed_time.unwrap_or_else(SystemTime::now)
+    }
+
+    /// The uncompressed content of `file`
+    fn get_datablock(&self, file: &FileRecord) -> Result<DataBlock, FSError> {
+        let Some(cache) = &self.block_cache else {
+            return self.fetch_datablock(&file.content_hash);
+        };
+        let hash_pointer: HashPointer = (&file.content_hash).into();
+        if let Some(block) = cache.lock().unwrap().get(&hash_pointer) {
+            return Ok(block.clone());
+        }
+        let block = self.fetch_datablock(&file.content_hash)?;
+        cache.lock().unwrap().insert(hash_pointer, block.clone());
+        Ok(block)
+    }
+
+    /// The block stored under `hash`, decompressed if it was stored
+    /// compressed, bypassing the block cache
+    fn fetch_datablock(&self, hash: &TypedHashPointer<DataBlock>) -> Result<DataBlock, FSError> {
+        let bytes = self.store.try_get_content(&hash.into())?;
+        DataBlock::from_stored(&bytes).ok_or_else(|| FileOperationError::Corrupt.into())
     }
 
-    fn get_record_by_inode(&mut self, inode: INode) -> Option<(TypedHashPointer<Record>, Record)> {
-        let (_, index) = self.get_index();
-        let record_hash = index.lookup_inode(&inode)?;
-        let record = self.store.get_parsed(record_hash);
-        Some((record_hash.clone(), record))
+    /// Stores `datablock` as new file content, compressed if this filesystem
+    /// compresses, the content looks like it will shrink, and the store can
+    /// keep the compressed form under the hash of the uncompressed block.
+    /// Either way the hash is the same, so copies still deduplicate.
+    fn store_datablock(
+        &mut self,
+        datablock: &DataBlock,
+    ) -> Result<(TypedHashPointer<DataBlock>, Option<Compression>), FSError> {
+        if let Some(compression) = self
+            .compression
+            .filter(|_| compression::worth_compressing(&datablock.data))
+        {
+            let compressed = CompressedBlock::new(compression, &datablock.data);
+            let encoded = bincode::encode_to_vec(&compressed, bincode::config::standard())
+                .expect("A compressed block should encode");
+            if let Some(content_hash) = self.store.store_encoded_content(datablock, &encoded)? {
+                return Ok((content_hash, Some(compression)));
+            }
+        }
+        Ok((self.store.store_new_content(datablock)?, None))
+    }
+
+    fn check_interrupted(&self) -> Result<(), FSError> {
+        if self.interrupt.is_interrupted() {
+            self.interrupt.reset();
+            return Err(FileOperationError::Interrupted.into());
+        }
+        Ok(())
     }
 
-    fn add_child(
+    fn get_record_by_inode_in(
+        &self,
+        index: &INodeIndex,
+        inode: INode,
+    ) -> Result<Option<(TypedHashPointer<Record>, Record)>, FSError> {
+        let Some(record_hash) = index.lookup_inode(&inode) else {
+            return Ok(None);
+        };
+        let record = self
+            .store
+            .try_get_parsed(record_hash)
+            .map_err(|e| FSError::from(e).with_inode(inode))?;
+        Ok(Some((record_hash.clone(), record)))
+    }
+
+    /// Links `record` into `parent_inode` under an inode from `allocate_inode`
+    fn add_child_in(
         &mut self,
+        index: &mut INodeIndex,
         parent_inode: INode,
         filename: Filename,
+        inode: INode,
         record: Record,
-    ) -> Result<(TypedHashPointer<Record>, INode), FileOperationError> {
-        let mut parent = self.lookup_directory_by_inode(parent_inode)?;
+    ) -> Result<TypedHashPointer<Record>, FSError> {
+        let mut parent = self.lookup_directory_by_inode_in(index, parent_inode)?;
         if parent.inner.children.contains_key(&filename) {
-            return Err(FileOperationError::AlreadyExists);
+            return Err(FileOperationError::AlreadyExists
+                .with_inode(parent_inode)
+                .with_name(&filename));
         }
 
-        let (prev_index_hash, mut index) = self.get_index();
-        let record_hash = self.store.store_new_content(&record);
-        let inode = index.insert_new_inode(record_hash.clone());
-        self.index_hash
-            .set_typed(&self.store.replace_content(&prev_index_hash, &index));
+        let record_hash = self.store.store_new_content(&record)?;
+        index.update_inode(inode, record_hash.clone());
 
         parent.inner.insert(filename, inode);
-        self.update_index(parent.inode, parent.inner.into());
-        Ok((record_hash, inode))
+        self.update_record_in(index, parent.inode, parent.inner.into())?;
+        Ok(record_hash)
+    }
+
+    /// Releases a record that has been unlinked so its inode can be reused
+    fn remove_inode_in<T: Clone + Into<Record>>(
+        &mut self,
+        index: &mut INodeIndex,
+        removed: &INodeResponse<T, Record>,
+    ) -> Result<(), FSError> {
+        let record: Record = removed.inner.clone().into();
+        self.store.delete_content(&removed.source)?;
+        index.remove_inode(removed.inode, record.common_attrs().generation);
+        Ok(())
+    }
+
+    /// Drops one name of `file`, releasing it only once no names are left
+    fn unlink_file_in(
+        &mut self,
+        index: &mut INodeIndex,
+        mut file: INodeResponse<FileRecord, Record>,
+    ) -> Result<(), FSError> {
+        if file.inner.nlink > 1 {
+            file.inner.nlink -= 1;
+            return self.update_record_in(index, file.inode, file.inner.into());
+        }
+        self.remove_inode_in(index, &file)
     }
 
-    fn update_index(&mut self, inode: INode, record: Record) {
-        let (prev_index_hash, mut index) = self.get_index();
+    fn update_record_in(
+        &mut self,
+        index: &mut INodeIndex,
+        inode: INode,
+        record: Record,
+    ) -> Result<(), FSError> {
         let prev_inode_hash = index
             .lookup_inode(&inode)
             .expect("INode should exist prior to update");
-        let new_inode_hash = self.store.replace_content(prev_inode_hash, &record);
-
+        let new_inode_hash = self.store.replace_content(prev_inode_hash, &record)?;
         index.update_inode(inode, new_inode_hash);
-        let new_index_hash = self.store.replace_content(&prev_index_hash, &index);
-        self.index_hash.set_typed(&new_index_hash);
+        Ok(())
     }
 
     pub fn lookup_record_by_inode(
         &mut self,
         inode: INode,
-    ) -> Result<INodeResponse<Record, Record>, FileOperationError> {
-        let result = self.get_record_by_inode(inode);
-        if result.is_none() {
-            return Err(FileOperationError::NotFound);
-        }
-        let (source, inner) = result.unwrap();
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        let (_, index) = self.get_index()?;
+        self.lookup_record_by_inode_in(&index, inode)
+    }
+
+    /// Whether `a` and `b` hold identical records, decided by their hashes
+    /// in the index without fetching either one
+    pub fn records_equal(&mut self, a: INode, b: INode) -> Result<bool, FSError> {
+        let (_, index) = self.get_index()?;
+        let record_hash = |inode| {
+            index
+                .lookup_inode(&inode)
+                .ok_or(FileOperationError::NotFound.with_inode(inode))
+        };
+        Ok(record_hash(a)? == record_hash(b)?)
+    }
+
+    fn lookup_record_by_inode_in(
+        &self,
+        index: &INodeIndex,
+        inode: INode,
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        let Some((source, inner)) = self.get_record_by_inode_in(index, inode)? else {
+            return Err(FileOperationError::NotFound.with_inode(inode));
+        };
         Ok(INodeResponse::new(inner, inode, source))
     }
 
     pub fn lookup_file_by_inode(
         &mut self,
         inode: INode,
-    ) -> Result<INodeResponse<FileRecord, Record>, FileOperationError> {
-        let record = self.lookup_record_by_inode(inode)?;
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        let (_, index) = self.get_index()?;
+        self.lookup_file_by_inode_in(&index, inode)
+    }
+
+    fn lookup_file_by_inode_in(
+        &self,
+        index: &INodeIndex,
+        inode: INode,
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        let record = self.lookup_record_by_inode_in(index, inode)?;
         match record.inner.clone() {
             Record::File(file) => Ok(record.swap_inner(file)),
-            _ => Err(FileOperationError::IsADirectory),
+            _ => Err(FileOperationError::IsADirectory.with_inode(inode)),
         }
     }
 
-    fn lookup_directory_by_inode(
+    /// The directory `..` in `inode` leads to
+    pub fn lookup_parent_by_inode(
         &mut self,
         inode: INode,
-    ) -> Result<INodeResponse<DirectoryRecord, Record>, FileOperationError> {
-        let record = self.lookup_record_by_inode(inode)?;
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        let (_, index) = self.get_index()?;
+        let directory = self.lookup_directory_by_inode_in(&index, inode)?;
+        self.lookup_parent_in(&index, &directory.inner)
+    }
+
+    /// Falls back to the root when the recorded parent no longer exists, so
+    /// a directory orphaned by a bug or a racing edit can still be left
+    fn lookup_parent_in(
+        &self,
+        index: &INodeIndex,
+        directory: &DirectoryRecord,
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        match self.lookup_record_by_inode_in(index, directory.parent) {
+            Err(e) if e == FileOperationError::NotFound => {
+                let root = index.root().ok_or(FileOperationError::Corrupt)?;
+                self.lookup_record_by_inode_in(index, root)
+            }
+            result => result,
+        }
+    }
+
+    fn lookup_directory_by_inode_in(
+        &self,
+        index: &INodeIndex,
+        inode: INode,
+    ) -> Result<INodeResponse<DirectoryRecord, Record>, FSError> {
+        let record = self.lookup_record_by_inode_in(index, inode)?;
         match record.inner.clone() {
             Record::Directory(directory) => Ok(record.swap_inner(directory)),
-            _ => Err(FileOperationError::NotADirectory),
+            _ => Err(FileOperationError::NotADirectory.with_inode(inode)),
         }
     }
 
@@ -119,35 +822,53 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
         &mut self,
         parent: INode,
         name: &Filename,
-    ) -> Result<INodeResponse<Record, Record>, FileOperationError> {
-        let parent = self.lookup_directory_by_inode(parent)?;
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        let (_, index) = self.get_index()?;
+        self.lookup_record_by_name_in(&index, parent, name)
+    }
+
+    fn lookup_record_by_name_in(
+        &self,
+        index: &INodeIndex,
+        parent: INode,
+        name: &Filename,
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        let parent = self.lookup_directory_by_inode_in(index, parent)?;
         match parent.inner.get(name) {
-            Some(&inode) => self.lookup_record_by_inode(inode),
-            None => Err(FileOperationError::NotFound),
+            Some(&inode) => self.lookup_record_by_inode_in(index, inode),
+            None => Err(FileOperationError::NotFound
+                .with_inode(parent.inode)
+                .with_name(name)),
         }
     }
 
-    fn lookup_directory_by_name(
-        &mut self,
+    fn lookup_directory_by_name_in(
+        &self,
+        index: &INodeIndex,
         parent: INode,
         name: &Filename,
-    ) -> Result<INodeResponse<DirectoryRecord, Record>, FileOperationError> {
-        let record = self.lookup_record_by_name(parent, name)?;
+    ) -> Result<INodeResponse<DirectoryRecord, Record>, FSError> {
+        let record = self.lookup_record_by_name_in(index, parent, name)?;
         match record.inner.clone() {
             Record::Directory(directory) => Ok(record.swap_inner(directory)),
-            _ => Err(FileOperationError::NotADirectory),
+            _ => Err(FileOperationError::NotADirectory
+                .with_inode(parent)
+                .with_name(name)),
         }
     }
 
-    fn lookup_file_by_name(
-        &mut self,
+    fn lookup_file_by_name_in(
+        &self,
+        index: &INodeIndex,
         parent: INode,
         name: &Filename,
-    ) -> Result<INodeResponse<FileRecord, Record>, FileOperationError> {
-        let record = self.lookup_record_by_name(parent, name)?;
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        let record = self.lookup_record_by_name_in(index, parent, name)?;
         match record.inner.clone() {
             Record::File(file) => Ok(record.swap_inner(file)),
-            _ => Err(FileOperationError::IsADirectory),
+            _ => Err(FileOperationError::IsADirectory
+                .with_inode(parent)
+                .with_name(name)),
         }
     }
 
@@ -156,13 +877,217 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
         inode: INode,
         offset: usize,
         size: usize,
-    ) -> Result<ReadFileResponse, FileOperationError> {
-        let file = self.lookup_file_by_inode(inode)?;
-        let data = self.store.get_parsed(&file.inner.content_hash);
+    ) -> Result<ReadFileResponse, FSError> {
+        self.interrupt.reset();
+        let (_, index) = self.get_index()?;
+        self.check_interrupted()?;
+        let response = self.read_file_data_by_inode_in(&index, inode, offset, size)?;
+        self.check_interrupted()?;
+        Ok(response)
+    }
+
+    /// A reader over the whole of the file `inode`, for copying it out
+    /// with `io::copy` rather than tracking offsets by hand
+    pub fn open_reader(
+        &mut self,
+        inode: INode,
+    ) -> Result<FileReader<'_, IndexHashT, StoreT>, FSError> {
+        self.lookup_file_by_inode(inode)?;
+        Ok(FileReader::new(self, inode))
+    }
+
+    /// A buffered writer over the file `inode` from its start, for copying
+    /// content in with `io::copy`
+    pub fn open_writer(
+        &mut self,
+        inode: INode,
+    ) -> Result<FileWriter<'_, IndexHashT, StoreT>, FSError> {
+        self.lookup_file_by_inode(inode)?;
+        Ok(FileWriter::new(self, inode))
+    }
+
+    /// The encoded record behind `inode` exactly as stored, for inspecting
+    /// it or checking it against its hash
+    pub fn read_raw_record(&mut self, inode: INode) -> Result<Vec<u8>, FSError> {
+        let (_, index) = self.get_index()?;
+        let record_hash = index
+            .lookup_inode(&inode)
+            .ok_or(FileOperationError::NotFound.with_inode(inode))?;
+        Ok(self.store.get_content(&record_hash.into()))
+    }
+
+    /// An absolute path to `inode`, built by walking parents up to the root,
+    /// which is its own parent. Finding a file's directory means scanning
+    /// every directory, and a hard-linked file resolves to any one of its
+    /// names.
+    pub fn path_of(&mut self, inode: INode) -> Result<PathBuf, FSError> {
+        let (_, index) = self.get_index()?;
+        let mut parent = match self.lookup_record_by_inode_in(&index, inode)?.inner {
+            Record::Directory(directory) => directory.parent,
+            Record::File(_) => self.find_parent_in(&index, inode)?,
+        };
+        let mut components = Vec::new();
+        let mut child = inode;
+        while parent != child {
+            let directory = self.lookup_directory_by_inode_in(&index, parent)?;
+            let (name, _) = directory
+                .inner
+                .children
+                .iter()
+                .find(|(_, inode)| **inode == child)
+                .ok_or(FileOperationError::NotFound.with_inode(child))?;
+            components.push(OsStr::from_bytes(&name.name).to_owned());
+            child = parent;
+            parent = directory.inner.parent;
+        }
+        let mut path = PathBuf::from("/");
+        path.extend(components.iter().rev());
+        Ok(path)
+    }
+
+    fn find_parent_in(&self, index: &INodeIndex, inode: INode) -> Result<INode, FSError> {
+        index
+            .inodes()
+            .copied()
+            .find(|&candidate| {
+                matches!(
+                    self.get_record_by_inode_in(index, candidate),
+                    Ok(Some((_, Record::Directory(directory))))
+                        if directory.children.values().any(|child| *child == inode)
+                )
+            })
+            .ok_or(FileOperationError::NotFound.with_inode(inode))
+    }
+
+    /// Collects the records from `root` down through `path`, so anyone
+    /// holding the root index hash can check the target belongs to it
+    pub fn prove_path(&mut self, root: INode, path: &[Filename]) -> Result<MerkleProof, FSError> {
+        let (index_hash, index) = self.get_index()?;
+        let mut records = Vec::new();
+        let mut inode = root;
+        for name in path {
+            let directory = self.lookup_directory_by_inode_in(&index, inode)?;
+            records.push(self.store.get_content(&(&directory.source).into()));
+            inode = *directory.inner.get(name).ok_or(
+                FileOperationError::NotFound
+                    .with_inode(inode)
+                    .with_name(name),
+            )?;
+        }
+        let target = self.lookup_record_by_inode_in(&index, inode)?;
+        records.push(self.store.get_content(&(&target.source).into()));
+        Ok(MerkleProof {
+            root_inode: root,
+            path: path.to_vec(),
+            index: self.store.get_content(&(&index_hash).into()),
+            records,
+        })
+    }
+
+    /// Pins the current root index so that later reads see a consistent tree
+    pub fn snapshot(&mut self) -> Snapshot {
+        Snapshot::new(self.index_hash.get_typed())
+    }
+
+    /// Keeps `snapshot` readable through `gc` even after the live tree has
+    /// moved on, by pinning its index and everything reachable from it
+    pub fn pin_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), FSError> {
+        for hash in self.snapshot_blocks(snapshot)? {
+            self.store.pin(&hash);
+        }
+        Ok(())
+    }
+
+    /// Releases the pins `pin_snapshot` took on `snapshot`
+    pub fn unpin_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), FSError> {
+        for hash in self.snapshot_blocks(snapshot)? {
+            self.store.unpin(&hash);
+        }
+        Ok(())
+    }
+
+    fn snapshot_blocks(&self, snapshot: &Snapshot) -> Result<Vec<HashPointer>, FSError> {
+        let index = self.store.try_get_parsed(snapshot.index_hash())?;
+        self.reachable_blocks_from(snapshot.index_hash(), &index)
+    }
+
+    /// Content pinned to survive `gc`, with how many pins each holds
+    pub fn pins(&self) -> &Manifest {
+        self.store.pins()
+    }
+
+    /// Evicts content nothing refers to from stores that evict, keeping
+    /// pinned content. Content written before this instance was created is
+    /// only protected once `rebuild_manifest` has counted it. A no-op under
+    /// `ReferenceCounting::Deferred`, which keeps no counts to go by.
+    pub fn gc(&mut self) {
+        self.store.gc();
+    }
+
+    pub fn lookup_record_in_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        inode: INode,
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        let index = self.store.try_get_parsed(snapshot.index_hash())?;
+        self.lookup_record_by_inode_in(&index, inode)
+    }
+
+    pub fn read_file_data_in_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        inode: INode,
+        offset: usize,
+        size: usize,
+    ) -> Result<ReadFileResponse, FSError> {
+        self.interrupt.reset();
+        let index = self.store.try_get_parsed(snapshot.index_hash())?;
+        self.check_interrupted()?;
+        self.read_file_data_by_inode_in(&index, inode, offset, size)
+    }
+
+    /// Reads a whole file, handing back its content without slicing or copying
+    pub fn read_entire_file(&mut self, inode: INode) -> Result<Vec<u8>, FSError> {
+        self.interrupt.reset();
+        let (_, index) = self.get_index()?;
+        self.check_interrupted()?;
+        let response = self.read_entire_file_in(&index, inode)?;
+        self.check_interrupted()?;
+        Ok(response.datablock.data)
+    }
+
+    fn read_entire_file_in(
+        &self,
+        index: &INodeIndex,
+        inode: INode,
+    ) -> Result<ReadFileResponse, FSError> {
+        let file = self.lookup_file_by_inode_in(index, inode)?;
+        self.check_interrupted()?;
+        let datablock = self.get_datablock(&file.inner)?;
+        Ok(ReadFileResponse { file, datablock })
+    }
+
+    fn read_file_data_by_inode_in(
+        &self,
+        index: &INodeIndex,
+        inode: INode,
+        offset: usize,
+        size: usize,
+    ) -> Result<ReadFileResponse, FSError> {
+        let file = self.lookup_file_by_inode_in(index, inode)?;
+        // Nothing to return, so don't fetch the content at all
+        if size == 0 || offset as u64 >= file.inner.size {
+            return Ok(ReadFileResponse {
+                file,
+                datablock: DataBlock::default(),
+            });
+        }
+        self.check_interrupted()?;
+        let data = self.get_datablock(&file.inner)?;
 
         let data_len = data.len();
         let start = offset;
-        let end = std::cmp::min(start + size, data_len);
+        let end = std::cmp::min(start.saturating_add(size), data_len);
         if start >= data_len {
             return Ok(ReadFileResponse {
                 file,
@@ -182,16 +1107,127 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
         parent: INode,
         name: Filename,
         attributes: CommonAttrs,
-    ) -> Result<INodeResponse<FileRecord, Record>, FileOperationError> {
-        let empty_data = DataBlock::default();
-        let content_hash = self.store.store_new_content(&empty_data);
-        let file_record = FileRecord::builder()
-            .content_hash(content_hash)
-            .common_attrs(attributes)
-            .size(empty_data.len() as u64)
-            .build();
-        let (source, inode) = self.add_child(parent, name, Record::File(file_record.clone()))?;
-        Ok(INodeResponse::new(file_record, inode, source))
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        self.mutate(
+            || Operation::CreateFile {
+                parent,
+                name: name.clone(),
+                attributes: attributes.clone(),
+            },
+            |bridgefs, index| {
+                let mut attributes = attributes.clone();
+                if let Some(defaults) = bridgefs.default_attrs_in(index, parent)? {
+                    defaults.apply(&mut attributes, false);
+                }
+                let empty_data = DataBlock::default();
+                let content_hash = bridgefs.store.store_new_content(&empty_data)?;
+                let (inode, generation) = index.allocate_inode();
+                let file_record = FileRecord::builder()
+                    .content_hash(content_hash)
+                    .common_attrs(CommonAttrs {
+                        generation,
+                        ..attributes
+                    })
+                    .size(empty_data.len() as u64)
+                    .build();
+                let source = bridgefs.add_child_in(
+                    index,
+                    parent,
+                    name.clone(),
+                    inode,
+                    file_record.clone().into(),
+                )?;
+                Ok(INodeResponse::new(file_record, inode, source))
+            },
+        )
+    }
+
+    /// Creates a file with no name in any directory, as `O_TMPFILE` does in
+    /// `parent`. It has no links until `link` gives it a name, and
+    /// `remove_if_unlinked` releases it if that never happens.
+    pub fn create_unnamed(
+        &mut self,
+        parent: INode,
+        attributes: CommonAttrs,
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        self.mutate(
+            || Operation::CreateUnnamed {
+                parent,
+                attributes: attributes.clone(),
+            },
+            |bridgefs, index| {
+                let mut attributes = attributes.clone();
+                if let Some(defaults) = bridgefs.default_attrs_in(index, parent)? {
+                    defaults.apply(&mut attributes, false);
+                }
+                let empty_data = DataBlock::default();
+                let content_hash = bridgefs.store.store_new_content(&empty_data)?;
+                let (inode, generation) = index.allocate_inode();
+                let file_record = FileRecord::builder()
+                    .content_hash(content_hash)
+                    .common_attrs(CommonAttrs {
+                        generation,
+                        ..attributes
+                    })
+                    .size(empty_data.len() as u64)
+                    .nlink(0)
+                    .build();
+                let source = bridgefs
+                    .store
+                    .store_new_content(&Record::from(file_record.clone()))?;
+                index.update_inode(inode, source.clone());
+                Ok(INodeResponse::new(file_record, inode, source))
+            },
+        )
+    }
+
+    /// Releases the file `inode` if no directory names it, as is left of a
+    /// file from `create_unnamed` that was never linked. Anything else,
+    /// including an inode already gone, is left alone.
+    pub fn remove_if_unlinked(&mut self, inode: INode) -> Result<(), FSError> {
+        match self.lookup_file_by_inode(inode) {
+            Ok(file) if file.inner.nlink == 0 => {}
+            Ok(_) => return Ok(()),
+            Err(e) if e == FileOperationError::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        self.mutate(
+            || Operation::RemoveUnlinked { inode },
+            |bridgefs, index| {
+                let file = bridgefs.lookup_file_by_inode_in(index, inode)?;
+                // Linked since it was checked
+                if file.inner.nlink == 0 {
+                    bridgefs.remove_inode_in(index, &file)?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Opens `name` in `parent` if it is already a file, and creates it
+    /// with `attributes` otherwise, as `open` with `O_CREAT` but not
+    /// `O_EXCL` does. The flag is whether the file was created.
+    pub fn create_or_open(
+        &mut self,
+        parent: INode,
+        name: Filename,
+        attributes: CommonAttrs,
+    ) -> Result<(INodeResponse<FileRecord, Record>, bool), FSError> {
+        let open = |bridgefs: &mut Self| {
+            let existing = bridgefs.lookup_record_by_name(parent, &name)?;
+            bridgefs.lookup_file_by_inode(existing.inode)
+        };
+        match open(self) {
+            Ok(file) => return Ok((file, false)),
+            Err(e) if e != FileOperationError::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        match self.create_file(parent, name.clone(), attributes) {
+            Ok(file) => Ok((file, true)),
+            // Someone else created it first, which is as good as it existing
+            Err(e) if e == FileOperationError::AlreadyExists => Ok((open(self)?, false)),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn create_directory(
@@ -199,14 +1235,38 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
         parent: INode,
         name: Filename,
         attributes: CommonAttrs,
-    ) -> Result<INodeResponse<DirectoryRecord, Record>, FileOperationError> {
-        let directory_record = DirectoryRecord::builder()
-            .common_attrs(attributes)
-            .parent(parent)
-            .build();
-        let (source, inode) =
-            self.add_child(parent, name, Record::Directory(directory_record.clone()))?;
-        Ok(INodeResponse::new(directory_record, inode, source))
+    ) -> Result<INodeResponse<DirectoryRecord, Record>, FSError> {
+        self.mutate(
+            || Operation::CreateDirectory {
+                parent,
+                name: name.clone(),
+                attributes: attributes.clone(),
+            },
+            |bridgefs, index| {
+                let mut attributes = attributes.clone();
+                let default_attrs = bridgefs.default_attrs_in(index, parent)?;
+                if let Some(defaults) = &default_attrs {
+                    defaults.apply(&mut attributes, true);
+                }
+                let (inode, generation) = index.allocate_inode();
+                let directory_record = DirectoryRecord::builder()
+                    .common_attrs(CommonAttrs {
+                        generation,
+                        ..attributes
+                    })
+                    .parent(parent)
+                    .maybe_default_attrs(default_attrs)
+                    .build();
+                let source = bridgefs.add_child_in(
+                    index,
+                    parent,
+                    name.clone(),
+                    inode,
+                    directory_record.clone().into(),
+                )?;
+                Ok(INodeResponse::new(directory_record, inode, source))
+            },
+        )
     }
 
     pub fn write_to_file(
@@ -214,40 +1274,188 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
         inode: INode,
         offset: usize,
         data: &[u8],
-    ) -> Result<usize, FileOperationError> {
+    ) -> Result<usize, FSError> {
         // TODO: support sparse files and writing without needing to read existing data
-        let original_file = self.lookup_file_by_inode(inode)?;
-        let mut existing_data = self.read_file_data_by_inode(inode, 0, usize::MAX)?;
-        if offset > existing_data.datablock.len() {
-            existing_data.datablock.data.resize(offset, 0);
+        if data.is_empty() {
+            self.lookup_file_by_inode(inode)?;
+            return Ok(0);
         }
-        if offset + data.len() > existing_data.datablock.len() {
-            existing_data.datablock.data.resize(offset + data.len(), 0);
+        let data = match (self.max_file_size, self.size_limit_policy) {
+            (Some(max_file_size), SizeLimitPolicy::ShortWrite)
+                if (offset as u64) < max_file_size =>
+            {
+                &data[..data.len().min(max_file_size as usize - offset)]
+            }
+            _ => data,
+        };
+        // An end past `usize::MAX` is larger than any file can be
+        let end = offset
+            .checked_add(data.len())
+            .ok_or(FileOperationError::FileTooLarge.with_inode(inode))?;
+        self.check_file_size(inode, end as u64)?;
+        self.interrupt.reset();
+        self.mutate(
+            || Operation::Write {
+                inode,
+                offset: offset as u64,
+                data: data.to_vec(),
+            },
+            |bridgefs, index| {
+                bridgefs.check_interrupted()?;
+                let mut existing_data = bridgefs.read_entire_file_in(index, inode)?;
+                check_mutable(&existing_data.file.inner.common_attrs, inode)?;
+                bridgefs.check_interrupted()?;
+                let end = offset + data.len();
+                let unchanged = existing_data.datablock.data.get(offset..end) == Some(data);
+                let mut written = data.len();
+                if !unchanged {
+                    let block = &mut existing_data.datablock.data;
+                    let original_len = block.len();
+                    // Kept so a short write can put back what its dropped
+                    // tail overwrote
+                    let overwritten = block
+                        .get(offset..end.min(original_len))
+                        .unwrap_or_default()
+                        .to_vec();
+                    if end > original_len {
+                        block.resize(end, 0);
+                    }
+                    block[offset..end].copy_from_slice(data);
+                    existing_data.file.inner.common_attrs.clear_setid();
+
+                    // The old content is released when the record is replaced below
+                    (
+                        existing_data.file.inner.content_hash,
+                        existing_data.file.inner.compression,
+                    ) = loop {
+                        match bridgefs.store_datablock(&existing_data.datablock) {
+                            Err(e)
+                                if e == FileOperationError::NoSpace
+                                    && bridgefs.size_limit_policy
+                                        == SizeLimitPolicy::ShortWrite
+                                    && written > 1 =>
+                            {
+                                // Halve the write until what is left fits
+                                written /= 2;
+                                let block = &mut existing_data.datablock.data;
+                                block.truncate(original_len.max(offset + written));
+                                if let Some(restored) = overwritten.get(written..) {
+                                    block[offset + written..offset + overwritten.len()]
+                                        .copy_from_slice(restored);
+                                }
+                            }
+                            result => break result?,
+                        }
+                    };
+                }
+                // Writes only ever grow the buffer; shrinking is left to truncate
+                existing_data.file.inner.size = existing_data.datablock.len() as u64;
+                existing_data.file.inner.common_attrs.mtime = bridgefs.now();
+                existing_data.file.inner.common_attrs.ctime = bridgefs.now();
+
+                let new_record = Record::File(existing_data.file.inner);
+                bridgefs.update_record_in(index, inode, new_record)?;
+                Ok(written)
+            },
+        )
+    }
+
+    /// `write_to_file` for writes that must not be lost silently: commits the
+    /// root, then reads the bytes back from the store, bypassing every cache,
+    /// and fails with `Corrupt` unless they match
+    pub fn write_verified(
+        &mut self,
+        inode: INode,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<usize, FSError> {
+        let written = self.write_to_file(inode, offset, data)?;
+        let root = self.commit()?;
+        let index = self.store.try_get_parsed(&root)?;
+        let file = self.lookup_file_by_inode_in(&index, inode)?;
+        let stored = self
+            .fetch_datablock(&file.inner.content_hash)
+            .map_err(|e| e.with_inode(inode))?
+            .data;
+        if stored.get(offset..offset + written) != Some(&data[..written]) {
+            return Err(FileOperationError::Corrupt.with_inode(inode));
         }
-        existing_data.datablock.data[offset..offset + data.len()].copy_from_slice(data);
+        Ok(written)
+    }
 
-        existing_data.file.inner.content_hash = self
-            .store
-            .replace_content(&original_file.inner.content_hash, &existing_data.datablock);
-        existing_data.file.inner.size = existing_data.datablock.len() as u64;
-        existing_data.file.inner.common_attrs.mtime = SystemTime::now();
-        existing_data.file.inner.common_attrs.ctime = SystemTime::now();
+    /// Bytes the store holds for the content beneath `root`, counting blobs
+    /// shared within the subtree once
+    pub fn subtree_physical_size(&mut self, root: INode) -> Result<u64, FSError> {
+        let (_, index) = self.get_index()?;
+        let mut content_hashes = HashSet::new();
+        let mut pending = vec![root];
+        while let Some(inode) = pending.pop() {
+            let record = self.lookup_record_by_inode_in(&index, inode)?;
+            if let Record::Directory(directory) = &record.inner {
+                pending.extend(directory.children.values());
+            }
+            content_hashes.extend(record.inner.content_hashes());
+        }
+        Ok(content_hashes
+            .iter()
+            .map(|hash| self.store.stored_size(hash) as u64)
+            .sum())
+    }
+
+    /// Cuts or zero-extends `inode` to `size` bytes. Truncating to zero
+    /// never fetches the old content.
+    pub fn truncate(
+        &mut self,
+        inode: INode,
+        size: u64,
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        self.check_file_size(inode, size)?;
+        self.interrupt.reset();
+        self.mutate(
+            || Operation::Truncate { inode, size },
+            |bridgefs, index| {
+                let mut file = bridgefs.lookup_file_by_inode_in(index, inode)?;
+                check_mutable(&file.inner.common_attrs, inode)?;
+                if file.inner.size == size {
+                    return Ok(file);
+                }
+                let datablock = if size == 0 {
+                    DataBlock::default()
+                } else {
+                    bridgefs.check_interrupted()?;
+                    let mut existing_data = bridgefs.read_entire_file_in(index, inode)?;
+                    bridgefs.check_interrupted()?;
+                    existing_data.datablock.data.resize(size as usize, 0);
+                    existing_data.datablock
+                };
+                // The old content is released when the record is replaced below
+                (file.inner.content_hash, file.inner.compression) =
+                    bridgefs.store_datablock(&datablock)?;
+                file.inner.size = size;
+                file.inner.common_attrs.mtime = bridgefs.now();
+                file.inner.common_attrs.ctime = bridgefs.now();
 
-        let new_record = Record::File(existing_data.file.inner);
-        self.update_index(inode, new_record);
-        Ok(data.len())
+                bridgefs.update_record_in(index, inode, file.inner.clone().into())?;
+                file.source = index
+                    .lookup_inode(&inode)
+                    .expect("INode should exist after update")
+                    .clone();
+                Ok(file)
+            },
+        )
     }
 
     pub fn list_directory_by_inode(
         &mut self,
         inode: INode,
-    ) -> Result<ListDirectoryResponse, FileOperationError> {
-        let directory = self.lookup_directory_by_inode(inode)?;
+    ) -> Result<ListDirectoryResponse, FSError> {
+        let (_, index) = self.get_index()?;
+        let directory = self.lookup_directory_by_inode_in(&index, inode)?;
 
         let mut entries = Vec::new();
 
         for entry in directory.inner.list_children() {
-            let record = self.lookup_record_by_inode(entry.inode)?;
+            let record = self.lookup_record_by_inode_in(&index, entry.inode)?;
             entries.push(ListDirectoryEntry {
                 name: entry.name,
                 record,
@@ -259,7 +1467,7 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
             record: directory.clone().convert_inner(),
         });
 
-        let parent = self.lookup_record_by_inode(directory.inner.parent)?;
+        let parent = self.lookup_parent_in(&index, &directory.inner)?;
         entries.push(ListDirectoryEntry {
             name: "..".into(),
             record: parent,
@@ -268,44 +1476,692 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
         Ok(ListDirectoryResponse { directory, entries })
     }
 
+    /// Every entry from the root down, depth first with each directory's
+    /// children in name order, paired with its path from `/`. A file with
+    /// several links appears once under each name.
+    pub fn walk(&mut self) -> Result<Vec<WalkEntry>, FSError> {
+        let (_, index) = self.get_index()?;
+        let mut entries = Vec::new();
+        let mut pending = vec![(PathBuf::from("/"), INode::ROOT)];
+        while let Some((path, inode)) = pending.pop() {
+            let record = self.lookup_record_by_inode_in(&index, inode)?;
+            if let Record::Directory(directory) = &record.inner {
+                // Pushed in reverse so the stack yields them in name order
+                for child in directory.list_children().into_iter().rev() {
+                    let name = OsString::from(child.name);
+                    pending.push((path.join(name), child.inode));
+                }
+            }
+            entries.push(WalkEntry { path, record });
+        }
+        Ok(entries)
+    }
+
+    /// How many entries `inode` holds, not counting `.` and `..`, read from
+    /// the directory record alone
+    pub fn directory_entry_count(&mut self, inode: INode) -> Result<usize, FSError> {
+        let (_, index) = self.get_index()?;
+        Ok(self
+            .lookup_directory_by_inode_in(&index, inode)?
+            .inner
+            .size())
+    }
+
+    /// The names in `inode`, sorted and without `.` and `..`. Unlike
+    /// `list_directory_by_inode` this never fetches the children's records.
+    pub fn directory_entry_names(&mut self, inode: INode) -> Result<Vec<Filename>, FSError> {
+        let (_, index) = self.get_index()?;
+        let directory = self.lookup_directory_by_inode_in(&index, inode)?;
+        Ok(directory.inner.children.into_keys().collect())
+    }
+
     pub fn remove_directory_by_name(
         &mut self,
         parent: INode,
         name: &Filename,
-    ) -> Result<(), FileOperationError> {
-        let target = self.lookup_directory_by_name(parent, name)?;
-        if !target.inner.children.is_empty() {
-            return Err(FileOperationError::DirectoryNotEmpty);
-        }
+    ) -> Result<(), FSError> {
+        self.remove_directory(parent, name, None)
+    }
 
-        let mut parent = self.lookup_directory_by_inode(parent)?;
-        parent.inner.remove(name);
-        self.update_index(parent.inode, parent.inner.into());
+    /// `remove_directory_by_name` on behalf of `uid`, refused with
+    /// `NotPermitted` when the sticky bit on `parent` protects the
+    /// directory from them
+    pub fn remove_directory_by_name_as(
+        &mut self,
+        parent: INode,
+        name: &Filename,
+        uid: u32,
+    ) -> Result<(), FSError> {
+        self.remove_directory(parent, name, Some(uid))
+    }
+
+    fn remove_directory(
+        &mut self,
+        parent: INode,
+        name: &Filename,
+        uid: Option<u32>,
+    ) -> Result<(), FSError> {
+        self.mutate(
+            || Operation::RemoveDirectory {
+                parent,
+                name: name.clone(),
+            },
+            |bridgefs, index| {
+                if let Some(uid) = uid {
+                    bridgefs.check_removal_in(index, parent, name, uid)?;
+                }
+                let target = bridgefs.lookup_directory_by_name_in(index, parent, name)?;
+                check_mutable(&target.inner.common_attrs, target.inode)?;
+                if !target.inner.children.is_empty() {
+                    return Err(FileOperationError::DirectoryNotEmpty
+                        .with_inode(parent)
+                        .with_name(name));
+                }
+                bridgefs.remove_inode_in(index, &target)?;
+
+                let mut parent = bridgefs.lookup_directory_by_inode_in(index, parent)?;
+                parent.inner.remove(name);
+                bridgefs.update_record_in(index, parent.inode, parent.inner.into())?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Removes the directory `name` along with everything beneath it in a
+    /// single commit
+    pub fn remove_tree(&mut self, parent: INode, name: &Filename) -> Result<(), FSError> {
+        self.mutate(
+            || Operation::RemoveTree {
+                parent,
+                name: name.clone(),
+            },
+            |bridgefs, index| {
+                let target = bridgefs.lookup_directory_by_name_in(index, parent, name)?;
+                check_mutable(&target.inner.common_attrs, target.inode)?;
+                bridgefs.remove_descendants_in(index, &target)?;
+                bridgefs.remove_inode_in(index, &target)?;
+
+                let mut parent = bridgefs.lookup_directory_by_inode_in(index, parent)?;
+                parent.inner.remove(name);
+                bridgefs.update_record_in(index, parent.inode, parent.inner.into())?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Unlinks everything beneath `directory`, deepest entries first
+    fn remove_descendants_in(
+        &mut self,
+        index: &mut INodeIndex,
+        directory: &INodeResponse<DirectoryRecord, Record>,
+    ) -> Result<(), FSError> {
+        for &child in directory.inner.children.values() {
+            // Looked up afresh, as a hardlink seen earlier may have changed it
+            let record = self.lookup_record_by_inode_in(index, child)?;
+            check_mutable(record.inner.common_attrs(), record.inode)?;
+            match record.inner.clone() {
+                Record::File(file) => self.unlink_file_in(index, record.swap_inner(file))?,
+                Record::Directory(subdirectory) => {
+                    let subdirectory = record.swap_inner(subdirectory);
+                    self.remove_descendants_in(index, &subdirectory)?;
+                    self.remove_inode_in(index, &subdirectory)?;
+                }
+            }
+        }
         Ok(())
     }
 
-    pub fn remove_file_by_name(
+    /// Fails with `NotPermitted` if the sticky bit on `parent` keeps `uid`
+    /// from unlinking or renaming `name`; a missing entry is left for the
+    /// operation itself to report
+    pub fn check_removal_by(
         &mut self,
         parent: INode,
         name: &Filename,
-    ) -> Result<(), FileOperationError> {
-        let deleted_file = self.lookup_file_by_name(parent, name)?;
-        self.store.delete_content(&deleted_file.source);
+        uid: u32,
+    ) -> Result<(), FSError> {
+        let (_, index) = self.get_index()?;
+        self.check_removal_in(&index, parent, name, uid)
+    }
 
-        let mut parent = self.lookup_directory_by_inode(parent)?;
-        parent.inner.remove(name);
-        self.update_index(parent.inode, parent.inner.into());
+    fn check_removal_in(
+        &self,
+        index: &INodeIndex,
+        parent: INode,
+        name: &Filename,
+        uid: u32,
+    ) -> Result<(), FSError> {
+        let directory = self.lookup_directory_by_inode_in(index, parent)?;
+        let Some(&inode) = directory.inner.get(name) else {
+            return Ok(());
+        };
+        let entry = self.lookup_record_by_inode_in(index, inode)?;
+        if directory
+            .inner
+            .common_attrs
+            .allows_removal_by(entry.inner.common_attrs(), uid)
+        {
+            Ok(())
+        } else {
+            Err(FileOperationError::NotPermitted
+                .with_inode(parent)
+                .with_name(name))
+        }
+    }
+
+    pub fn remove_file_by_name(&mut self, parent: INode, name: &Filename) -> Result<(), FSError> {
+        self.remove_file(parent, name, None)
+    }
+
+    /// `remove_file_by_name` on behalf of `uid`, refused with `NotPermitted`
+    /// when the sticky bit on `parent` protects the file from them
+    pub fn remove_file_by_name_as(
+        &mut self,
+        parent: INode,
+        name: &Filename,
+        uid: u32,
+    ) -> Result<(), FSError> {
+        self.remove_file(parent, name, Some(uid))
+    }
+
+    fn remove_file(
+        &mut self,
+        parent: INode,
+        name: &Filename,
+        uid: Option<u32>,
+    ) -> Result<(), FSError> {
+        self.mutate(
+            || Operation::RemoveFile {
+                parent,
+                name: name.clone(),
+            },
+            |bridgefs, index| {
+                if let Some(uid) = uid {
+                    bridgefs.check_removal_in(index, parent, name, uid)?;
+                }
+                let deleted_file = bridgefs.lookup_file_by_name_in(index, parent, name)?;
+                check_mutable(&deleted_file.inner.common_attrs, deleted_file.inode)?;
+                bridgefs.unlink_file_in(index, deleted_file)?;
+
+                let mut parent = bridgefs.lookup_directory_by_inode_in(index, parent)?;
+                parent.inner.remove(name);
+                bridgefs.update_record_in(index, parent.inode, parent.inner.into())?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Adds `new_name` under `new_parent` as another name for the file
+    /// `inode`
+    pub fn link(
+        &mut self,
+        inode: INode,
+        new_parent: INode,
+        new_name: &Filename,
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        self.mutate(
+            || Operation::Link {
+                inode,
+                new_parent,
+                new_name: new_name.clone(),
+            },
+            |bridgefs, index| {
+                let mut file = match bridgefs.lookup_file_by_inode_in(index, inode) {
+                    Err(e) if e == FileOperationError::IsADirectory => {
+                        return Err(FileOperationError::NotPermitted.with_inode(inode));
+                    }
+                    result => result?,
+                };
+                let mut parent = bridgefs.lookup_directory_by_inode_in(index, new_parent)?;
+                if parent.inner.children.contains_key(new_name) {
+                    return Err(FileOperationError::AlreadyExists
+                        .with_inode(new_parent)
+                        .with_name(new_name));
+                }
+                parent.inner.insert(new_name.clone(), inode);
+                bridgefs.update_record_in(index, parent.inode, parent.inner.into())?;
+
+                file.inner.nlink += 1;
+                bridgefs.update_record_in(index, inode, file.inner.clone().into())?;
+                file.source = index
+                    .lookup_inode(&inode)
+                    .expect("INode should exist after update")
+                    .clone();
+                Ok(file)
+            },
+        )
+    }
+
+    /// Copies `name` to `new_name` in the same directory without copying
+    /// its content: the clone shares the source's content hash but has its
+    /// own inode and attributes, so writing to either one leaves the other
+    /// alone. The source's timestamps are kept.
+    pub fn clone_file(
+        &mut self,
+        parent: INode,
+        name: &Filename,
+        new_name: Filename,
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        self.clone_file_with_times(parent, name, new_name, CloneTimes::Preserve)
+    }
+
+    /// `clone_file`, with `times` deciding whether the clone keeps the
+    /// source's timestamps
+    pub fn clone_file_with_times(
+        &mut self,
+        parent: INode,
+        name: &Filename,
+        new_name: Filename,
+        times: CloneTimes,
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        self.mutate(
+            || Operation::CloneFile {
+                parent,
+                name: name.clone(),
+                new_name: new_name.clone(),
+                times,
+            },
+            |bridgefs, index| {
+                let source = bridgefs.lookup_file_by_name_in(index, parent, name)?;
+                bridgefs
+                    .store
+                    .add_reference(&(&source.inner.content_hash).into());
+                let (inode, generation) = index.allocate_inode();
+                let now = bridgefs.now();
+                let mut common_attrs = CommonAttrs {
+                    generation,
+                    ctime: now,
+                    ..source.inner.common_attrs.clone()
+                };
+                if times == CloneTimes::Reset {
+                    common_attrs.atime = now;
+                    common_attrs.mtime = now;
+                    common_attrs.crtime = now;
+                }
+                let file_record = FileRecord {
+                    nlink: 1,
+                    common_attrs,
+                    ..source.inner
+                };
+                let source = bridgefs.add_child_in(
+                    index,
+                    parent,
+                    new_name.clone(),
+                    inode,
+                    file_record.clone().into(),
+                )?;
+                Ok(INodeResponse::new(file_record, inode, source))
+            },
+        )
+    }
+
+    /// Moves `name` under `new_parent` as `new_name`.
+    ///
+    /// An existing file at the destination is replaced and its content
+    /// released; an existing directory is only replaced if it is empty.
+    pub fn rename(
+        &mut self,
+        parent: INode,
+        name: &Filename,
+        new_parent: INode,
+        new_name: &Filename,
+    ) -> Result<(), FSError> {
+        self.rename_with_mode(parent, name, new_parent, new_name, RenameMode::Replace)
+    }
+
+    /// `rename`, with `mode` deciding what happens to an existing
+    /// destination. Renaming an entry onto itself, or onto another hard
+    /// link to the same file, succeeds without changing anything, whatever
+    /// the mode.
+    pub fn rename_with_mode(
+        &mut self,
+        parent: INode,
+        name: &Filename,
+        new_parent: INode,
+        new_name: &Filename,
+        mode: RenameMode,
+    ) -> Result<(), FSError> {
+        // Caught before `mutate` so nothing is journaled or logged for it
+        let source = self.lookup_record_by_name(parent, name)?;
+        if (parent == new_parent && name == new_name)
+            || self
+                .lookup_record_by_name(new_parent, new_name)
+                .is_ok_and(|existing| existing.inode == source.inode)
+        {
+            return Ok(());
+        }
+        self.mutate(
+            || Operation::Rename {
+                parent,
+                name: name.clone(),
+                new_parent,
+                new_name: new_name.clone(),
+                mode,
+            },
+            |bridgefs, index| {
+                let source = bridgefs.lookup_record_by_name_in(index, parent, name)?;
+                check_mutable(source.inner.common_attrs(), source.inode)?;
+                let destination = bridgefs.lookup_directory_by_inode_in(index, new_parent)?;
+                if let Record::Directory(_) = source.inner
+                    && bridgefs.is_ancestor_in(index, source.inode, new_parent)
+                {
+                    return Err(FileOperationError::InvalidArgument
+                        .with_inode(new_parent)
+                        .with_name(new_name));
+                }
+
+                if let Some(&existing_inode) = destination.inner.get(new_name) {
+                    // Checked again in case a link appeared since
+                    if existing_inode == source.inode {
+                        return Ok(());
+                    }
+                    if mode == RenameMode::NoReplace {
+                        return Err(FileOperationError::AlreadyExists
+                            .with_inode(new_parent)
+                            .with_name(new_name));
+                    }
+                    let existing = bridgefs.lookup_record_by_inode_in(index, existing_inode)?;
+                    check_mutable(existing.inner.common_attrs(), existing.inode)?;
+                    if mode == RenameMode::Exchange {
+                        if let Record::Directory(_) = existing.inner
+                            && bridgefs.is_ancestor_in(index, existing.inode, parent)
+                        {
+                            return Err(FileOperationError::InvalidArgument
+                                .with_inode(parent)
+                                .with_name(name));
+                        }
+                        bridgefs.link_entry_in(
+                            index,
+                            parent,
+                            name,
+                            existing.inode,
+                            existing.inner,
+                        )?;
+                        bridgefs.link_entry_in(
+                            index,
+                            new_parent,
+                            new_name,
+                            source.inode,
+                            source.inner,
+                        )?;
+                        return Ok(());
+                    }
+                    match (&source.inner, &existing.inner) {
+                        (Record::File(_), Record::Directory(_)) => {
+                            return Err(FileOperationError::IsADirectory
+                                .with_inode(new_parent)
+                                .with_name(new_name));
+                        }
+                        (Record::Directory(_), Record::File(_)) => {
+                            return Err(FileOperationError::NotADirectory
+                                .with_inode(new_parent)
+                                .with_name(new_name));
+                        }
+                        (_, Record::Directory(directory)) if !directory.children.is_empty() => {
+                            return Err(FileOperationError::DirectoryNotEmpty
+                                .with_inode(new_parent)
+                                .with_name(new_name));
+                        }
+                        (_, Record::File(file)) => {
+                            let file = existing.clone().swap_inner(file.clone());
+                            bridgefs.unlink_file_in(index, file)?;
+                        }
+                        _ => bridgefs.remove_inode_in(index, &existing)?,
+                    }
+                } else if mode == RenameMode::Exchange {
+                    return Err(FileOperationError::NotFound
+                        .with_inode(new_parent)
+                        .with_name(new_name));
+                }
+
+                let mut old_parent = bridgefs.lookup_directory_by_inode_in(index, parent)?;
+                old_parent.inner.remove(name);
+                bridgefs.update_record_in(index, old_parent.inode, old_parent.inner.into())?;
+
+                // Re-read in case the source and destination are the same directory
+                let mut new_parent = bridgefs.lookup_directory_by_inode_in(index, new_parent)?;
+                new_parent.inner.insert(new_name.clone(), source.inode);
+                bridgefs.update_record_in(index, new_parent.inode, new_parent.inner.into())?;
+
+                if let Record::Directory(mut directory) = source.inner.clone() {
+                    directory.parent = new_parent.inode;
+                    bridgefs.update_record_in(index, source.inode, directory.into())?;
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Points `name` in `parent` at `inode`, replacing whatever it named,
+    /// and moves `record` under `parent` if it is a directory
+    fn link_entry_in(
+        &mut self,
+        index: &mut INodeIndex,
+        parent: INode,
+        name: &Filename,
+        inode: INode,
+        record: Record,
+    ) -> Result<(), FSError> {
+        let mut directory = self.lookup_directory_by_inode_in(index, parent)?;
+        directory.inner.insert(name.clone(), inode);
+        self.update_record_in(index, parent, directory.inner.into())?;
+        if let Record::Directory(mut directory) = record {
+            directory.parent = parent;
+            self.update_record_in(index, inode, directory.into())?;
+        }
         Ok(())
     }
 
+    /// Whether `inode` is `ancestor` or sits somewhere beneath it
+    fn is_ancestor_in(&self, index: &INodeIndex, ancestor: INode, inode: INode) -> bool {
+        let mut current = inode;
+        loop {
+            if current == ancestor {
+                return true;
+            }
+            let Ok(directory) = self.lookup_directory_by_inode_in(index, current) else {
+                return false;
+            };
+            // The root has no real parent, so stop once the link is not mirrored
+            let parent = directory.inner.parent;
+            match self.lookup_directory_by_inode_in(index, parent) {
+                Ok(parent_dir) if parent_dir.inner.children.values().any(|&c| c == current) => {
+                    current = parent;
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// Sets what entries created inside the directory `inode` inherit, or
+    /// with `None` goes back to entries keeping their creator's attributes
+    pub fn set_default_attrs(
+        &mut self,
+        inode: INode,
+        default_attrs: Option<DefaultAttrs>,
+    ) -> Result<INodeResponse<DirectoryRecord, Record>, FSError> {
+        self.mutate(
+            || Operation::SetDefaultAttrs {
+                inode,
+                default_attrs: default_attrs.clone(),
+            },
+            |bridgefs, index| {
+                let mut directory = bridgefs.lookup_directory_by_inode_in(index, inode)?;
+                directory.inner.default_attrs = default_attrs.clone();
+                bridgefs.update_record_in(index, inode, directory.inner.clone().into())?;
+                Ok(directory)
+            },
+        )
+    }
+
+    fn default_attrs_in(
+        &self,
+        index: &INodeIndex,
+        parent: INode,
+    ) -> Result<Option<DefaultAttrs>, FSError> {
+        Ok(self
+            .lookup_directory_by_inode_in(index, parent)?
+            .inner
+            .default_attrs)
+    }
+
+    /// Replaces the attributes of `inode`, except for its birth time, which
+    /// is kept so that attributes built from scratch cannot clobber it
     pub fn update_attributes_by_inode(
         &mut self,
         inode: INode,
         attributes: CommonAttrs,
-    ) -> Result<INodeResponse<Record, Record>, FileOperationError> {
-        let mut record = self.lookup_record_by_inode(inode)?;
-        record.inner.set_attrs(attributes);
-        self.update_index(inode, record.inner.clone());
-        Ok(record)
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        self.set_attributes_by_inode(inode, attributes, false)
+    }
+
+    /// `update_attributes_by_inode`, for callers that mean to change the
+    /// birth time as well
+    pub fn replace_attributes_by_inode(
+        &mut self,
+        inode: INode,
+        attributes: CommonAttrs,
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        self.set_attributes_by_inode(inode, attributes, true)
+    }
+
+    fn set_attributes_by_inode(
+        &mut self,
+        inode: INode,
+        attributes: CommonAttrs,
+        replace_crtime: bool,
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        self.mutate(
+            || Operation::UpdateAttributes {
+                inode,
+                attributes: attributes.clone(),
+                replace_crtime,
+            },
+            |bridgefs, index| {
+                let mut record = bridgefs.lookup_record_by_inode_in(index, inode)?;
+                let existing = record.inner.common_attrs();
+                // The generation belongs to the inode, not to the caller
+                let generation = existing.generation;
+                let crtime = if replace_crtime {
+                    attributes.crtime
+                } else {
+                    existing.crtime
+                };
+                let updated = CommonAttrs {
+                    generation,
+                    crtime,
+                    ..attributes.clone()
+                };
+                // The flags alone may still change, or an immutable file
+                // could never be made mutable again
+                let flags_only = CommonAttrs {
+                    flags: existing.flags,
+                    ..updated.clone()
+                };
+                if existing.is_immutable() && flags_only != *existing {
+                    return Err(FileOperationError::NotPermitted.with_inode(inode));
+                }
+                record.inner.set_attrs(updated);
+                bridgefs.update_record_in(index, inode, record.inner.clone())?;
+                Ok(record)
+            },
+        )
+    }
+
+    /// Replays operations that were journaled but never committed, returning
+    /// how many had to be applied again
+    pub fn recover(&mut self) -> Result<usize, FSError> {
+        let Some(mut journal) = self.journal.take() else {
+            return Ok(0);
+        };
+        let mut replayed = 0;
+        let mut result = Ok(());
+        for entry in journal.pending() {
+            // The process may have died after committing but before clearing
+            if self.index_hash.get() == entry.target {
+                continue;
+            }
+            result = self.apply(entry.operation);
+            if result.is_err() {
+                break;
+            }
+            replayed += 1;
+        }
+        if result.is_ok() {
+            journal.clear();
+        }
+        self.journal = Some(journal);
+        result.map(|()| replayed)
+    }
+
+    fn apply(&mut self, operation: Operation) -> Result<(), FSError> {
+        match operation {
+            Operation::CreateFile {
+                parent,
+                name,
+                attributes,
+            } => self.create_file(parent, name, attributes).map(|_| ()),
+            Operation::CreateUnnamed { parent, attributes } => {
+                self.create_unnamed(parent, attributes).map(|_| ())
+            }
+            Operation::RemoveUnlinked { inode } => self.remove_if_unlinked(inode),
+            Operation::CreateDirectory {
+                parent,
+                name,
+                attributes,
+            } => self.create_directory(parent, name, attributes).map(|_| ()),
+            Operation::Write {
+                inode,
+                offset,
+                data,
+            } => self
+                .write_to_file(inode, offset as usize, &data)
+                .map(|_| ()),
+            Operation::Truncate { inode, size } => self.truncate(inode, size).map(|_| ()),
+            Operation::RemoveFile { parent, name } => self.remove_file_by_name(parent, &name),
+            Operation::RemoveDirectory { parent, name } => {
+                self.remove_directory_by_name(parent, &name)
+            }
+            Operation::RemoveTree { parent, name } => self.remove_tree(parent, &name),
+            Operation::Link {
+                inode,
+                new_parent,
+                new_name,
+            } => self.link(inode, new_parent, &new_name).map(|_| ()),
+            Operation::Rename {
+                parent,
+                name,
+                new_parent,
+                new_name,
+                mode,
+            } => self.rename_with_mode(parent, &name, new_parent, &new_name, mode),
+            Operation::CloneFile {
+                parent,
+                name,
+                new_name,
+                times,
+            } => self
+                .clone_file_with_times(parent, &name, new_name, times)
+                .map(|_| ()),
+            Operation::UpdateAttributes {
+                inode,
+                attributes,
+                replace_crtime,
+            } => self
+                .set_attributes_by_inode(inode, attributes, replace_crtime)
+                .map(|_| ()),
+            Operation::SetDefaultAttrs {
+                inode,
+                default_attrs,
+            } => self.set_default_attrs(inode, default_attrs).map(|_| ()),
+        }
+    }
+}
+
+/// Refuses changes to a file whose flags make it immutable
+fn check_mutable(attributes: &CommonAttrs, inode: INode) -> Result<(), FSError> {
+    if attributes.is_immutable() {
+        return Err(FileOperationError::NotPermitted.with_inode(inode));
     }
+    Ok(())
 }
@@ -0,0 +1,34 @@
+use std::time::SystemTime;
+
+use bincode::{Decode, Encode};
+
+use crate::{hash_pointer::TypedHashPointer, index::INodeIndex};
+
+/// A committed root index, linked to the entry committed before it
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct CheckpointLogEntry {
+    pub timestamp: SystemTime,
+    pub root: TypedHashPointer<INodeIndex>,
+    pub previous: Option<TypedHashPointer<CheckpointLogEntry>>,
+}
+
+/// The newest entry of a log of committed roots kept in the content store
+#[derive(Debug, Clone, Default)]
+pub struct CheckpointLog {
+    head: Option<TypedHashPointer<CheckpointLogEntry>>,
+}
+
+impl CheckpointLog {
+    /// Continues a log whose newest entry is `head`
+    pub fn new(head: Option<TypedHashPointer<CheckpointLogEntry>>) -> Self {
+        Self { head }
+    }
+
+    pub fn head(&self) -> Option<&TypedHashPointer<CheckpointLogEntry>> {
+        self.head.as_ref()
+    }
+
+    pub fn set_head(&mut self, head: TypedHashPointer<CheckpointLogEntry>) {
+        self.head = Some(head);
+    }
+}
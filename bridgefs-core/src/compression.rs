@@ -0,0 +1,78 @@
+use bincode::{Decode, Encode};
+
+/// Files smaller than this are stored as they are; the savings would not
+/// cover the cost of compressing
+const MIN_COMPRESSED_SIZE: usize = 4096;
+/// How much of the start of a file is looked at to judge whether it
+/// compresses
+const SAMPLE_SIZE: usize = 4096;
+/// Shannon entropy, in bits per byte, above which a sample is taken to be
+/// already compressed or random
+const MAX_SAMPLE_ENTROPY: f64 = 6.0;
+
+/// How a file's data block is encoded in the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Decode, Encode)]
+pub enum Compression {
+    Lz4,
+}
+
+impl Compression {
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Lz4 => lz4_flex::compress_prepend_size(data),
+        }
+    }
+
+    /// `None` if `data` is not something `compress` produced
+    pub fn decompress(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(data).ok(),
+        }
+    }
+}
+
+/// A data block as stored compressed. The codec is kept with the bytes, so
+/// a block reads back the same whichever way it ended up stored.
+#[derive(Debug, Clone, PartialEq, Eq, Decode, Encode)]
+pub struct CompressedBlock {
+    pub compression: Compression,
+    pub data: Vec<u8>,
+}
+
+impl CompressedBlock {
+    pub fn new(compression: Compression, data: &[u8]) -> Self {
+        CompressedBlock {
+            compression,
+            data: compression.compress(data),
+        }
+    }
+
+    /// `None` if the bytes are not what `compression` produced
+    pub fn decompress(&self) -> Option<Vec<u8>> {
+        self.compression.decompress(&self.data)
+    }
+}
+
+/// Whether `data` is large enough, and its first few KiB repetitive enough,
+/// that compressing it is likely to pay off. Only looks at the bytes, so
+/// the same content always gets the same answer and still deduplicates.
+pub fn worth_compressing(data: &[u8]) -> bool {
+    data.len() >= MIN_COMPRESSED_SIZE
+        && sample_entropy(&data[..SAMPLE_SIZE.min(data.len())]) <= MAX_SAMPLE_ENTROPY
+}
+
+fn sample_entropy(sample: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in sample {
+        counts[*byte as usize] += 1;
+    }
+    let total = sample.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
@@ -1,42 +1,400 @@
-use bincode::Decode;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+};
 
-use crate::hash_pointer::{HashPointer, TypedHashPointer};
+use bincode::{Decode, error::DecodeError};
+
+use crate::{
+    hash_pointer::{Blake3, HashPointer, Hasher, TypedHashPointer},
+    manifest::Manifest,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentStoreError {
+    /// The store has no room left for the content
+    CapacityExceeded,
+    /// The store cannot accept new content at all
+    ReadOnly,
+    /// The backend behind the store failed the call; a `transient` failure,
+    /// such as a timeout, may succeed if the call is repeated
+    Unavailable { transient: bool },
+    /// Content the store did produce could not be decoded
+    Corrupt,
+}
+
+impl ContentStoreError {
+    /// Whether repeating the call could succeed, as opposed to a logical
+    /// error that will fail the same way every time
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ContentStoreError::Unavailable { transient: true })
+    }
+}
+
+impl Display for ContentStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContentStoreError::CapacityExceeded => f.write_str("content store capacity exceeded"),
+            ContentStoreError::ReadOnly => f.write_str("content store is read-only"),
+            ContentStoreError::Unavailable { transient: true } => {
+                f.write_str("content store backend is temporarily unavailable")
+            }
+            ContentStoreError::Unavailable { transient: false } => {
+                f.write_str("content store backend failed")
+            }
+            ContentStoreError::Corrupt => f.write_str("stored content is corrupt"),
+        }
+    }
+}
+
+impl Error for ContentStoreError {}
+
+/// Stored content that could not be read back as the type it was read as,
+/// because the store could not produce it, or it is truncated or something
+/// else entirely
+#[derive(Debug)]
+pub struct ParseError {
+    hash: HashPointer,
+    cause: ParseFailure,
+}
+
+#[derive(Debug)]
+enum ParseFailure {
+    Fetch(ContentStoreError),
+    Decode(DecodeError),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.cause {
+            ParseFailure::Fetch(e) => write!(f, "content {} could not be fetched: {e}", self.hash),
+            ParseFailure::Decode(e) => write!(f, "content {} does not decode: {e}", self.hash),
+        }
+    }
+}
+
+impl From<ParseError> for ContentStoreError {
+    fn from(error: ParseError) -> Self {
+        match error.cause {
+            ParseFailure::Fetch(e) => e,
+            ParseFailure::Decode(_) => ContentStoreError::Corrupt,
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.cause {
+            ParseFailure::Fetch(e) => Some(e),
+            ParseFailure::Decode(e) => Some(e),
+        }
+    }
+}
 
 pub trait ContentStore {
-    fn add_content(&mut self, content: &[u8]) -> HashPointer;
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError>;
 
     fn get_content(&self, hash: &HashPointer) -> Vec<u8>;
+
+    /// Like `get_content`, but fails instead of panicking when the backend
+    /// cannot produce the content. Stores that cannot fail need not
+    /// override it.
+    fn try_get_content(&self, hash: &HashPointer) -> Result<Vec<u8>, ContentStoreError> {
+        Ok(self.get_content(hash))
+    }
+
+    /// The hash `add_content` would store `content` under, for stores that
+    /// can work it out without storing anything
+    fn content_hash(&self, _content: &[u8]) -> Option<HashPointer> {
+        None
+    }
+
+    /// Stores `content` under `hash` rather than its own hash, so that an
+    /// encoding of some value, such as its compressed form, is found under
+    /// the hash of the value itself. Returns `false`, storing nothing, from
+    /// stores that can only key content by its own hash.
+    fn add_content_under(
+        &mut self,
+        _hash: &HashPointer,
+        _content: &[u8],
+    ) -> Result<bool, ContentStoreError> {
+        Ok(false)
+    }
+
+    /// Drops content that `manifest` no longer refers to. Stores that never
+    /// evict can leave this as a no-op.
+    fn evict_unreferenced(&mut self, _manifest: &Manifest) {}
+
+    /// Whether `add_content` can succeed at all; mutations are refused up
+    /// front when it cannot
+    fn is_writable(&self) -> bool {
+        true
+    }
 }
 
-#[derive(Default, Debug)]
+/// An in-memory store, unbounded unless given a budget.
+///
+/// A budgeted store is a `BoundedInMemoryContentStore`; an unbudgeted one
+/// is the same store with room for anything and never evicts.
+#[derive(Debug)]
 pub struct InMemoryContentStore {
-    store: std::collections::HashMap<HashPointer, Vec<u8>>,
+    inner: BoundedInMemoryContentStore,
+    budgeted: bool,
+}
+
+impl Default for InMemoryContentStore {
+    fn default() -> Self {
+        Self {
+            inner: BoundedInMemoryContentStore::new(usize::MAX),
+            budgeted: false,
+        }
+    }
+}
+
+impl InMemoryContentStore {
+    /// Refuses content past `budget` bytes instead of growing without
+    /// bound, evicting content the manifest no longer refers to to make
+    /// room
+    pub fn with_budget(budget: usize) -> Self {
+        Self {
+            inner: BoundedInMemoryContentStore::new(budget),
+            budgeted: true,
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.inner.used()
+    }
 }
 
 impl ContentStore for InMemoryContentStore {
-    fn add_content(&mut self, content: &[u8]) -> HashPointer {
-        let hash: HashPointer = blake3::hash(content).into();
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        self.inner.add_content(content)
+    }
+
+    fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        self.inner.get_content(hash)
+    }
+
+    fn content_hash(&self, content: &[u8]) -> Option<HashPointer> {
+        self.inner.content_hash(content)
+    }
+
+    fn add_content_under(
+        &mut self,
+        hash: &HashPointer,
+        content: &[u8],
+    ) -> Result<bool, ContentStoreError> {
+        self.inner.add_content_under(hash, content)
+    }
+
+    fn evict_unreferenced(&mut self, manifest: &Manifest) {
+        if self.budgeted {
+            self.inner.evict_unreferenced(manifest);
+        }
+    }
+}
+
+/// An in-memory store that holds at most `capacity` bytes of content.
+///
+/// Only content missing from the manifest is ever evicted, so the manifest
+/// must account for everything reachable from the root (see
+/// `BridgeFS::rebuild_manifest`). Snapshots pinned with
+/// `BridgeFS::pin_snapshot` hold references in the manifest, so their
+/// content survives eviction until they are unpinned.
+#[derive(Debug)]
+pub struct BoundedInMemoryContentStore {
+    store: HashMap<HashPointer, Vec<u8>>,
+    capacity: usize,
+    used: usize,
+}
+
+impl BoundedInMemoryContentStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            store: HashMap::new(),
+            capacity,
+            used: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+}
+
+impl ContentStore for BoundedInMemoryContentStore {
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        let hash = Blake3::hash(content);
+        if self.store.contains_key(&hash) {
+            return Ok(hash);
+        }
+        if self.used + content.len() > self.capacity {
+            return Err(ContentStoreError::CapacityExceeded);
+        }
+        self.used += content.len();
         self.store.insert(hash.clone(), content.to_vec());
-        hash
+        Ok(hash)
     }
 
     fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
         self.store.get(hash).cloned().unwrap_or_default()
     }
+
+    fn content_hash(&self, content: &[u8]) -> Option<HashPointer> {
+        Some(Blake3::hash(content))
+    }
+
+    fn add_content_under(
+        &mut self,
+        hash: &HashPointer,
+        content: &[u8],
+    ) -> Result<bool, ContentStoreError> {
+        // Whatever is already there is an encoding of the same value
+        if self.store.contains_key(hash) {
+            return Ok(true);
+        }
+        if self.used + content.len() > self.capacity {
+            return Err(ContentStoreError::CapacityExceeded);
+        }
+        self.used += content.len();
+        self.store.insert(hash.clone(), content.to_vec());
+        Ok(true)
+    }
+
+    fn evict_unreferenced(&mut self, manifest: &Manifest) {
+        self.store.retain(|hash, _| manifest.has_reference(hash));
+        self.used = self.store.values().map(Vec::len).sum();
+    }
+}
+
+/// Reads through to a read-only `lower` store for anything that has not been
+/// written to `upper`, which receives all new content
+#[derive(Debug)]
+pub struct OverlayContentStore<LowerT: ContentStore, UpperT: ContentStore> {
+    lower: LowerT,
+    upper: UpperT,
+    upper_hashes: HashSet<HashPointer>,
+}
+
+impl<LowerT: ContentStore, UpperT: ContentStore> OverlayContentStore<LowerT, UpperT> {
+    pub fn new(lower: LowerT, upper: UpperT) -> Self {
+        Self {
+            lower,
+            upper,
+            upper_hashes: HashSet::new(),
+        }
+    }
+}
+
+impl<LowerT: ContentStore, UpperT: ContentStore> ContentStore
+    for OverlayContentStore<LowerT, UpperT>
+{
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        let hash = self.upper.add_content(content)?;
+        self.upper_hashes.insert(hash.clone());
+        Ok(hash)
+    }
+
+    fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        if self.upper_hashes.contains(hash) {
+            self.upper.get_content(hash)
+        } else {
+            self.lower.get_content(hash)
+        }
+    }
+
+    fn try_get_content(&self, hash: &HashPointer) -> Result<Vec<u8>, ContentStoreError> {
+        if self.upper_hashes.contains(hash) {
+            self.upper.try_get_content(hash)
+        } else {
+            self.lower.try_get_content(hash)
+        }
+    }
+
+    fn content_hash(&self, content: &[u8]) -> Option<HashPointer> {
+        self.upper.content_hash(content)
+    }
+
+    fn add_content_under(
+        &mut self,
+        hash: &HashPointer,
+        content: &[u8],
+    ) -> Result<bool, ContentStoreError> {
+        let stored = self.upper.add_content_under(hash, content)?;
+        if stored {
+            self.upper_hashes.insert(hash.clone());
+        }
+        Ok(stored)
+    }
+
+    fn evict_unreferenced(&mut self, manifest: &Manifest) {
+        self.upper.evict_unreferenced(manifest);
+        // Upper may keep more than it was asked to drop, and whatever it
+        // still holds must not be read from lower instead
+        let upper = &self.upper;
+        self.upper_hashes
+            .retain(|hash| manifest.has_reference(hash) || !upper.get_content(hash).is_empty());
+    }
+
+    fn is_writable(&self) -> bool {
+        self.upper.is_writable()
+    }
 }
 
 pub trait ParsingContentStoreExt: ContentStore {
     fn get_parsed<U: Decode<()>>(&self, hash: &TypedHashPointer<U>) -> U {
-        let bytes = self.get_content(&hash.into());
-        bincode::decode_from_slice::<U, _>(&bytes, bincode::config::standard())
-            .unwrap()
-            .0
+        self.try_get_parsed(hash)
+            .expect("Stored content should decode")
+    }
+
+    /// Like `get_parsed`, but fails if the content is missing or does not
+    /// decode to exactly one `U`
+    fn try_get_parsed<U: Decode<()>>(&self, hash: &TypedHashPointer<U>) -> Result<U, ParseError> {
+        let hash: HashPointer = hash.into();
+        let bytes = match self.try_get_content(&hash) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return Err(ParseError {
+                    hash,
+                    cause: ParseFailure::Fetch(e),
+                });
+            }
+        };
+        match bincode::decode_from_slice::<U, _>(&bytes, bincode::config::standard()) {
+            Ok((value, len)) if len == bytes.len() => Ok(value),
+            Ok(_) => Err(ParseError {
+                hash,
+                cause: ParseFailure::Decode(DecodeError::Other(
+                    "trailing bytes after the decoded value",
+                )),
+            }),
+            Err(e) => Err(ParseError {
+                hash,
+                cause: ParseFailure::Decode(e),
+            }),
+        }
+    }
+
+    fn add_parsed<U: bincode::Encode>(
+        &mut self,
+        value: &U,
+    ) -> Result<TypedHashPointer<U>, ContentStoreError> {
+        let bytes = bincode::encode_to_vec(value, bincode::config::standard()).unwrap();
+        let hash_pointer = self.add_content(&bytes)?;
+        Ok(TypedHashPointer::new(hash_pointer))
     }
 
-    fn add_parsed<U: bincode::Encode>(&mut self, value: &U) -> TypedHashPointer<U> {
+    /// The hash `add_parsed` would store `value` under, if the store can
+    /// tell without storing it
+    fn parsed_hash<U: bincode::Encode>(&self, value: &U) -> Option<TypedHashPointer<U>> {
         let bytes = bincode::encode_to_vec(value, bincode::config::standard()).unwrap();
-        let hash_pointer = self.add_content(&bytes);
-        TypedHashPointer::new(hash_pointer)
+        self.content_hash(&bytes).map(TypedHashPointer::new)
     }
 }
 
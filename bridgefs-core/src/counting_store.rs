@@ -1,53 +1,255 @@
 use bincode::{Decode, Encode};
 
 use crate::{
-    content_store::{ContentStore, ParsingContentStoreExt},
-    hash_pointer::TypedHashPointer,
-    manifest::Manifest,
+    content_store::{ContentStore, ContentStoreError, ParseError, ParsingContentStoreExt},
+    hash_pointer::{HashPointer, TypedHashPointer},
+    manifest::{Manifest, ReferenceCounting, StoreReport},
 };
 
 #[derive(Debug)]
 pub struct CountingStore<T: ContentStore> {
     store: T,
     manifest: Manifest,
+    checkpoint: Option<Manifest>,
+    counting: ReferenceCounting,
+    /// Content kept alive whether or not anything refers to it; each pin
+    /// also holds a reference in `manifest`
+    pins: Manifest,
 }
 
 pub trait HasReferences<StoreT: ContentStore> {
-    fn delete_references(&self, new_value: Option<&Self>, store: &mut CountingStore<StoreT>);
+    /// Leaves can set this to false so deleting them never fetches content
+    const HAS_REFERENCES: bool = true;
+
+    fn delete_references(
+        &self,
+        new_value: Option<&Self>,
+        store: &mut CountingStore<StoreT>,
+    ) -> Result<(), ContentStoreError>;
 }
 
 impl<StoreT: ContentStore> CountingStore<StoreT> {
-    pub fn new(store: StoreT, manifest: Manifest) -> CountingStore<StoreT> {
-        CountingStore { store, manifest }
+    pub fn new(
+        store: StoreT,
+        manifest: Manifest,
+        counting: ReferenceCounting,
+    ) -> CountingStore<StoreT> {
+        CountingStore {
+            store,
+            manifest,
+            checkpoint: None,
+            counting,
+            pins: Manifest::new(),
+        }
+    }
+
+    pub fn into_store(self) -> StoreT {
+        self.store
+    }
+
+    pub fn counting(&self) -> ReferenceCounting {
+        self.counting
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Summarises the manifest, fetching each shared block to size it
+    pub fn report(&self) -> StoreReport {
+        let mut report = StoreReport::default();
+        for (reference, count) in self.manifest.reference_counts() {
+            report.referenced_blocks += 1;
+            report.total_references += count;
+            if count > 1 {
+                report.dedup_savings += (count - 1) * self.store.get_content(reference).len();
+            }
+            if report
+                .most_referenced
+                .as_ref()
+                .is_none_or(|(_, most)| count > *most)
+            {
+                report.most_referenced = Some((reference.clone(), count));
+            }
+        }
+        report
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.store.is_writable()
+    }
+
+    /// How many bytes the store holds for `hash`
+    pub fn stored_size(&self, hash: &HashPointer) -> usize {
+        self.get_content(hash).len()
+    }
+
+    pub fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        self.store.get_content(hash)
+    }
+
+    pub fn try_get_content(&self, hash: &HashPointer) -> Result<Vec<u8>, ContentStoreError> {
+        self.store.try_get_content(hash)
+    }
+
+    /// Replaces the reference counts, keeping the references pins hold
+    pub fn set_manifest(&mut self, mut manifest: Manifest) {
+        manifest.extend(&self.pins);
+        self.manifest = manifest;
+    }
+
+    /// Keeps `hash` from being collected even once nothing refers to it.
+    /// Pins are counted, so content pinned twice needs unpinning twice.
+    pub fn pin(&mut self, hash: &HashPointer) {
+        self.pins.add_reference(hash.clone());
+        self.manifest.add_reference(hash.clone());
+    }
+
+    /// Releases one pin on `hash`; content that was never pinned is left
+    /// alone rather than losing a real reference
+    pub fn unpin(&mut self, hash: &HashPointer) {
+        if self.pins.has_reference(hash) {
+            self.pins.remove_reference(hash.clone());
+            self.manifest.remove_reference(hash.clone());
+        }
+    }
+
+    pub fn pins(&self) -> &Manifest {
+        &self.pins
+    }
+
+    /// Evicts everything the manifest does not refer to, pinned content
+    /// excepted, from stores that evict at all. Does nothing under
+    /// `ReferenceCounting::Deferred`, where the manifest holds only pins.
+    pub fn gc(&mut self) {
+        if self.counting == ReferenceCounting::Eager {
+            self.store.evict_unreferenced(&self.manifest);
+        }
+    }
+
+    /// Captures the reference counts so a failed operation can be undone
+    pub fn checkpoint(&mut self) {
+        self.checkpoint = Some(self.manifest.clone());
+    }
+
+    pub fn rollback(&mut self) {
+        if let Some(checkpoint) = self.checkpoint.take() {
+            self.manifest = checkpoint;
+        }
+    }
+
+    pub fn commit(&mut self) {
+        self.checkpoint = None;
     }
 
-    pub fn get_parsed<U: Decode<()>>(&self, hash: &TypedHashPointer<U>) -> U {
-        self.store.get_parsed(hash)
+    /// Counts another reference to content that is already stored
+    pub fn add_reference(&mut self, hash: &HashPointer) {
+        if self.counting == ReferenceCounting::Eager {
+            self.manifest.add_reference(hash.clone());
+        }
     }
 
-    pub fn store_new_content<T: Encode>(&mut self, value: &T) -> TypedHashPointer<T> {
-        let hash = self.store.add_parsed(value);
-        self.manifest.add_reference((&hash).into());
-        hash
+    pub fn try_get_parsed<U: Decode<()>>(
+        &self,
+        hash: &TypedHashPointer<U>,
+    ) -> Result<U, ParseError> {
+        self.store.try_get_parsed(hash)
+    }
+
+    /// Stores `value`, giving the underlying store one chance to evict
+    /// unreferenced content if it is full
+    pub fn store_new_content<T: Encode>(
+        &mut self,
+        value: &T,
+    ) -> Result<TypedHashPointer<T>, ContentStoreError> {
+        let hash = self.with_room(|store| store.add_parsed(value))?;
+        if self.counting == ReferenceCounting::Eager {
+            self.manifest.add_reference((&hash).into());
+        }
+        Ok(hash)
+    }
+
+    /// Stores `encoded`, an encoding of `value` such as its compressed
+    /// form, under the hash `value` itself would be stored under, so both
+    /// forms share one hash. `None`, with nothing stored, when the store
+    /// cannot key content by any hash but its own.
+    pub fn store_encoded_content<T: Encode>(
+        &mut self,
+        value: &T,
+        encoded: &[u8],
+    ) -> Result<Option<TypedHashPointer<T>>, ContentStoreError> {
+        let Some(hash) = self.store.parsed_hash(value) else {
+            return Ok(None);
+        };
+        let hash_pointer: HashPointer = (&hash).into();
+        if !self.with_room(|store| store.add_content_under(&hash_pointer, encoded))? {
+            return Ok(None);
+        }
+        if self.counting == ReferenceCounting::Eager {
+            self.manifest.add_reference(hash_pointer);
+        }
+        Ok(Some(hash))
+    }
+
+    /// Runs `add` against the store, evicting unreferenced content and
+    /// trying once more if the store is full
+    fn with_room<R>(
+        &mut self,
+        mut add: impl FnMut(&mut StoreT) -> Result<R, ContentStoreError>,
+    ) -> Result<R, ContentStoreError> {
+        match add(&mut self.store) {
+            Err(ContentStoreError::CapacityExceeded)
+                if self.counting == ReferenceCounting::Eager =>
+            {
+                // Content released since the checkpoint comes back on rollback
+                let mut live = self.manifest.clone();
+                if let Some(checkpoint) = &self.checkpoint {
+                    live.extend(checkpoint);
+                }
+                self.store.evict_unreferenced(&live);
+                add(&mut self.store)
+            }
+            result => result,
+        }
     }
 
     pub fn delete_content<T: Encode + Decode<()> + HasReferences<StoreT>>(
         &mut self,
         hash: &TypedHashPointer<T>,
-    ) {
-        let item_to_delete: T = self.get_parsed(hash);
+    ) -> Result<(), ContentStoreError> {
+        if self.counting == ReferenceCounting::Deferred {
+            return Ok(());
+        }
         self.manifest.remove_reference(hash.into());
-        item_to_delete.delete_references(None, self);
+        if T::HAS_REFERENCES {
+            let item_to_delete: T = self.try_get_parsed(hash)?;
+            item_to_delete.delete_references(None, self)?;
+        }
+        Ok(())
     }
 
     pub fn replace_content<T: Encode + Decode<()> + HasReferences<StoreT>>(
         &mut self,
         previous: &TypedHashPointer<T>,
         value: &T,
-    ) -> TypedHashPointer<T> {
-        let item_to_delete: T = self.get_parsed(previous);
+    ) -> Result<TypedHashPointer<T>, ContentStoreError> {
+        // Rewriting what is already there, as a write of the same bytes
+        // does, leaves both the store and the manifest alone
+        if self.store.parsed_hash(value).as_ref() == Some(previous) {
+            return Ok(previous.clone());
+        }
+        if self.counting == ReferenceCounting::Deferred {
+            return self.store_new_content(value);
+        }
+        // Stored while the previous value is still referenced, so it cannot
+        // be evicted before it has been read below
+        let hash = self.store_new_content(value)?;
         self.manifest.remove_reference(previous.into());
-        item_to_delete.delete_references(Some(value), self);
-        self.store_new_content(value)
+        // An unchanged hash is unchanged content, which releases nothing
+        if T::HAS_REFERENCES && hash != *previous {
+            let item_to_delete: T = self.try_get_parsed(previous)?;
+            item_to_delete.delete_references(Some(value), self)?;
+        }
+        Ok(hash)
     }
 }
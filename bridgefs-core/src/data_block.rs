@@ -1,6 +1,10 @@
 use bincode::{Decode, Encode};
 
-use crate::{content_store::ContentStore, counting_store::HasReferences};
+use crate::{
+    compression::CompressedBlock,
+    content_store::{ContentStore, ContentStoreError},
+    counting_store::HasReferences,
+};
 
 #[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, Default)]
 pub struct DataBlock {
@@ -15,14 +19,38 @@ impl DataBlock {
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
+
+    /// Reads a block back from the bytes stored for it, either a plain
+    /// `DataBlock` or a `CompressedBlock` under the plain block's hash.
+    /// `None` if the bytes are neither.
+    pub fn from_stored(bytes: &[u8]) -> Option<DataBlock> {
+        // A compressed block never decodes as exactly one plain block
+        if let Some(block) = decode_exactly::<DataBlock>(bytes) {
+            return Some(block);
+        }
+        let compressed = decode_exactly::<CompressedBlock>(bytes)?;
+        Some(DataBlock {
+            data: compressed.decompress()?,
+        })
+    }
 }
 
 impl<StoreT: ContentStore> HasReferences<StoreT> for DataBlock {
+    const HAS_REFERENCES: bool = false;
+
     fn delete_references(
         &self,
         _new_value: Option<&Self>,
         _store: &mut crate::counting_store::CountingStore<StoreT>,
-    ) {
+    ) -> Result<(), ContentStoreError> {
         // no-op
+        Ok(())
+    }
+}
+
+fn decode_exactly<T: Decode<()>>(bytes: &[u8]) -> Option<T> {
+    match bincode::decode_from_slice(bytes, bincode::config::standard()) {
+        Ok((value, len)) if len == bytes.len() => Some(value),
+        _ => None,
     }
 }
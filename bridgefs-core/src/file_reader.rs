@@ -0,0 +1,60 @@
+use std::io::{self, Read};
+
+use crate::{
+    bridgefs::BridgeFS, content_store::ContentStore, hash_pointer::TypedHashPointerReference,
+    index::INodeIndex, inode::INode,
+};
+
+/// Streams a file's bytes from the start, as returned by
+/// `BridgeFS::open_reader`.
+///
+/// The first `read` fetches the whole file from the current root and later
+/// ones are served from that copy, so writes made after reading starts are
+/// not seen. Errors from the filesystem are returned as `io::Error`s
+/// wrapping the `FSError`.
+pub struct FileReader<'a, IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> {
+    bridgefs: &'a mut BridgeFS<IndexHashT, StoreT>,
+    inode: INode,
+    position: usize,
+    data: Option<Vec<u8>>,
+}
+
+impl<'a, IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
+    FileReader<'a, IndexHashT, StoreT>
+{
+    pub(crate) fn new(bridgefs: &'a mut BridgeFS<IndexHashT, StoreT>, inode: INode) -> Self {
+        Self {
+            bridgefs,
+            inode,
+            position: 0,
+            data: None,
+        }
+    }
+
+    /// How many bytes have been read so far
+    pub fn position(&self) -> u64 {
+        self.position as u64
+    }
+}
+
+impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Read
+    for FileReader<'_, IndexHashT, StoreT>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = match &mut self.data {
+            Some(data) => data,
+            None => {
+                let data = self
+                    .bridgefs
+                    .read_entire_file(self.inode)
+                    .map_err(io::Error::other)?;
+                self.data.insert(data)
+            }
+        };
+        let remaining = data.get(self.position..).unwrap_or_default();
+        let read = remaining.len().min(buf.len());
+        buf[..read].copy_from_slice(&remaining[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
@@ -1,13 +1,14 @@
-use std::{collections::HashMap, time::SystemTime};
+use std::{collections::BTreeMap, time::SystemTime};
 
 use bincode::{Decode, Encode};
 
 use crate::{
-    content_store::ContentStore,
+    compression::Compression,
+    content_store::{ContentStore, ContentStoreError},
     counting_store::{CountingStore, HasReferences},
     data_block::DataBlock,
     filename::Filename,
-    hash_pointer::TypedHashPointer,
+    hash_pointer::{HashPointer, TypedHashPointer},
     inode::INode,
 };
 
@@ -18,15 +19,32 @@ pub enum Record {
 }
 
 impl<StoreT: ContentStore> HasReferences<StoreT> for Record {
-    fn delete_references(&self, _new_value: Option<&Self>, store: &mut CountingStore<StoreT>) {
+    fn delete_references(
+        &self,
+        new_value: Option<&Self>,
+        store: &mut CountingStore<StoreT>,
+    ) -> Result<(), ContentStoreError> {
         match self {
-            Record::File(file_record) => store.delete_content(&file_record.content_hash),
+            Record::File(file_record) => {
+                // Blocks the new version still points at, as after an
+                // attribute-only update, keep their reference
+                let retained = match new_value {
+                    Some(Record::File(new_file_record)) => new_file_record.blocks(),
+                    _ => Vec::new(),
+                };
+                for block in file_record.blocks() {
+                    if !retained.contains(&block) {
+                        store.delete_content(block)?;
+                    }
+                }
+            }
             Record::Directory(_directory_record) => {
                 // Directory should already be empty when deleted with rmdir
                 // When contents, change, deletion of inodes is handled by the BridgeFS layer
                 // So there's nothing to do!
             }
         }
+        Ok(())
     }
 }
 
@@ -44,6 +62,19 @@ impl Record {
             Record::Directory(directory_record) => directory_record.common_attrs = attrs,
         }
     }
+
+    /// The content blocks this record references; the read-only counterpart
+    /// to `HasReferences::delete_references`
+    pub fn content_hashes(&self) -> Vec<HashPointer> {
+        match self {
+            Record::File(file_record) => file_record
+                .blocks()
+                .into_iter()
+                .map(HashPointer::from)
+                .collect(),
+            Record::Directory(_directory_record) => Vec::new(),
+        }
+    }
 }
 
 impl From<FileRecord> for Record {
@@ -58,12 +89,62 @@ impl From<DirectoryRecord> for Record {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Decode, Encode, Default, bon::Builder)]
+#[derive(Debug, Clone, PartialEq, Eq, Decode, Encode, bon::Builder)]
 pub struct DirectoryRecord {
-    #[builder(default = HashMap::new())]
-    pub children: HashMap<Filename, INode>,
+    /// Sorted, so a directory always encodes to the same bytes
+    #[builder(default = BTreeMap::new())]
+    pub children: BTreeMap<Filename, INode>,
+    #[builder(default = CommonAttrs::directory())]
     pub common_attrs: CommonAttrs,
+    /// The root is its own parent
+    #[builder(default = INode::ROOT)]
     pub parent: INode,
+    /// What entries created inside take on in place of their creator's own
+    /// mode and ownership
+    pub default_attrs: Option<DefaultAttrs>,
+    /// When each child was added, as the value `next_sequence` had then.
+    /// Unlike birth times, callers cannot set these.
+    #[builder(default = BTreeMap::new())]
+    pub sequence: BTreeMap<Filename, u64>,
+    #[builder(default = 0)]
+    pub next_sequence: u64,
+}
+
+/// Mode and ownership a directory hands down to new entries, like a default
+/// ACL; fields left as `None` are kept as the creator asked. Subdirectories
+/// inherit the defaults themselves too.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Decode, Encode)]
+pub struct DefaultAttrs {
+    pub file_perm: Option<u16>,
+    pub directory_perm: Option<u16>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl DefaultAttrs {
+    /// `attributes` as a new file, or directory, inside sees them
+    pub fn apply(&self, attributes: &mut CommonAttrs, directory: bool) {
+        let perm = if directory {
+            self.directory_perm
+        } else {
+            self.file_perm
+        };
+        if let Some(perm) = perm {
+            attributes.perm = perm;
+        }
+        if let Some(uid) = self.uid {
+            attributes.uid = uid;
+        }
+        if let Some(gid) = self.gid {
+            attributes.gid = gid;
+        }
+    }
+}
+
+impl Default for DirectoryRecord {
+    fn default() -> DirectoryRecord {
+        DirectoryRecord::builder().build()
+    }
 }
 
 #[derive(Hash, PartialEq, Eq, Clone)]
@@ -74,13 +155,22 @@ pub struct IndexMapping {
 
 impl DirectoryRecord {
     pub fn insert(&mut self, filename: Filename, inode: INode) {
+        self.sequence.insert(filename.clone(), self.next_sequence);
+        self.next_sequence += 1;
         self.children.insert(filename, inode);
     }
 
     pub fn remove(&mut self, filename: &Filename) -> Option<INode> {
+        self.sequence.remove(filename);
         self.children.remove(filename)
     }
 
+    /// Where `filename` falls in the order children were added; `None` for
+    /// names this directory does not hold
+    pub fn sequence_of(&self, filename: &Filename) -> Option<u64> {
+        self.sequence.get(filename).copied()
+    }
+
     pub fn get(&self, filename: &Filename) -> Option<&INode> {
         self.children.get(filename)
     }
@@ -105,12 +195,46 @@ pub struct FileRecord {
     pub content_hash: TypedHashPointer<DataBlock>,
     pub size: u64,
     pub common_attrs: CommonAttrs,
+    /// How many directory entries name this file; its content is only
+    /// released once the last one is removed
+    #[builder(default = 1)]
+    pub nlink: u32,
+    /// How this file's writer stored the block behind `content_hash`. The
+    /// stored block names its own codec, which is what reads go by, since
+    /// the same content may have been stored the other way first. `size` is
+    /// always the uncompressed length.
+    pub compression: Option<Compression>,
+}
+
+impl FileRecord {
+    pub fn is_executable(&self) -> bool {
+        self.common_attrs.is_executable()
+    }
+
+    /// Every data block the file's content is made of, in order.
+    ///
+    /// Always just `content_hash` until files are chunked, so releasing only
+    /// the blocks an edit replaced cannot be exercised with more than one
+    /// block before then.
+    pub fn blocks(&self) -> Vec<&TypedHashPointer<DataBlock>> {
+        vec![&self.content_hash]
+    }
 }
 
+/// `rw-r--r--`, so new files are not executable unless asked for
+pub const DEFAULT_FILE_PERM: u16 = 0o644;
+/// `rwxr-xr-x`, since directories need the execute bit to be searched
+pub const DEFAULT_DIRECTORY_PERM: u16 = 0o755;
+
+/// The BSD flag the owner sets to make a file immutable
+pub const UF_IMMUTABLE: u32 = 0x2;
+/// The BSD flag only root can set to make a file immutable
+pub const SF_IMMUTABLE: u32 = 0x20000;
+
 /// Attributes that are shared between files and directories
 #[derive(Debug, Clone, PartialEq, Eq, Decode, Encode, bon::Builder)]
 pub struct CommonAttrs {
-    #[builder(default = 0o755)]
+    #[builder(default = DEFAULT_FILE_PERM)]
     pub perm: u16,
     #[builder(default = 501)]
     pub uid: u32,
@@ -124,8 +248,79 @@ pub struct CommonAttrs {
     pub ctime: SystemTime,
     #[builder(default = SystemTime::now())]
     pub crtime: SystemTime,
+    /// BSD-style file flags such as `UF_HIDDEN`
+    #[builder(default)]
+    pub flags: u32,
+    /// Bumped each time the inode number is reused, so stale handles can be told apart
+    #[builder(default)]
+    pub generation: u64,
+}
+
+const READ: u16 = 0o4;
+const WRITE: u16 = 0o2;
+const EXECUTE: u16 = 0o1;
+const SETUID: u16 = 0o4000;
+const SETGID: u16 = 0o2000;
+const STICKY: u16 = 0o1000;
+
+impl CommonAttrs {
+    /// The defaults for a new directory
+    pub fn directory() -> CommonAttrs {
+        CommonAttrs::builder().perm(DEFAULT_DIRECTORY_PERM).build()
+    }
+
+    /// Whether either immutable flag is set, so that the content cannot be
+    /// changed, the file cannot be removed, and only the flags themselves
+    /// can be updated
+    pub fn is_immutable(&self) -> bool {
+        self.flags & (UF_IMMUTABLE | SF_IMMUTABLE) != 0
+    }
+
+    /// Whether any of the owner, group or other execute bits are set
+    pub fn is_executable(&self) -> bool {
+        self.perm & 0o111 != 0
+    }
+
+    pub fn is_readable_by(&self, uid: u32, gid: u32) -> bool {
+        uid == 0 || self.permission_bits_for(uid, gid) & READ != 0
+    }
+
+    pub fn is_writable_by(&self, uid: u32, gid: u32) -> bool {
+        uid == 0 || self.permission_bits_for(uid, gid) & WRITE != 0
+    }
+
+    /// Root may execute anything that is executable by someone
+    pub fn is_executable_by(&self, uid: u32, gid: u32) -> bool {
+        if uid == 0 {
+            return self.is_executable();
+        }
+        self.permission_bits_for(uid, gid) & EXECUTE != 0
+    }
+
+    /// Whether `uid` may unlink or rename `entry` out of this directory:
+    /// with the sticky bit set only the owner of either one, or root, can
+    pub fn allows_removal_by(&self, entry: &CommonAttrs, uid: u32) -> bool {
+        self.perm & STICKY == 0 || uid == 0 || uid == self.uid || uid == entry.uid
+    }
+
+    /// Drops setuid and setgid, as POSIX requires when file content changes
+    pub fn clear_setid(&mut self) {
+        self.perm &= !(SETUID | SETGID);
+    }
+
+    /// The rwx triplet that applies to the given credentials
+    fn permission_bits_for(&self, uid: u32, gid: u32) -> u16 {
+        if uid == self.uid {
+            (self.perm >> 6) & 0o7
+        } else if gid == self.gid {
+            (self.perm >> 3) & 0o7
+        } else {
+            self.perm & 0o7
+        }
+    }
 }
 
+/// Suits a file; use `CommonAttrs::directory` for directories
 impl Default for CommonAttrs {
     fn default() -> CommonAttrs {
         CommonAttrs::builder().build()
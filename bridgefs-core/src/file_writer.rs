@@ -0,0 +1,78 @@
+use std::io::{self, Write};
+
+use crate::{
+    bridgefs::BridgeFS, content_store::ContentStore, hash_pointer::TypedHashPointerReference,
+    index::INodeIndex, inode::INode,
+};
+
+/// How much a `FileWriter` buffers before committing it on its own
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// Writes a file from the start, as returned by `BridgeFS::open_writer`.
+///
+/// Writes are buffered and committed as one `write_to_file` each time the
+/// buffer fills and on `flush`, so copying a file in costs a commit per
+/// megabyte rather than per call. Dropping the writer commits what is left
+/// but, like `BufWriter`, cannot report a failure to; call `flush` first to
+/// see it.
+pub struct FileWriter<'a, IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> {
+    bridgefs: &'a mut BridgeFS<IndexHashT, StoreT>,
+    inode: INode,
+    /// Where in the file `buffer` starts
+    position: usize,
+    buffer: Vec<u8>,
+}
+
+impl<'a, IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
+    FileWriter<'a, IndexHashT, StoreT>
+{
+    pub(crate) fn new(bridgefs: &'a mut BridgeFS<IndexHashT, StoreT>, inode: INode) -> Self {
+        Self {
+            bridgefs,
+            inode,
+            position: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// How many bytes have been written so far, committed or not
+    pub fn position(&self) -> u64 {
+        (self.position + self.buffer.len()) as u64
+    }
+}
+
+impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Write
+    for FileWriter<'_, IndexHashT, StoreT>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.buffer.is_empty() {
+            let written = self
+                .bridgefs
+                .write_to_file(self.inode, self.position, &self.buffer)
+                .map_err(io::Error::other)?;
+            // Only a size limit makes a write come up short
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.buffer.drain(..written);
+            self.position += written;
+        }
+        Ok(())
+    }
+}
+
+impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Drop
+    for FileWriter<'_, IndexHashT, StoreT>
+{
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
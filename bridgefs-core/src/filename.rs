@@ -1,14 +1,39 @@
-use std::os::unix::ffi::OsStringExt;
+use std::{ffi::OsStr, fmt::Display, os::unix::ffi::OsStringExt};
 
 use bincode::{Decode, Encode};
 
-#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, Hash)]
+use crate::response::FileOperationError;
+
+/// How names coming from the host are turned into `Filename`s
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilenameEncoding {
+    /// Keep the platform's bytes as-is, which is all Linux guarantees
+    #[default]
+    Raw,
+    /// Only accept valid UTF-8, so names round-trip on any platform
+    Utf8,
+}
+
+impl FilenameEncoding {
+    pub fn encode(self, os_str: &OsStr) -> Result<Filename, FileOperationError> {
+        match self {
+            FilenameEncoding::Raw => Ok(os_str.into()),
+            FilenameEncoding::Utf8 => os_str
+                .to_str()
+                .map(Filename::from)
+                .ok_or(FileOperationError::InvalidName),
+        }
+    }
+}
+
+/// Compares byte-wise, so orderings are stable across platforms and locales
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Filename {
     pub name: Vec<u8>,
 }
 
-impl From<&std::ffi::OsStr> for Filename {
-    fn from(os_str: &std::ffi::OsStr) -> Self {
+impl From<&OsStr> for Filename {
+    fn from(os_str: &OsStr) -> Self {
         Filename {
             name: os_str.as_encoded_bytes().to_vec(),
         }
@@ -34,3 +59,9 @@ impl From<&str> for Filename {
         }
     }
 }
+
+impl Display for Filename {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.name))
+    }
+}
@@ -1,24 +1,101 @@
-use std::{fmt::Debug, marker::PhantomData};
+use std::{
+    array::TryFromSliceError,
+    fmt::{Debug, Display},
+    hash::Hash,
+    marker::PhantomData,
+    str::FromStr,
+};
 
 use base64::{Engine, engine::general_purpose};
 use bincode::{Decode, Encode};
 
+use crate::content_store::ContentStoreError;
+
+/// Shortest digest a `HashPointer` holds, below which collisions get likely
+pub const MIN_DIGEST_LEN: usize = 16;
+/// Longest digest a `HashPointer` holds
+pub const MAX_DIGEST_LEN: usize = 64;
+
+/// A digest of content, of whatever length the `Hasher` that made it
+/// produces. The length is encoded along with the bytes, so pointers of
+/// different lengths can sit side by side without being mistaken for one
+/// another.
 #[derive(Encode, Decode, Hash, PartialOrd, Ord, PartialEq, Eq, Clone)]
 pub struct HashPointer {
-    bytes: [u8; 32],
+    bytes: Vec<u8>,
+}
+
+impl HashPointer {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Turns content into the `HashPointer` it is stored under
+pub trait Hasher {
+    /// Length in bytes of every digest `hash` returns
+    const DIGEST_LEN: usize;
+
+    fn hash(content: &[u8]) -> HashPointer;
+}
+
+/// blake3 at its native 32 bytes, what every store uses by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3;
+
+impl Hasher for Blake3 {
+    const DIGEST_LEN: usize = blake3::OUT_LEN;
+
+    fn hash(content: &[u8]) -> HashPointer {
+        blake3::hash(content).into()
+    }
+}
+
+/// blake3 read out to `LEN` bytes, for interop with systems using shorter
+/// digests, like git's 20, or longer ones
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3Xof<const LEN: usize>;
+
+impl<const LEN: usize> Hasher for Blake3Xof<LEN> {
+    const DIGEST_LEN: usize = {
+        assert!(MIN_DIGEST_LEN <= LEN && LEN <= MAX_DIGEST_LEN);
+        LEN
+    };
+
+    fn hash(content: &[u8]) -> HashPointer {
+        let mut bytes = vec![0; Self::DIGEST_LEN];
+        blake3::Hasher::new()
+            .update(content)
+            .finalize_xof()
+            .fill(&mut bytes);
+        HashPointer { bytes }
+    }
 }
 
 impl From<blake3::Hash> for HashPointer {
     fn from(blake3_hash: blake3::Hash) -> Self {
         HashPointer {
-            bytes: *blake3_hash.as_bytes(),
+            bytes: blake3_hash.as_bytes().to_vec(),
         }
     }
 }
 
-impl From<&HashPointer> for blake3::Hash {
-    fn from(hash_pointer: &HashPointer) -> Self {
-        blake3::Hash::from_bytes(hash_pointer.bytes)
+/// Only pointers of blake3's native length convert back
+impl TryFrom<&HashPointer> for blake3::Hash {
+    type Error = TryFromSliceError;
+
+    fn try_from(hash_pointer: &HashPointer) -> Result<Self, Self::Error> {
+        Ok(blake3::Hash::from_bytes(
+            hash_pointer.bytes.as_slice().try_into()?,
+        ))
     }
 }
 
@@ -36,15 +113,31 @@ impl<T> From<&TypedHashPointer<T>> for HashPointer {
 
 impl Debug for HashPointer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "HashPointer({})",
-            general_purpose::STANDARD.encode(self.bytes)
-        )
+        write!(f, "HashPointer({self})")
     }
 }
 
-#[derive(Encode, Decode, Hash, PartialEq, PartialOrd, Ord, Eq, Debug)]
+/// Formats as standard base64
+impl Display for HashPointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&general_purpose::STANDARD.encode(&self.bytes))
+    }
+}
+
+/// Parses the standard base64 `Display` produces
+impl FromStr for HashPointer {
+    type Err = base64::DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = general_purpose::STANDARD.decode(s)?;
+        if !(MIN_DIGEST_LEN..=MAX_DIGEST_LEN).contains(&bytes.len()) {
+            return Err(base64::DecodeError::InvalidLength(bytes.len()));
+        }
+        Ok(HashPointer { bytes })
+    }
+}
+
+#[derive(Encode, Decode, PartialOrd, Ord, Debug)]
 pub struct TypedHashPointer<T> {
     hash_pointer: HashPointer,
     _marker: PhantomData<T>,
@@ -59,6 +152,22 @@ impl<T> Clone for TypedHashPointer<T> {
     }
 }
 
+/// Content addressed, so equal hashes mean equal values; comparing them
+/// never needs the value decoded, or even `T: PartialEq`
+impl<T> PartialEq for TypedHashPointer<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash_pointer == other.hash_pointer
+    }
+}
+
+impl<T> Eq for TypedHashPointer<T> {}
+
+impl<T> Hash for TypedHashPointer<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.hash_pointer.hash(state)
+    }
+}
+
 impl<T> TypedHashPointer<T> {
     pub fn new(hash_pointer: HashPointer) -> Self {
         Self {
@@ -72,6 +181,30 @@ pub trait HashPointerReference {
     fn set(&mut self, value: &HashPointer);
 
     fn get(&mut self) -> HashPointer;
+
+    /// Like `get`, but fails instead of panicking when the backend cannot
+    /// read the value
+    fn try_get(&mut self) -> Result<HashPointer, ContentStoreError> {
+        Ok(self.get())
+    }
+
+    /// Sets the value only if it still equals `expected`, returning whether it did.
+    ///
+    /// Only as atomic as the backend allows: baybridge has no conditional
+    /// write, so its reference compares and then sets, and a writer landing
+    /// between the two is still overwritten. There the check is best-effort,
+    /// catching a change made before the compare but not one racing it.
+    fn set_if(&mut self, expected: &HashPointer, value: &HashPointer) -> bool;
+
+    /// Makes a value `set` earlier durable, for backends that hold writes
+    /// back; others have nothing to do
+    fn flush(&mut self) {}
+
+    /// Every value this reference has held, oldest first, for backends that
+    /// keep track of them; others return nothing
+    fn history(&self) -> Vec<HashPointer> {
+        Vec::new()
+    }
 }
 
 pub trait TypedHashPointerReference<T>: HashPointerReference {
@@ -81,26 +214,61 @@ pub trait TypedHashPointerReference<T>: HashPointerReference {
     fn get_typed(&mut self) -> TypedHashPointer<T> {
         TypedHashPointer::new(self.get())
     }
+    fn try_get_typed(&mut self) -> Result<TypedHashPointer<T>, ContentStoreError> {
+        self.try_get().map(TypedHashPointer::new)
+    }
+    fn set_typed_if(
+        &mut self,
+        expected: &TypedHashPointer<T>,
+        value: &TypedHashPointer<T>,
+    ) -> bool {
+        self.set_if(&expected.into(), &value.into())
+    }
 }
 
 impl<U, T: HashPointerReference> TypedHashPointerReference<U> for T {}
 
 pub struct InMemoryHashPointerReference {
     value: HashPointer,
+    history: Option<Vec<HashPointer>>,
 }
 
 impl InMemoryHashPointerReference {
     pub fn new(value: HashPointer) -> Self {
-        Self { value }
+        Self {
+            value,
+            history: None,
+        }
+    }
+
+    /// Remembers every value set from now on, starting with the current one
+    pub fn track_history(mut self) -> Self {
+        self.history = Some(vec![self.value.clone()]);
+        self
     }
 }
 
 impl HashPointerReference for InMemoryHashPointerReference {
     fn set(&mut self, value: &HashPointer) {
         self.value = value.clone();
+        if let Some(history) = &mut self.history {
+            history.push(value.clone());
+        }
     }
 
     fn get(&mut self) -> HashPointer {
         self.value.clone()
     }
+
+    fn set_if(&mut self, expected: &HashPointer, value: &HashPointer) -> bool {
+        if self.value != *expected {
+            return false;
+        }
+        self.set(value);
+        true
+    }
+
+    fn history(&self) -> Vec<HashPointer> {
+        self.history.clone().unwrap_or_default()
+    }
 }
@@ -1,37 +1,71 @@
 use crate::{
-    content_store::ContentStore,
+    content_store::{ContentStore, ContentStoreError, ParsingContentStoreExt},
     counting_store::{CountingStore, HasReferences},
-    file_record::Record,
+    file_record::{DirectoryRecord, Record},
     hash_pointer::TypedHashPointer,
     inode::INode,
 };
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use bincode::{Decode, Encode};
 
-#[derive(Encode, Decode, Debug)]
+/// Maps every inode to the hash of its record.
+///
+/// The whole map is stored as one block and decoded in one go. `BridgeFS`
+/// keeps the current one decoded between operations, but a cold start or a
+/// root moved by another writer still decodes all of it; the map is not
+/// split into separately stored nodes that could be decoded one at a time.
+#[derive(Encode, Decode, Debug, Clone)]
 pub struct INodeIndex {
     next_inode: INode,
-    inode_mapping: HashMap<INode, TypedHashPointer<Record>>,
+    // Sorted, so that identical trees encode to identical index hashes
+    inode_mapping: BTreeMap<INode, TypedHashPointer<Record>>,
+    // Removed inodes available for reuse, with the generation they were last used at
+    freed_inodes: BTreeMap<INode, u64>,
 }
 
 impl INodeIndex {
     pub fn new(root_inode: INode, root: TypedHashPointer<Record>) -> Self {
-        let mut inode_mapping = HashMap::new();
+        let mut inode_mapping = BTreeMap::new();
         inode_mapping.insert(root_inode, root);
         Self {
-            next_inode: INode::default(),
+            next_inode: INode::FIRST_ALLOCATED,
             inode_mapping,
+            freed_inodes: BTreeMap::new(),
         }
     }
+
+    /// Adds an index holding nothing but an empty root directory at
+    /// `INode::ROOT` to `store`
+    pub fn add_empty(
+        store: &mut impl ContentStore,
+    ) -> Result<TypedHashPointer<INodeIndex>, ContentStoreError> {
+        // The root is its own parent, so `..` at the root stays there
+        let root_directory = DirectoryRecord {
+            parent: INode::ROOT,
+            ..DirectoryRecord::default()
+        };
+        let root_hash = store.add_parsed(&Record::Directory(root_directory))?;
+        store.add_parsed(&INodeIndex::new(INode::ROOT, root_hash))
+    }
 }
 
 impl INodeIndex {
-    pub fn insert_new_inode(&mut self, hash: TypedHashPointer<Record>) -> INode {
+    /// Picks an inode for a new record along with its generation, which is
+    /// bumped whenever a removed inode is handed out again
+    pub fn allocate_inode(&mut self) -> (INode, u64) {
+        if let Some((inode, generation)) = self.freed_inodes.pop_first() {
+            return (inode, generation + 1);
+        }
         let inode = self.next_inode;
-        self.inode_mapping.insert(inode, hash);
         self.next_inode = self.next_inode.next_inode();
-        inode
+        (inode, 0)
+    }
+
+    pub fn remove_inode(&mut self, inode: INode, generation: u64) {
+        if self.inode_mapping.remove(&inode).is_some() {
+            self.freed_inodes.insert(inode, generation);
+        }
     }
 
     pub fn update_inode(&mut self, inode: INode, hash: TypedHashPointer<Record>) {
@@ -41,10 +75,33 @@ impl INodeIndex {
     pub fn lookup_inode(&self, inode: &INode) -> Option<&TypedHashPointer<Record>> {
         self.inode_mapping.get(inode)
     }
+
+    pub fn records(&self) -> impl Iterator<Item = &TypedHashPointer<Record>> {
+        self.inode_mapping.values()
+    }
+
+    pub fn inodes(&self) -> impl Iterator<Item = &INode> {
+        self.inode_mapping.keys()
+    }
+
+    /// The root is created along with the index, below any inode
+    /// `allocate_inode` hands out, so it is always the lowest one held
+    pub fn root(&self) -> Option<INode> {
+        self.inode_mapping.keys().next().copied()
+    }
 }
 
 impl<StoreT: ContentStore> HasReferences<StoreT> for INodeIndex {
-    fn delete_references(&self, _new_value: Option<&Self>, _store: &mut CountingStore<StoreT>) {
+    // Nothing is released through the index, so replacing it should not
+    // decode the old one just to find that out
+    const HAS_REFERENCES: bool = false;
+
+    fn delete_references(
+        &self,
+        _new_value: Option<&Self>,
+        _store: &mut CountingStore<StoreT>,
+    ) -> Result<(), ContentStoreError> {
         // TODO: should we delete references?
+        Ok(())
     }
 }
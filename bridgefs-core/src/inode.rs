@@ -1,14 +1,21 @@
 use bincode::{Decode, Encode};
 
-#[derive(Encode, Decode, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Encode, Decode, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct INode(u64);
 
 impl INode {
-    pub fn new(inode: u64) -> Self {
+    /// Where a tree made by `BridgeFS::init_empty` keeps its root, the
+    /// number FUSE gives the root too
+    pub const ROOT: INode = INode(1);
+
+    /// The first inode a new index hands out, the one after the root
+    pub const FIRST_ALLOCATED: INode = INode(2);
+
+    pub const fn new(inode: u64) -> Self {
         INode(inode)
     }
 
-    pub fn get(&self) -> u64 {
+    pub const fn get(&self) -> u64 {
         self.0
     }
 
@@ -17,12 +24,6 @@ impl INode {
     }
 }
 
-impl Default for INode {
-    fn default() -> Self {
-        INode(2)
-    }
-}
-
 impl From<u64> for INode {
     fn from(inode: u64) -> Self {
         INode::new(inode)
@@ -0,0 +1,28 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// Lets another thread cancel the operation a `BridgeFS` is currently running.
+///
+/// Long-running operations check the handle before each store round-trip,
+/// and once more when done, and bail out with
+/// `FileOperationError::Interrupted` once it has been triggered.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.interrupted.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    fmt::Debug,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bincode::{Decode, Encode};
+
+use crate::{
+    bridgefs::{CloneTimes, RenameMode},
+    file_record::{CommonAttrs, DefaultAttrs},
+    filename::Filename,
+    hash_pointer::HashPointer,
+    inode::INode,
+};
+
+/// A mutation as it was requested, so it can be replayed after a crash
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    CreateFile {
+        parent: INode,
+        name: Filename,
+        attributes: CommonAttrs,
+    },
+    CreateUnnamed {
+        parent: INode,
+        attributes: CommonAttrs,
+    },
+    RemoveUnlinked {
+        inode: INode,
+    },
+    CreateDirectory {
+        parent: INode,
+        name: Filename,
+        attributes: CommonAttrs,
+    },
+    Write {
+        inode: INode,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Truncate {
+        inode: INode,
+        size: u64,
+    },
+    RemoveFile {
+        parent: INode,
+        name: Filename,
+    },
+    RemoveDirectory {
+        parent: INode,
+        name: Filename,
+    },
+    RemoveTree {
+        parent: INode,
+        name: Filename,
+    },
+    Link {
+        inode: INode,
+        new_parent: INode,
+        new_name: Filename,
+    },
+    Rename {
+        parent: INode,
+        name: Filename,
+        new_parent: INode,
+        new_name: Filename,
+        mode: RenameMode,
+    },
+    CloneFile {
+        parent: INode,
+        name: Filename,
+        new_name: Filename,
+        times: CloneTimes,
+    },
+    UpdateAttributes {
+        inode: INode,
+        attributes: CommonAttrs,
+        /// Whether the birth time in `attributes` applies too
+        replace_crtime: bool,
+    },
+    SetDefaultAttrs {
+        inode: INode,
+        default_attrs: Option<DefaultAttrs>,
+    },
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub operation: Operation,
+    /// The root index the operation was about to commit
+    pub target: HashPointer,
+}
+
+/// Durable record of operations that have not been committed yet
+pub trait Journal: Debug {
+    fn append(&mut self, entry: &JournalEntry);
+
+    fn pending(&self) -> Vec<JournalEntry>;
+
+    fn clear(&mut self);
+}
+
+/// Keeps entries in memory; clones share the same entries
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryJournal {
+    entries: Arc<Mutex<Vec<JournalEntry>>>,
+}
+
+impl Journal for InMemoryJournal {
+    fn append(&mut self, entry: &JournalEntry) {
+        self.entries.lock().unwrap().push(entry.clone());
+    }
+
+    fn pending(&self) -> Vec<JournalEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    fn clear(&mut self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Keeps entries in a local file so they survive the process
+#[derive(Debug)]
+pub struct FileJournal {
+    path: PathBuf,
+}
+
+impl FileJournal {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl Journal for FileJournal {
+    fn append(&mut self, entry: &JournalEntry) {
+        let mut entries = self.pending();
+        entries.push(entry.clone());
+        let bytes = bincode::encode_to_vec(&entries, bincode::config::standard()).unwrap();
+        // Write then rename so a crash mid-write never leaves a torn journal
+        let temporary_path = self.path.with_extension("tmp");
+        fs::write(&temporary_path, bytes).expect("Failed to write journal");
+        fs::rename(&temporary_path, &self.path).expect("Failed to write journal");
+    }
+
+    fn pending(&self) -> Vec<JournalEntry> {
+        match fs::read(&self.path) {
+            Ok(bytes) => {
+                bincode::decode_from_slice(&bytes, bincode::config::standard())
+                    .expect("Journal is corrupt")
+                    .0
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => panic!("Failed to read journal: {e}"),
+        }
+    }
+
+    fn clear(&mut self) {
+        match fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => panic!("Failed to clear journal: {e}"),
+        }
+    }
+}
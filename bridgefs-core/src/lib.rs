@@ -1,11 +1,20 @@
+pub mod block_cache;
 pub mod bridgefs;
+pub mod checkpoint_log;
+pub mod compression;
 pub mod content_store;
 mod counting_store;
 pub mod data_block;
+pub mod file_reader;
 pub mod file_record;
+pub mod file_writer;
 pub mod filename;
 pub mod hash_pointer;
 pub mod index;
 pub mod inode;
+pub mod interrupt;
+pub mod journal;
 pub mod manifest;
+pub mod merkle_proof;
 pub mod response;
+pub mod snapshot;
@@ -2,6 +2,17 @@ use std::collections::{BTreeMap, BTreeSet, btree_map::Entry};
 
 use crate::hash_pointer::HashPointer;
 
+/// When reference counts in the manifest are kept up to date
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceCounting {
+    /// Every mutation adjusts the counts, so unreferenced content is known immediately
+    #[default]
+    Eager,
+    /// Mutations skip all bookkeeping, leaving garbage for an offline
+    /// mark-and-sweep. Stores never evict anything in this mode.
+    Deferred,
+}
+
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct Manifest {
     // How many references to each hash pointer
@@ -36,7 +47,66 @@ impl Manifest {
         }
     }
 
+    /// Adds every reference held by `other` to this manifest
+    pub fn extend(&mut self, other: &Manifest) {
+        for (reference, count) in &other.references {
+            *self.references.entry(reference.clone()).or_insert(0) += count;
+        }
+    }
+
     pub fn has_reference(&self, reference: &HashPointer) -> bool {
         self.references.contains_key(reference)
     }
+
+    pub fn reference_counts(&self) -> impl Iterator<Item = (&HashPointer, usize)> {
+        self.references
+            .iter()
+            .map(|(reference, count)| (reference, *count))
+    }
+
+    /// Every hash whose count here differs from its count in `actual`
+    pub fn discrepancies(&self, actual: &Manifest) -> Vec<ManifestDiscrepancy> {
+        let hashes: BTreeSet<_> = self
+            .references()
+            .union(&actual.references())
+            .copied()
+            .collect();
+        hashes
+            .into_iter()
+            .filter_map(|hash| {
+                let recorded = self.references.get(hash).copied().unwrap_or(0);
+                let actual = actual.references.get(hash).copied().unwrap_or(0);
+                (recorded != actual).then(|| ManifestDiscrepancy {
+                    hash: hash.clone(),
+                    recorded,
+                    actual,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A hash the manifest counts differently from the references that really
+/// exist. Counting too many leaks the content, while counting too few lets
+/// it be evicted while still in use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestDiscrepancy {
+    pub hash: HashPointer,
+    /// The count in the manifest
+    pub recorded: usize,
+    /// The count walking the tree gives
+    pub actual: usize,
+}
+
+/// How much the reference counts say deduplication is saving
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreReport {
+    /// Distinct blocks with at least one reference
+    pub referenced_blocks: usize,
+    /// Sum of every block's reference count
+    pub total_references: usize,
+    /// The block with the highest reference count, along with that count
+    pub most_referenced: Option<(HashPointer, usize)>,
+    /// Bytes that would be stored again if each reference had its own copy
+    pub dedup_savings: usize,
 }
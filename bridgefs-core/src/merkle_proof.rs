@@ -0,0 +1,61 @@
+use bincode::{Decode, Encode};
+
+use crate::{
+    file_record::Record,
+    filename::Filename,
+    hash_pointer::{Blake3, HashPointer, Hasher, TypedHashPointer},
+    index::INodeIndex,
+    inode::INode,
+};
+
+/// Everything needed to show that the record at `path` belongs to a root
+/// index, without access to the rest of the tree.
+///
+/// Verifying assumes the store addresses content by its blake3 hash.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct MerkleProof {
+    pub root_inode: INode,
+    pub path: Vec<Filename>,
+    /// The encoded root index
+    pub index: Vec<u8>,
+    /// The encoded record of each directory along `path`, then the target's
+    pub records: Vec<Vec<u8>>,
+}
+
+impl MerkleProof {
+    /// The record at the end of `path`, or `None` if any step does not hash
+    /// to what the step above it refers to
+    pub fn verify(&self, root: &TypedHashPointer<INodeIndex>) -> Option<Record> {
+        if hash(&self.index) != HashPointer::from(root) || self.records.len() != self.path.len() + 1
+        {
+            return None;
+        }
+        let index: INodeIndex = decode(&self.index)?;
+        let mut inode = self.root_inode;
+        let mut names = self.path.iter();
+        for bytes in &self.records {
+            if hash(bytes) != HashPointer::from(index.lookup_inode(&inode)?) {
+                return None;
+            }
+            let record: Record = decode(bytes)?;
+            let Some(name) = names.next() else {
+                return Some(record);
+            };
+            let Record::Directory(directory) = record else {
+                return None;
+            };
+            inode = *directory.get(name)?;
+        }
+        None
+    }
+}
+
+fn hash(bytes: &[u8]) -> HashPointer {
+    Blake3::hash(bytes)
+}
+
+fn decode<T: Decode<()>>(bytes: &[u8]) -> Option<T> {
+    bincode::decode_from_slice(bytes, bincode::config::standard())
+        .ok()
+        .map(|(value, _)| value)
+}
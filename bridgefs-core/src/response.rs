@@ -1,4 +1,7 @@
+use std::{error::Error, fmt::Display, path::PathBuf};
+
 use crate::{
+    content_store::{ContentStoreError, ParseError},
     data_block::DataBlock,
     file_record::{DirectoryRecord, FileRecord, Record},
     filename::Filename,
@@ -48,13 +51,148 @@ impl<T: Clone, U> Clone for INodeResponse<T, U> {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileOperationError {
     NotFound,
     NotADirectory,
     IsADirectory,
     DirectoryNotEmpty,
     AlreadyExists,
+    Interrupted,
+    InvalidName,
+    InvalidArgument,
+    NoSpace,
+    ReadOnly,
+    NotPermitted,
+    /// Stored content is missing or cannot be decoded
+    Corrupt,
+    FileTooLarge,
+    /// The store's backend could not be reached or failed the call
+    Unavailable,
+}
+
+impl FileOperationError {
+    pub fn with_inode(self, inode: INode) -> FSError {
+        FSError::from(self).with_inode(inode)
+    }
+
+    pub fn with_name(self, name: &Filename) -> FSError {
+        FSError::from(self).with_name(name)
+    }
+}
+
+impl Display for FileOperationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            FileOperationError::NotFound => "not found",
+            FileOperationError::NotADirectory => "not a directory",
+            FileOperationError::IsADirectory => "is a directory",
+            FileOperationError::DirectoryNotEmpty => "directory not empty",
+            FileOperationError::AlreadyExists => "already exists",
+            FileOperationError::Interrupted => "interrupted",
+            FileOperationError::InvalidName => "invalid name",
+            FileOperationError::InvalidArgument => "invalid argument",
+            FileOperationError::NoSpace => "no space left",
+            FileOperationError::ReadOnly => "read-only filesystem",
+            FileOperationError::NotPermitted => "operation not permitted",
+            FileOperationError::Corrupt => "stored content is missing or corrupt",
+            FileOperationError::FileTooLarge => "file too large",
+            FileOperationError::Unavailable => "storage backend unavailable",
+        };
+        f.write_str(description)
+    }
+}
+
+impl Error for FileOperationError {}
+
+/// The inode and/or name an operation was acting on when it failed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub inode: Option<INode>,
+    pub name: Option<Filename>,
+    /// What went wrong underneath, such as why stored content failed to decode
+    pub detail: Option<String>,
+}
+
+/// A `FileOperationError` along with the context it was raised in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FSError {
+    pub kind: FileOperationError,
+    pub context: ErrorContext,
+}
+
+impl FSError {
+    pub fn with_inode(mut self, inode: INode) -> Self {
+        self.context.inode = Some(inode);
+        self
+    }
+
+    pub fn with_name(mut self, name: &Filename) -> Self {
+        self.context.name = Some(name.clone());
+        self
+    }
+}
+
+impl From<FileOperationError> for FSError {
+    fn from(kind: FileOperationError) -> Self {
+        FSError {
+            kind,
+            context: ErrorContext::default(),
+        }
+    }
+}
+
+impl From<ContentStoreError> for FSError {
+    fn from(error: ContentStoreError) -> Self {
+        match error {
+            ContentStoreError::CapacityExceeded => FileOperationError::NoSpace.into(),
+            ContentStoreError::ReadOnly => FileOperationError::ReadOnly.into(),
+            ContentStoreError::Unavailable { .. } => FileOperationError::Unavailable.into(),
+            ContentStoreError::Corrupt => FileOperationError::Corrupt.into(),
+        }
+    }
+}
+
+impl From<ParseError> for FSError {
+    fn from(error: ParseError) -> Self {
+        FSError {
+            kind: FileOperationError::Corrupt,
+            context: ErrorContext {
+                detail: Some(error.to_string()),
+                ..ErrorContext::default()
+            },
+        }
+    }
+}
+
+impl PartialEq<FileOperationError> for FSError {
+    fn eq(&self, other: &FileOperationError) -> bool {
+        self.kind == *other
+    }
+}
+
+impl Display for FSError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        match (&self.context.name, self.context.inode) {
+            (Some(name), Some(inode)) => {
+                write!(f, " (name {:?} in inode {})", name.to_string(), inode.get())
+            }
+            (Some(name), None) => write!(f, " (name {:?})", name.to_string()),
+            (None, Some(inode)) => write!(f, " (inode {})", inode.get()),
+            (None, None) => Ok(()),
+        }?;
+        match &self.context.detail {
+            Some(detail) => write!(f, ": {detail}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for FSError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.kind)
+    }
 }
 
 #[derive(Debug)]
@@ -69,6 +207,14 @@ pub struct ListDirectoryEntry {
     pub record: INodeResponse<Record, Record>,
 }
 
+/// One entry visited by `BridgeFS::walk`
+#[derive(Debug)]
+pub struct WalkEntry {
+    /// From the root, which is `/` itself
+    pub path: PathBuf,
+    pub record: INodeResponse<Record, Record>,
+}
+
 #[derive(Debug)]
 pub struct ListDirectoryResponse {
     pub directory: INodeResponse<DirectoryRecord, Record>,
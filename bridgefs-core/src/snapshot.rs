@@ -0,0 +1,20 @@
+use crate::{hash_pointer::TypedHashPointer, index::INodeIndex};
+
+/// A read-only view of the filesystem pinned to a single root index.
+///
+/// Writes produce a new index rather than mutating the old one, so reads
+/// through a snapshot keep seeing the tree as it was when it was taken.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    index_hash: TypedHashPointer<INodeIndex>,
+}
+
+impl Snapshot {
+    pub fn new(index_hash: TypedHashPointer<INodeIndex>) -> Self {
+        Self { index_hash }
+    }
+
+    pub fn index_hash(&self) -> &TypedHashPointer<INodeIndex> {
+        &self.index_hash
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+};
+
+use bridgefs_core::{
+    filename::{Filename, FilenameEncoding},
+    response::FileOperationError,
+};
+
+#[test]
+fn test_utf8_name_round_trips() {
+    let original = OsStr::new("résumé-日本.txt");
+    let filename = FilenameEncoding::Utf8.encode(original);
+    assert!(filename.is_ok());
+    let filename = filename.unwrap();
+    assert_eq!(filename, Filename::from("résumé-日本.txt"));
+    let round_tripped: OsString = filename.into();
+    assert_eq!(round_tripped, original);
+}
+
+#[test]
+fn test_utf8_mode_rejects_invalid_bytes() {
+    let invalid = OsStr::from_bytes(b"bad\xffname");
+    let result = FilenameEncoding::Utf8.encode(invalid);
+    assert_eq!(result, Err(FileOperationError::InvalidName));
+}
+
+#[test]
+fn test_raw_mode_keeps_invalid_bytes() {
+    let invalid = OsStr::from_bytes(b"bad\xffname");
+    let filename = FilenameEncoding::default().encode(invalid);
+    assert!(filename.is_ok());
+    assert_eq!(filename.unwrap().name, b"bad\xffname");
+}
+
+#[test]
+fn test_filenames_sort_byte_wise() {
+    let mut filenames: Vec<Filename> = vec![
+        "b".into(),
+        "a".into(),
+        "B".into(),
+        "ab".into(),
+        "é".into(),
+        "".into(),
+    ];
+    filenames.sort();
+    let sorted: Vec<&[u8]> = filenames.iter().map(|f| f.name.as_slice()).collect();
+    assert_eq!(
+        sorted,
+        vec![b"".as_slice(), b"B", b"a", b"ab", b"b", "é".as_bytes()]
+    );
+}
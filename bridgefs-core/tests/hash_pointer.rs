@@ -0,0 +1,56 @@
+use bridgefs_core::hash_pointer::{
+    Blake3, Blake3Xof, HashPointer, HashPointerReference, Hasher, InMemoryHashPointerReference,
+};
+
+#[test]
+fn test_in_memory_reference_tracks_history() {
+    let values: Vec<HashPointer> = (0..4u8)
+        .map(|i| HashPointer::from(blake3::hash(&[i])))
+        .collect();
+
+    let mut reference = InMemoryHashPointerReference::new(values[0].clone()).track_history();
+    reference.set(&values[1]);
+    reference.set(&values[2]);
+    assert!(reference.set_if(&values[2], &values[3]));
+    assert!(!reference.set_if(&values[0], &values[1]));
+
+    assert_eq!(reference.history(), values);
+}
+
+#[test]
+fn test_in_memory_reference_history_is_opt_in() {
+    let value = HashPointer::from(blake3::hash(b"root"));
+    let mut reference = InMemoryHashPointerReference::new(value.clone());
+    reference.set(&value);
+    assert!(reference.history().is_empty());
+}
+
+#[test]
+fn test_hash_pointer_parses_its_display() {
+    let value = HashPointer::from(blake3::hash(b"root"));
+    assert_eq!(value.to_string().parse::<HashPointer>(), Ok(value));
+    assert!("c2hvcnQ=".parse::<HashPointer>().is_err());
+}
+
+#[test]
+fn test_digest_lengths_coexist() {
+    let native = Blake3::hash(b"content");
+    let short = Blake3Xof::<20>::hash(b"content");
+    assert_eq!(native.len(), Blake3::DIGEST_LEN);
+    assert_eq!(short.len(), 20);
+    // The same content under another length is another pointer
+    assert_ne!(native, short);
+    assert_eq!(HashPointer::from(blake3::hash(b"content")), native);
+    assert!(blake3::Hash::try_from(&short).is_err());
+
+    for pointer in [native, short] {
+        assert_eq!(
+            pointer.to_string().parse::<HashPointer>(),
+            Ok(pointer.clone())
+        );
+        let encoded = bincode::encode_to_vec(&pointer, bincode::config::standard()).unwrap();
+        let (decoded, _): (HashPointer, _) =
+            bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+        assert_eq!(decoded, pointer);
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use bridgefs_core::{
+    bridgefs::BridgeFS, content_store::InMemoryContentStore, file_record::CommonAttrs,
+    hash_pointer::InMemoryHashPointerReference, inode::INode, manifest::ReferenceCounting,
+    response::FileOperationError,
+};
+
+#[test]
+fn test_init_empty_bootstraps_a_usable_tree() {
+    let (index_hash, store) =
+        BridgeFS::init_empty(InMemoryContentStore::default()).expect("Failed to init tree");
+    let pointer = InMemoryHashPointerReference::new(index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+
+    assert_eq!(bridgefs.inode_count(), Ok(1));
+    let root = bridgefs
+        .list_directory_by_inode(INode::ROOT)
+        .expect("Root should be a directory");
+    // Nothing but `.` and `..`, both the root itself
+    assert_eq!(root.entries.len(), 2);
+    assert!(
+        root.entries
+            .iter()
+            .all(|entry| entry.record.inode == INode::ROOT)
+    );
+
+    let file = bridgefs
+        .create_file(INode::ROOT, "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(file.inode, 0, b"hello")
+        .expect("Failed to write data");
+    assert_eq!(bridgefs.read_entire_file(file.inode).unwrap(), b"hello");
+}
+
+#[test]
+fn test_root_inode_is_one_and_allocation_starts_after_it() {
+    assert_eq!(INode::ROOT.get(), 1);
+    let (index_hash, store) =
+        BridgeFS::init_empty(InMemoryContentStore::default()).expect("Failed to init tree");
+    let pointer = InMemoryHashPointerReference::new(index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+
+    let root_parent = bridgefs
+        .lookup_parent_by_inode(INode::ROOT)
+        .expect("Root should have a parent")
+        .inode;
+    assert_eq!(root_parent, INode::ROOT);
+    let child = bridgefs
+        .create_directory(INode::ROOT, "child".into(), CommonAttrs::directory())
+        .expect("Failed to create directory");
+    assert!(child.inode > INode::ROOT);
+    assert_ne!(child.inode, root_parent);
+    assert_eq!(child.inner.parent, INode::ROOT);
+}
+
+#[test]
+fn test_from_entries_builds_nested_directories() {
+    let mut bridgefs = BridgeFS::from_entries([
+        (PathBuf::from("top.txt"), b"top".to_vec()),
+        (PathBuf::from("a/b/deep.txt"), b"deep".to_vec()),
+        (PathBuf::from("a/sibling.txt"), Vec::new()),
+    ])
+    .expect("Failed to build tree");
+
+    let top = bridgefs
+        .lookup_record_by_name(INode::ROOT, &"top.txt".into())
+        .unwrap();
+    assert_eq!(bridgefs.read_entire_file(top.inode).unwrap(), b"top");
+    let a = bridgefs
+        .lookup_record_by_name(INode::ROOT, &"a".into())
+        .unwrap();
+    assert_eq!(
+        bridgefs.directory_entry_names(a.inode).unwrap(),
+        vec!["b".into(), "sibling.txt".into()]
+    );
+    let b = bridgefs
+        .lookup_record_by_name(a.inode, &"b".into())
+        .unwrap();
+    let deep = bridgefs
+        .lookup_record_by_name(b.inode, &"deep.txt".into())
+        .unwrap();
+    assert_eq!(bridgefs.read_entire_file(deep.inode).unwrap(), b"deep");
+    assert_eq!(
+        bridgefs.path_of(deep.inode).unwrap(),
+        PathBuf::from("/a/b/deep.txt")
+    );
+
+    let escaping = BridgeFS::from_entries([(PathBuf::from("../outside"), Vec::new())]);
+    assert!(escaping.is_err_and(|e| e == FileOperationError::InvalidArgument));
+}
@@ -0,0 +1,27 @@
+use bridgefs_core::{
+    filename::Filename,
+    hash_pointer::HashPointer,
+    inode::INode,
+    journal::{FileJournal, Journal, JournalEntry, Operation},
+};
+
+#[test]
+fn test_file_journal_survives_reopening() {
+    let path = std::env::temp_dir().join(format!("bridgefs-journal-{}", std::process::id()));
+    let entry = JournalEntry {
+        operation: Operation::RemoveFile {
+            parent: INode::new(1),
+            name: Filename::from("file"),
+        },
+        target: HashPointer::from(blake3::hash(b"target")),
+    };
+
+    let mut journal = FileJournal::new(&path);
+    assert!(journal.pending().is_empty());
+    journal.append(&entry);
+
+    let mut reopened = FileJournal::new(&path);
+    assert_eq!(reopened.pending(), vec![entry]);
+    reopened.clear();
+    assert!(FileJournal::new(&path).pending().is_empty());
+}
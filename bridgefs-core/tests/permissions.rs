@@ -0,0 +1,85 @@
+use bridgefs_core::file_record::CommonAttrs;
+
+static OWNER: u32 = 1000;
+static GROUP: u32 = 100;
+static OTHER_UID: u32 = 2000;
+static OTHER_GID: u32 = 200;
+
+fn attrs_with_perm(perm: u16) -> CommonAttrs {
+    CommonAttrs::builder()
+        .perm(perm)
+        .uid(OWNER)
+        .gid(GROUP)
+        .build()
+}
+
+#[test]
+fn test_is_executable() {
+    let cases = [
+        (0o644, false),
+        (0o744, true),
+        (0o654, true),
+        (0o645, true),
+        (0o755, true),
+        (0o4644, false),
+    ];
+    for (perm, expected) in cases {
+        assert_eq!(attrs_with_perm(perm).is_executable(), expected, "{perm:o}");
+    }
+}
+
+#[test]
+fn test_permission_matrix() {
+    // (perm, uid, gid, readable, writable, executable)
+    let cases = [
+        (0o640, OWNER, GROUP, true, true, false),
+        (0o640, OTHER_UID, GROUP, true, false, false),
+        (0o640, OTHER_UID, OTHER_GID, false, false, false),
+        (0o755, OWNER, OTHER_GID, true, true, true),
+        (0o755, OTHER_UID, OTHER_GID, true, false, true),
+        // The owner class applies even when it is more restrictive
+        (0o077, OWNER, GROUP, false, false, false),
+        (0o077, OTHER_UID, GROUP, true, true, true),
+        (0o701, OTHER_UID, GROUP, false, false, false),
+        (0o701, OTHER_UID, OTHER_GID, false, false, true),
+        // Root bypasses read and write checks but needs some execute bit
+        (0o000, 0, 0, true, true, false),
+        (0o001, 0, 0, true, true, true),
+    ];
+    for (perm, uid, gid, readable, writable, executable) in cases {
+        let attrs = attrs_with_perm(perm);
+        let case = format!("perm {perm:o} uid {uid} gid {gid}");
+        assert_eq!(attrs.is_readable_by(uid, gid), readable, "{case}");
+        assert_eq!(attrs.is_writable_by(uid, gid), writable, "{case}");
+        assert_eq!(attrs.is_executable_by(uid, gid), executable, "{case}");
+    }
+}
+
+#[test]
+fn test_sticky_directory_removal() {
+    let entry = attrs_with_perm(0o644);
+    let mut others_entry = entry.clone();
+    others_entry.uid = OTHER_UID;
+
+    // (directory perm, entry, uid, allowed)
+    let cases = [
+        (0o777, &others_entry, 3000, true),
+        (0o1777, &others_entry, 3000, false),
+        (0o1777, &others_entry, OTHER_UID, true),
+        (0o1777, &entry, OWNER, true),
+        (0o1777, &others_entry, OWNER, true),
+        (0o1777, &others_entry, 0, true),
+    ];
+    for (perm, entry, uid, allowed) in cases {
+        let directory = attrs_with_perm(perm);
+        let case = format!("perm {perm:o} entry uid {} uid {uid}", entry.uid);
+        assert_eq!(directory.allows_removal_by(entry, uid), allowed, "{case}");
+    }
+}
+
+#[test]
+fn test_clear_setid() {
+    let mut attrs = attrs_with_perm(0o7755);
+    attrs.clear_setid();
+    assert_eq!(attrs.perm, 0o1755);
+}
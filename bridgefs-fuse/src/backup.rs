@@ -0,0 +1,143 @@
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use bridgefs_core::{
+    bridgefs::BridgeFS,
+    content_store::{ContentStore, ContentStoreError, ParsingContentStoreExt},
+    data_block::DataBlock,
+    hash_pointer::{HashPointer, TypedHashPointer, TypedHashPointerReference},
+    index::INodeIndex,
+};
+use tar::{Archive, Builder, EntryType, Header};
+
+/// Archive entry holding the root index hash, in its `Display` form
+const ROOT_ENTRY: &str = "root";
+/// Directory the blocks are archived under, numbered in the order written
+const BLOCKS_DIR: &str = "blocks";
+
+/// Why a backup could not be restored
+#[derive(Debug)]
+pub enum RestoreError {
+    /// The archive itself could not be read
+    Archive(std::io::Error),
+    /// The archive has no root, or a root that does not parse
+    MissingRoot,
+    /// The archive names a root whose index it does not hold
+    Incomplete(HashPointer),
+    /// A block could not be added to the store
+    Store(ContentStoreError),
+}
+
+impl Display for RestoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestoreError::Archive(e) => write!(f, "failed to read archive: {e}"),
+            RestoreError::MissingRoot => f.write_str("archive has no root"),
+            RestoreError::Incomplete(root) => write!(f, "archive is missing its root {root}"),
+            RestoreError::Store(e) => write!(f, "failed to store block: {e}"),
+        }
+    }
+}
+
+impl Error for RestoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RestoreError::Archive(e) => Some(e),
+            RestoreError::Store(e) => Some(e),
+            RestoreError::MissingRoot | RestoreError::Incomplete(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RestoreError {
+    fn from(error: std::io::Error) -> Self {
+        RestoreError::Archive(error)
+    }
+}
+
+impl From<ContentStoreError> for RestoreError {
+    fn from(error: ContentStoreError) -> Self {
+        RestoreError::Store(error)
+    }
+}
+
+/// Writes a snapshot of the current tree to `out` as a tar archive holding
+/// its root and every block reachable from it, each once, and nothing
+/// else. Returns the root that was backed up.
+///
+/// Blocks are archived exactly as stored, so `restore` rebuilds the same
+/// root rather than an equivalent tree.
+pub fn backup<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>(
+    bridgefs: &mut BridgeFS<IndexHashT, StoreT>,
+    out: impl Write,
+) -> std::io::Result<TypedHashPointer<INodeIndex>> {
+    let root = bridgefs.snapshot().index_hash().clone();
+    let mut builder = Builder::new(out);
+    append(
+        &mut builder,
+        ROOT_ENTRY,
+        HashPointer::from(&root).to_string().as_bytes(),
+    )?;
+    let blocks = bridgefs
+        .missing_blocks(&BTreeSet::new())
+        .map_err(std::io::Error::other)?;
+    for (i, hash) in blocks.iter().enumerate() {
+        let block = bridgefs.read_block(hash);
+        append(&mut builder, &format!("{BLOCKS_DIR}/{i}"), &block)?;
+    }
+    builder.into_inner()?.flush()?;
+    Ok(root)
+}
+
+fn append(builder: &mut Builder<impl Write>, path: &str, data: &[u8]) -> std::io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    builder.append_data(&mut header, path, data)
+}
+
+/// Adds every block in a `backup` archive to `store`, returning the root
+/// to point a filesystem at
+pub fn restore(
+    archive: impl Read,
+    store: &mut impl ContentStore,
+) -> Result<TypedHashPointer<INodeIndex>, RestoreError> {
+    let mut root = None;
+    let mut restored = BTreeSet::new();
+    for entry in Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        if entry.path()?.as_os_str() == ROOT_ENTRY {
+            let text = String::from_utf8(data).map_err(|_| RestoreError::MissingRoot)?;
+            root = Some(
+                text.parse::<HashPointer>()
+                    .map_err(|_| RestoreError::MissingRoot)?,
+            );
+        } else {
+            let hash = match compressed_block_hash(store, &data) {
+                Some(hash) if store.add_content_under(&hash, &data)? => hash,
+                _ => store.add_content(&data)?,
+            };
+            restored.insert(hash);
+        }
+    }
+    let root = root.ok_or(RestoreError::MissingRoot)?;
+    if !restored.contains(&root) {
+        return Err(RestoreError::Incomplete(root));
+    }
+    Ok(TypedHashPointer::new(root))
+}
+
+/// The hash a compressed data block was stored under, that of the block it
+/// decompresses to, which unlike a plain block's is not the hash of `data`
+fn compressed_block_hash(store: &impl ContentStore, data: &[u8]) -> Option<HashPointer> {
+    let block = DataBlock::from_stored(data)?;
+    let hash: HashPointer = store.parsed_hash(&block)?.into();
+    (store.content_hash(data) != Some(hash.clone())).then_some(hash)
+}
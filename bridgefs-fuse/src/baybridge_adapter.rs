@@ -1,118 +1,531 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    sync::{
+        Mutex,
+        mpsc::{Receiver, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+
 use baybridge::{
     client::Actions,
     models::{ContentBlock, Name, Value},
 };
 use bridgefs_core::{
-    content_store::ContentStore,
+    content_store::{ContentStore, ContentStoreError},
     hash_pointer::{HashPointer, HashPointerReference, TypedHashPointer},
     index::INodeIndex,
 };
 
-pub struct BaybridgeAdapter {
+use tracing::warn;
+
+use crate::retry::{RetryPolicy, Transient};
+
+/// Name used for the root pointer when none is given
+pub const DEFAULT_FS_NAME: &str = "filesystem3";
+
+/// Wording of baybridge errors that repeating the call will not fix
+const PERMANENT_ERRORS: &[&str] = &[
+    "decode",
+    "deserialize",
+    "invalid",
+    "signature",
+    "unauthorized",
+    "forbidden",
+    "too large",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaybridgeError {
+    message: String,
+    transient: bool,
+    not_found: bool,
+}
+
+impl BaybridgeError {
+    /// A failure that may go away if the call is repeated, such as a
+    /// network error
+    pub fn new(error: impl Display) -> Self {
+        Self {
+            message: error.to_string(),
+            transient: true,
+            not_found: false,
+        }
+    }
+
+    /// A failure that repeating the call will not fix
+    pub fn permanent(error: impl Display) -> Self {
+        Self {
+            message: error.to_string(),
+            transient: false,
+            not_found: false,
+        }
+    }
+
+    /// The name or content asked for does not exist, as opposed to the
+    /// lookup itself failing
+    pub fn not_found(error: impl Display) -> Self {
+        Self {
+            not_found: true,
+            ..Self::permanent(error)
+        }
+    }
+
+    /// Sorts an error from the baybridge client by its message.
+    ///
+    /// TODO: baybridge reports every failure as a plain error, so this goes
+    /// by the wording until it has dedicated kinds. Anything unrecognized is
+    /// taken to be a network failure and retried, including a "not found"
+    /// that may be about a route or a peer rather than what was asked for.
+    pub fn classify(error: impl Display) -> Self {
+        let message = error.to_string().to_lowercase();
+        if PERMANENT_ERRORS
+            .iter()
+            .any(|permanent| message.contains(permanent))
+        {
+            Self::permanent(error)
+        } else {
+            Self::new(error)
+        }
+    }
+
+    /// Like `classify`, for a lookup of a name, where "not found" means
+    /// the name has never been set
+    pub fn classify_lookup(error: impl Display) -> Self {
+        if error.to_string().to_lowercase().contains("not found") {
+            Self::not_found(error)
+        } else {
+            Self::classify(error)
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        self.transient
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.not_found
+    }
+}
+
+impl Display for BaybridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for BaybridgeError {}
+
+impl Transient for BaybridgeError {
+    fn is_transient(&self) -> bool {
+        BaybridgeError::is_transient(self)
+    }
+}
+
+impl From<BaybridgeError> for ContentStoreError {
+    fn from(error: BaybridgeError) -> Self {
+        ContentStoreError::Unavailable {
+            transient: error.is_transient(),
+        }
+    }
+}
+
+/// The subset of baybridge operations bridgefs relies on
+pub trait BaybridgeActions {
+    fn set_immutable(
+        &self,
+        content_block: ContentBlock,
+    ) -> impl Future<Output = Result<HashPointer, BaybridgeError>>;
+
+    fn get_immutable(
+        &self,
+        hash: &HashPointer,
+    ) -> impl Future<Output = Result<ContentBlock, BaybridgeError>>;
+
+    fn set(&self, name: &Name, value: Value) -> impl Future<Output = Result<(), BaybridgeError>>;
+
+    fn get_mine(&self, name: &Name) -> impl Future<Output = Result<Value, BaybridgeError>>;
+
+    /// Whether `set` and `set_immutable` can succeed, i.e. a signing key is
+    /// available
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+impl BaybridgeActions for Actions {
+    async fn set_immutable(
+        &self,
+        content_block: ContentBlock,
+    ) -> Result<HashPointer, BaybridgeError> {
+        Actions::set_immutable(self, content_block)
+            .await
+            .map(Into::into)
+            .map_err(BaybridgeError::classify)
+    }
+
+    async fn get_immutable(&self, hash: &HashPointer) -> Result<ContentBlock, BaybridgeError> {
+        // baybridge only addresses content by 32 byte blake3
+        let hash = hash.try_into().map_err(BaybridgeError::not_found)?;
+        Actions::get_immutable(self, &hash)
+            .await
+            .map_err(BaybridgeError::classify)
+    }
+
+    async fn set(&self, name: &Name, value: Value) -> Result<(), BaybridgeError> {
+        Actions::set(self)
+            .name(name.clone())
+            .value(value)
+            .call()
+            .await
+            .map_err(BaybridgeError::classify)
+    }
+
+    async fn get_mine(&self, name: &Name) -> Result<Value, BaybridgeError> {
+        Actions::get_mine(self, name)
+            .await
+            .map_err(BaybridgeError::classify_lookup)
+    }
+}
+
+/// Refuses every write, for identities without a signing key or mounts that
+/// should never modify the filesystem
+pub struct ReadOnlyActions<ActionsT: BaybridgeActions>(pub ActionsT);
+
+impl<ActionsT: BaybridgeActions> BaybridgeActions for ReadOnlyActions<ActionsT> {
+    async fn set_immutable(
+        &self,
+        _content_block: ContentBlock,
+    ) -> Result<HashPointer, BaybridgeError> {
+        Err(BaybridgeError::permanent("read-only"))
+    }
+
+    async fn get_immutable(&self, hash: &HashPointer) -> Result<ContentBlock, BaybridgeError> {
+        self.0.get_immutable(hash).await
+    }
+
+    async fn set(&self, _name: &Name, _value: Value) -> Result<(), BaybridgeError> {
+        Err(BaybridgeError::permanent("read-only"))
+    }
+
+    async fn get_mine(&self, name: &Name) -> Result<Value, BaybridgeError> {
+        self.0.get_mine(name).await
+    }
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+}
+
+type Clock = Box<dyn Fn() -> Instant + Send + Sync>;
+
+pub struct BaybridgeAdapter<ActionsT: BaybridgeActions = Actions> {
     runtime: tokio::runtime::Runtime,
-    actions: Actions,
+    actions: ActionsT,
+    retry_policy: RetryPolicy,
+    flush_interval: Option<Duration>,
+    clock: Clock,
+    /// Roots not yet published, by the name they are for, and since when
+    /// each has been waiting. Shared with `flush_until`.
+    pending: Mutex<HashMap<String, (HashPointer, Instant)>>,
+    /// Held for the whole of a flush, so two of them never land out of
+    /// order, while `pending` stays free for new roots to be held back
+    publishing: Mutex<()>,
 }
 
-impl BaybridgeAdapter {
-    pub fn new(actions: Actions) -> Self {
+impl<ActionsT: BaybridgeActions> BaybridgeAdapter<ActionsT> {
+    pub fn new(actions: ActionsT) -> Self {
         let runtime = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap();
-        Self { runtime, actions }
+        Self {
+            runtime,
+            actions,
+            retry_policy: RetryPolicy::default(),
+            flush_interval: None,
+            clock: Box::new(Instant::now),
+            pending: Mutex::new(HashMap::new()),
+            publishing: Mutex::new(()),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Holds each new root back and publishes it once it has been pending
+    /// for `interval`, instead of on every mutation. The check runs on every
+    /// read or write of the root, which is every filesystem call, and on
+    /// `flush_until`'s thread while the filesystem sits idle; `flush`
+    /// publishes straight away. At most `interval` is lost in a crash, and
+    /// other readers of the name see nothing new until then.
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    /// Measures the flush interval with `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: impl Fn() -> Instant + Send + Sync + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Publishes held-back roots as they fall due, even while nothing uses
+    /// the filesystem, until `stop` receives or its sender is dropped.
+    /// Blocks, so it belongs on its own thread beside the mount; without a
+    /// flush interval it returns straight away.
+    pub fn flush_until(&self, stop: &Receiver<()>) {
+        if self.flush_interval.is_none() {
+            return;
+        }
+        loop {
+            let wait = self.flush_due();
+            if stop.recv_timeout(wait) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+        }
+    }
+
+    /// Publishes every held-back root that has waited out the flush
+    /// interval, returning how long until the next one is due. A root that
+    /// fails to publish is held back again, to be retried on the next tick.
+    fn flush_due(&self) -> Duration {
+        let Some(interval) = self.flush_interval else {
+            return Duration::MAX;
+        };
+        let _publishing = self.publishing.lock().unwrap();
+        let now = (self.clock)();
+        let mut next = interval;
+        let mut due = Vec::new();
+        self.pending.lock().unwrap().retain(|name, (root, since)| {
+            let waited = now.duration_since(*since);
+            if waited >= interval {
+                due.push((name.clone(), root.clone(), *since));
+                return false;
+            }
+            next = next.min(interval - waited);
+            true
+        });
+        for (name, root, since) in due {
+            self.publish_or_hold(&name, root, since);
+        }
+        next
+    }
+
+    /// Holds `root` back for `name`, keeping the time the oldest
+    /// unpublished change under it started waiting
+    fn hold(&self, name: &str, root: &HashPointer) {
+        let mut pending = self.pending.lock().unwrap();
+        let since = pending
+            .get(name)
+            .map_or_else(|| (self.clock)(), |(_, since)| *since);
+        pending.insert(name.to_string(), (root.clone(), since));
+    }
+
+    /// Publishes the root held back for `name`, if there is one
+    fn flush_name(&self, name: &str) {
+        let _publishing = self.publishing.lock().unwrap();
+        let held = self.pending.lock().unwrap().remove(name);
+        if let Some((root, since)) = held {
+            self.publish_or_hold(name, root, since);
+        }
+    }
+
+    /// Publishes `root`, or on failure holds it back again for the next
+    /// flush. A newer root held back in the meantime wins, but keeps `since`
+    /// so it is not kept waiting any longer.
+    fn publish_or_hold(&self, name: &str, root: HashPointer, since: Instant) {
+        if let Err(error) = self.publish(name, &root) {
+            warn!(name, %error, "failed to publish the root, will retry");
+            self.pending
+                .lock()
+                .unwrap()
+                .entry(name.to_string())
+                .and_modify(|(_, held_since)| *held_since = since)
+                .or_insert((root, since));
+        }
+    }
+
+    fn pending_root(&self, name: &str) -> Option<HashPointer> {
+        let pending = self.pending.lock().unwrap();
+        pending.get(name).map(|(root, _)| root.clone())
+    }
+
+    fn publish(&self, name: &str, value: &HashPointer) -> Result<(), BaybridgeError> {
+        let name = Name::new(name.to_string());
+        let serialized_value = bincode::encode_to_vec(value, bincode::config::standard()).unwrap();
+
+        // TODO: find a way to get an increasing priority, probably need baybridge to support strong reads
+        self.run(|| {
+            let value = Value::new(serialized_value.clone());
+            self.actions.set(&name, value)
+        })
+    }
+
+    /// Runs a baybridge call to completion, retrying it on transient errors
+    fn run<T, F: Future<Output = Result<T, BaybridgeError>>>(
+        &self,
+        mut call: impl FnMut() -> F,
+    ) -> Result<T, BaybridgeError> {
+        self.retry_policy.retry(|| self.runtime.block_on(call()))
     }
 
-    pub fn content_store(&self) -> BaybridgeContentStore<'_> {
+    /// Points `to` at the root `from` holds, sharing all of its content,
+    /// including a root still held back for `from` by the flush interval.
+    /// baybridge cannot delete names, so `from` keeps its root too.
+    pub fn rename_filesystem(&self, from: &str, to: &str) -> Result<(), BaybridgeError> {
+        // Keeps a flush from taking the held-back root out of `pending`
+        // before it reaches the node while this looks for it
+        let _publishing = self.publishing.lock().unwrap();
+        if let Some(root) = self.pending_root(from) {
+            return self.publish(to, &root);
+        }
+        let from = Name::new(from.to_string());
+        let to = Name::new(to.to_string());
+        let root = self.run(|| self.actions.get_mine(&from))?;
+        self.run(|| self.actions.set(&to, root.clone()))
+    }
+
+    pub fn content_store(&self) -> BaybridgeContentStore<'_, ActionsT> {
         BaybridgeContentStore { adapter: self }
     }
 
+    /// Each name holds an independent root pointer, so one baybridge
+    /// identity can host several filesystems
     pub fn hash_pointer_reference(
         &self,
+        name: &str,
         default_value: TypedHashPointer<INodeIndex>,
-    ) -> BaybridgeHashPointerReference<'_> {
+    ) -> BaybridgeHashPointerReference<'_, ActionsT> {
         BaybridgeHashPointerReference {
-            name: Name::new("filesystem3".to_string()),
+            name: name.to_string(),
             default_value,
             adapter: self,
         }
     }
 }
 
-pub struct BaybridgeContentStore<'a> {
-    adapter: &'a BaybridgeAdapter,
+pub struct BaybridgeContentStore<'a, ActionsT: BaybridgeActions = Actions> {
+    adapter: &'a BaybridgeAdapter<ActionsT>,
 }
 
-pub struct BaybridgeHashPointerReference<'a> {
-    name: Name,
+pub struct BaybridgeHashPointerReference<'a, ActionsT: BaybridgeActions = Actions> {
+    name: String,
     default_value: TypedHashPointer<INodeIndex>,
-    adapter: &'a BaybridgeAdapter,
+    adapter: &'a BaybridgeAdapter<ActionsT>,
 }
 
-impl ContentStore for BaybridgeContentStore<'_> {
-    fn add_content(&mut self, content: &[u8]) -> HashPointer {
-        let content_block = ContentBlock {
-            data: content.to_vec(),
-            references: Vec::new(),
-        };
-        self.adapter
-            .runtime
-            .block_on(self.adapter.actions.set_immutable(content_block))
-            .unwrap()
-            .into()
+impl<ActionsT: BaybridgeActions> BaybridgeContentStore<'_, ActionsT> {
+    pub fn is_writable(&self) -> bool {
+        self.adapter.actions.is_writable()
+    }
+}
+
+impl<ActionsT: BaybridgeActions> ContentStore for BaybridgeContentStore<'_, ActionsT> {
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        if !self.is_writable() {
+            return Err(ContentStoreError::ReadOnly);
+        }
+        let hash = self.adapter.run(|| {
+            let content_block = ContentBlock {
+                data: content.to_vec(),
+                references: Vec::new(),
+            };
+            self.adapter.actions.set_immutable(content_block)
+        })?;
+        Ok(hash)
     }
 
     fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        self.try_get_content(hash)
+            .expect("Failed to fetch content from baybridge")
+    }
+
+    /// A block the node does not hold, such as a root whose index was never
+    /// uploaded, fails here rather than taking the mount down
+    fn try_get_content(&self, hash: &HashPointer) -> Result<Vec<u8>, ContentStoreError> {
         let content_block = self
             .adapter
-            .runtime
-            .block_on(self.adapter.actions.get_immutable(&hash.into()))
-            .unwrap();
-        content_block.data
+            .run(|| self.adapter.actions.get_immutable(hash))?;
+        Ok(content_block.data)
+    }
+
+    fn is_writable(&self) -> bool {
+        BaybridgeContentStore::is_writable(self)
     }
 }
 
-impl HashPointerReference for BaybridgeHashPointerReference<'_> {
+impl<ActionsT: BaybridgeActions> HashPointerReference
+    for BaybridgeHashPointerReference<'_, ActionsT>
+{
     fn set(&mut self, value: &HashPointer) {
-        let serialized_value = bincode::encode_to_vec(value, bincode::config::standard()).unwrap();
-        let value = Value::new(serialized_value);
+        if self.adapter.flush_interval.is_none() {
+            self.adapter
+                .publish(&self.name, value)
+                .expect("Failed to publish the root pointer");
+            return;
+        }
+        self.adapter.hold(&self.name, value);
+        self.adapter.flush_due();
+    }
 
-        // TODO: find a way to get an increasing priority, probably need baybridge to support strong reads
-        self.adapter
-            .runtime
-            .block_on(
-                self.adapter
-                    .actions
-                    .set()
-                    .name(self.name.clone())
-                    .value(value)
-                    .call(),
-            )
-            .unwrap()
+    fn set_if(&mut self, expected: &HashPointer, value: &HashPointer) -> bool {
+        // TODO: baybridge has no conditional write yet, so this is only a
+        // best-effort compare-and-swap: another writer can still slip in
+        // between this read and the set, and is then overwritten. A failed
+        // read is left for the retried mutation to report.
+        if self.try_get().ok().as_ref() != Some(expected) {
+            return false;
+        }
+        self.set(value);
+        true
+    }
+
+    fn flush(&mut self) {
+        self.adapter.flush_name(&self.name);
     }
 
+    // TODO: override `history` once baybridge can list earlier values of a name
+
     fn get(&mut self) -> HashPointer {
-        match self.get_internal() {
-            Some(hash_pointer) => hash_pointer,
-            None => {
-                let default_value = (&self.default_value).into();
-                self.set(&default_value);
-                default_value
-            }
+        self.try_get().expect("Failed to read the root pointer")
+    }
+
+    /// Only a name that does not exist yet is initialized to the default;
+    /// failing to read it is an error rather than hide the real tree behind
+    /// an empty one
+    fn try_get(&mut self) -> Result<HashPointer, ContentStoreError> {
+        self.adapter.flush_due();
+        if let Some(pending) = self.adapter.pending_root(&self.name) {
+            return Ok(pending);
+        }
+        if let Some(hash_pointer) = self.get_internal()? {
+            return Ok(hash_pointer);
         }
+        let default_value = (&self.default_value).into();
+        if self.adapter.actions.is_writable() {
+            self.adapter.publish(&self.name, &default_value)?;
+        }
+        Ok(default_value)
     }
 }
 
-impl BaybridgeHashPointerReference<'_> {
-    fn get_internal(&self) -> Option<HashPointer> {
-        let value = self
-            .adapter
-            .runtime
-            .block_on(self.adapter.actions.get_mine(&self.name))
-            .ok()?;
-        Some(
-            bincode::decode_from_slice(value.as_bytes(), bincode::config::standard())
-                .unwrap()
-                .0,
-        )
+impl<ActionsT: BaybridgeActions> BaybridgeHashPointerReference<'_, ActionsT> {
+    fn get_internal(&self) -> Result<Option<HashPointer>, BaybridgeError> {
+        let name = Name::new(self.name.clone());
+        let value = match self.adapter.run(|| self.adapter.actions.get_mine(&name)) {
+            Ok(value) => value,
+            Err(e) if e.is_not_found() => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (hash_pointer, _) =
+            bincode::decode_from_slice(value.as_bytes(), bincode::config::standard()).map_err(
+                |e| BaybridgeError::permanent(format!("root pointer does not decode: {e}")),
+            )?;
+        Ok(Some(hash_pointer))
     }
 }
@@ -1,31 +1,39 @@
+use std::time::SystemTime;
+
 use bridgefs_core::{
     file_record::{CommonAttrs, DirectoryRecord, FileRecord, Record},
     inode::INode,
-    response::{FileOperationError, INodeResponse},
+    response::{FSError, FileOperationError, INodeResponse},
 };
-use fuser::{FileAttr, FileType};
+use fuser::{FileAttr, FileType, TimeOrNow};
 use libc::c_int;
 
 pub trait FuseFileResponseExt {
-    fn attrs(&self) -> FileAttr;
+    fn attrs(&self, block_size: u32) -> FileAttr;
+    fn generation(&self) -> u64;
 }
 
 impl<T: FuseFileExt, U> FuseFileResponseExt for INodeResponse<T, U> {
-    fn attrs(&self) -> FileAttr {
-        self.inner.attrs(self.inode)
+    fn attrs(&self, block_size: u32) -> FileAttr {
+        self.inner.attrs(self.inode, block_size)
+    }
+
+    fn generation(&self) -> u64 {
+        self.inner.common_attrs().generation
     }
 }
 
 pub trait FuseFileExt {
-    fn attrs(&self, inode: INode) -> FileAttr;
+    fn attrs(&self, inode: INode, block_size: u32) -> FileAttr;
     fn file_type(&self) -> FileType;
+    fn common_attrs(&self) -> &CommonAttrs;
 }
 
 impl FuseFileExt for Record {
-    fn attrs(&self, inode: INode) -> FileAttr {
+    fn attrs(&self, inode: INode, block_size: u32) -> FileAttr {
         match self {
-            Record::File(file_record) => file_record.attrs(inode),
-            Record::Directory(directory_record) => directory_record.attrs(inode),
+            Record::File(file_record) => file_record.attrs(inode, block_size),
+            Record::Directory(directory_record) => directory_record.attrs(inode, block_size),
         }
     }
 
@@ -35,50 +43,153 @@ impl FuseFileExt for Record {
             Record::Directory(directory_record) => directory_record.file_type(),
         }
     }
+
+    fn common_attrs(&self) -> &CommonAttrs {
+        Record::common_attrs(self)
+    }
 }
 
 impl FuseFileExt for FileRecord {
-    fn attrs(&self, inode: INode) -> FileAttr {
-        to_file_attrs(&self.common_attrs, self.size, FileType::RegularFile, inode)
+    fn attrs(&self, inode: INode, block_size: u32) -> FileAttr {
+        self.common_attrs.to_file_attr(
+            inode,
+            FileType::RegularFile,
+            self.size,
+            self.nlink,
+            block_size,
+        )
     }
 
     fn file_type(&self) -> FileType {
         FileType::RegularFile
     }
+
+    fn common_attrs(&self) -> &CommonAttrs {
+        &self.common_attrs
+    }
 }
 
 impl FuseFileExt for DirectoryRecord {
-    fn attrs(&self, inode: INode) -> FileAttr {
-        to_file_attrs(
-            &self.common_attrs,
-            self.size() as u64,
-            FileType::Directory,
+    fn attrs(&self, inode: INode, block_size: u32) -> FileAttr {
+        self.common_attrs.to_file_attr(
             inode,
+            FileType::Directory,
+            self.size() as u64,
+            2,
+            block_size,
         )
     }
 
     fn file_type(&self) -> FileType {
         FileType::Directory
     }
+
+    fn common_attrs(&self) -> &CommonAttrs {
+        &self.common_attrs
+    }
 }
 
-fn to_file_attrs(common_attrs: &CommonAttrs, size: u64, kind: FileType, inode: INode) -> FileAttr {
-    FileAttr {
-        ino: inode.into(),
-        size,
-        blocks: 0,
-        atime: common_attrs.atime,
-        mtime: common_attrs.mtime,
-        ctime: common_attrs.ctime,
-        crtime: common_attrs.crtime,
-        kind,
-        perm: common_attrs.perm,
-        nlink: 2,
-        uid: common_attrs.uid, // Change to appropriate user ID
-        gid: common_attrs.gid, // Change to appropriate group ID
-        rdev: 0,
-        flags: 0,
-        blksize: 512,
+/// The changes a `setattr` call asks for; fields left as `None` are kept
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SetAttr {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub atime: Option<TimeOrNow>,
+    pub mtime: Option<TimeOrNow>,
+    pub ctime: Option<SystemTime>,
+    pub crtime: Option<SystemTime>,
+    pub flags: Option<u32>,
+}
+
+pub trait FuseAttrsExt {
+    /// Everything in a `FileAttr` except what depends on the kind of record
+    fn to_file_attr(
+        &self,
+        inode: INode,
+        kind: FileType,
+        size: u64,
+        nlink: u32,
+        block_size: u32,
+    ) -> FileAttr;
+    /// The attributes `attr` carries; the generation is not one of them
+    fn from_file_attr(attr: &FileAttr) -> Self;
+    fn apply_setattr(&mut self, changes: &SetAttr);
+}
+
+impl FuseAttrsExt for CommonAttrs {
+    fn to_file_attr(
+        &self,
+        inode: INode,
+        kind: FileType,
+        size: u64,
+        nlink: u32,
+        block_size: u32,
+    ) -> FileAttr {
+        FileAttr {
+            ino: inode.into(),
+            size,
+            blocks: size.div_ceil(block_size.into()),
+            atime: self.atime,
+            mtime: self.mtime,
+            ctime: self.ctime,
+            crtime: self.crtime,
+            kind,
+            perm: self.perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            flags: self.flags,
+            blksize: block_size,
+        }
+    }
+
+    fn from_file_attr(attr: &FileAttr) -> Self {
+        CommonAttrs::builder()
+            .perm(attr.perm)
+            .uid(attr.uid)
+            .gid(attr.gid)
+            .atime(attr.atime)
+            .mtime(attr.mtime)
+            .ctime(attr.ctime)
+            .crtime(attr.crtime)
+            .flags(attr.flags)
+            .build()
+    }
+
+    fn apply_setattr(&mut self, changes: &SetAttr) {
+        if let Some(mode) = changes.mode {
+            self.perm = mode as u16;
+        }
+        if let Some(uid) = changes.uid {
+            self.uid = uid;
+        }
+        if let Some(gid) = changes.gid {
+            self.gid = gid;
+        }
+        if let Some(atime) = changes.atime {
+            self.atime = resolve_time(atime);
+        }
+        if let Some(mtime) = changes.mtime {
+            self.mtime = resolve_time(mtime);
+        }
+        if let Some(ctime) = changes.ctime {
+            self.ctime = ctime;
+        }
+        if let Some(crtime) = changes.crtime {
+            self.crtime = crtime;
+        }
+        if let Some(flags) = changes.flags {
+            self.flags = flags;
+        }
+    }
+}
+
+fn resolve_time(time: TimeOrNow) -> SystemTime {
+    match time {
+        TimeOrNow::SpecificTime(time) => time,
+        TimeOrNow::Now => SystemTime::now(),
     }
 }
 
@@ -94,6 +205,21 @@ impl FuseErrorExt for FileOperationError {
             FileOperationError::IsADirectory => libc::EISDIR,
             FileOperationError::DirectoryNotEmpty => libc::ENOTEMPTY,
             FileOperationError::AlreadyExists => libc::EEXIST,
+            FileOperationError::Interrupted => libc::EINTR,
+            FileOperationError::InvalidName => libc::EINVAL,
+            FileOperationError::InvalidArgument => libc::EINVAL,
+            FileOperationError::NoSpace => libc::ENOSPC,
+            FileOperationError::ReadOnly => libc::EROFS,
+            FileOperationError::NotPermitted => libc::EPERM,
+            FileOperationError::Corrupt => libc::EIO,
+            FileOperationError::FileTooLarge => libc::EFBIG,
+            FileOperationError::Unavailable => libc::EIO,
         }
     }
 }
+
+impl FuseErrorExt for FSError {
+    fn to_errno(&self) -> c_int {
+        self.kind.to_errno()
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use bridgefs_core::{file_record::Record, hash_pointer::HashPointer, inode::INode};
+
+/// A record read when its file was opened, along with the root it was read
+/// from. Any mutation moves the root, so the record is current exactly as
+/// long as the root is.
+#[derive(Debug)]
+struct OpenHandle {
+    inode: INode,
+    root: HashPointer,
+    record: Record,
+}
+
+/// The file handles `open` has given out that have not been released yet
+#[derive(Debug, Default)]
+pub struct HandleTable {
+    next_fh: u64,
+    handles: HashMap<u64, OpenHandle>,
+}
+
+impl HandleTable {
+    /// Hands out a new handle caching `record`, as read from `root`
+    pub fn open(&mut self, inode: INode, root: HashPointer, record: Record) -> u64 {
+        // 0 is what the handle-less replies use, so never hand it out
+        self.next_fh += 1;
+        let fh = self.next_fh;
+        self.handles.insert(
+            fh,
+            OpenHandle {
+                inode,
+                root,
+                record,
+            },
+        );
+        fh
+    }
+
+    /// The record cached under `fh`, if `fh` was opened for `inode` and
+    /// nothing has changed since it was read
+    pub fn get(&self, fh: u64, inode: INode, root: &HashPointer) -> Option<&Record> {
+        self.handles
+            .get(&fh)
+            .filter(|handle| handle.inode == inode && handle.root == *root)
+            .map(|handle| &handle.record)
+    }
+
+    /// Replaces a stale record under `fh` with one read from `root`
+    pub fn refresh(&mut self, fh: u64, inode: INode, root: HashPointer, record: Record) {
+        if let Some(handle) = self.handles.get_mut(&fh)
+            && handle.inode == inode
+        {
+            handle.root = root;
+            handle.record = record;
+        }
+    }
+
+    /// Forgets `fh`, returning the inode it was opened for
+    pub fn release(&mut self, fh: u64) -> Option<INode> {
+        self.handles.remove(&fh).map(|handle| handle.inode)
+    }
+
+    /// Whether any handle is still open for `inode`
+    pub fn is_open(&self, inode: INode) -> bool {
+        self.handles.values().any(|handle| handle.inode == inode)
+    }
+
+    /// Every handle given out and not yet released
+    pub fn open_handles(&self) -> Vec<u64> {
+        self.handles.keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    ffi::{OsStr, OsString},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use fuser::Notifier;
+use tracing::debug;
+
+/// Something the kernel may have cached that a mutation made stale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation {
+    /// The lookup of `name` in the directory `parent`
+    Entry { parent: u64, name: OsString },
+    /// The attributes and cached content of an inode
+    Inode(u64),
+}
+
+/// Queues invalidations for a thread that passes them on to the kernel.
+///
+/// Notifying from the request handler itself deadlocks, since the kernel
+/// holds the directory locked until the reply it is waiting on arrives.
+#[derive(Debug, Default)]
+pub struct Invalidator(Option<Sender<Invalidation>>);
+
+impl Invalidator {
+    /// An invalidator queueing into the returned receiver, for `forward`
+    /// once the session exists
+    pub fn channel() -> (Invalidator, Receiver<Invalidation>) {
+        let (sender, receiver) = mpsc::channel();
+        (Invalidator(Some(sender)), receiver)
+    }
+
+    /// Hands everything queued in `receiver` to `notifier` until every
+    /// `Invalidator` feeding it is dropped
+    pub fn forward(receiver: Receiver<Invalidation>, notifier: Notifier) -> JoinHandle<()> {
+        thread::spawn(move || {
+            for invalidation in receiver {
+                let result = match &invalidation {
+                    Invalidation::Entry { parent, name } => notifier.inval_entry(*parent, name),
+                    // Offset 0 with no length drops the whole page cache
+                    Invalidation::Inode(inode) => notifier.inval_inode(*inode, 0, 0),
+                };
+                // The kernel refuses entries it has nothing cached for
+                if let Err(error) = result {
+                    debug!(%error, ?invalidation, "invalidation not applied");
+                }
+            }
+        })
+    }
+
+    /// `name` in `parent` changed, and so did `parent` itself
+    pub fn entry(&self, parent: u64, name: &OsStr) {
+        self.send(Invalidation::Entry {
+            parent,
+            name: name.to_owned(),
+        });
+        self.send(Invalidation::Inode(parent));
+    }
+
+    pub fn inode(&self, inode: u64) {
+        self.send(Invalidation::Inode(inode));
+    }
+
+    fn send(&self, invalidation: Invalidation) {
+        if let Some(sender) = &self.0 {
+            // Nothing is left to invalidate once the session has gone
+            let _ = sender.send(invalidation);
+        }
+    }
+}
@@ -4,67 +4,559 @@ use std::{
 };
 
 use bridgefs_core::{
-    bridgefs::BridgeFS, content_store::ContentStore, file_record::CommonAttrs,
-    hash_pointer::TypedHashPointerReference, index::INodeIndex,
+    bridgefs::{BridgeFS, RenameMode, SizeLimitPolicy},
+    content_store::{ContentStore, InMemoryContentStore},
+    file_record::{CommonAttrs, FileRecord, Record},
+    filename::{Filename, FilenameEncoding},
+    hash_pointer::{TypedHashPointer, TypedHashPointerReference},
+    index::INodeIndex,
+    inode::INode,
+    interrupt::InterruptHandle,
+    journal::Journal,
+    manifest::ReferenceCounting,
+    response::{FSError, FileOperationError, INodeResponse, ListDirectoryEntry, ReadFileResponse},
 };
 use fuser::{
-    Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
-    ReplyWrite, Request, TimeOrNow,
+    FUSE_ROOT_ID, FileAttr, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr,
+    Request, TimeOrNow, consts::FOPEN_DIRECT_IO,
 };
+use libc::c_int;
+use tracing::{debug, debug_span, warn};
 
 use crate::{
-    baybridge_adapter::{BaybridgeAdapter, BaybridgeContentStore, BaybridgeHashPointerReference},
-    fuse_file_ext::{FuseErrorExt, FuseFileExt, FuseFileResponseExt},
+    baybridge_adapter::{
+        BaybridgeActions, BaybridgeAdapter, BaybridgeContentStore, BaybridgeHashPointerReference,
+    },
+    fuse_file_ext::{FuseAttrsExt, FuseErrorExt, FuseFileExt, FuseFileResponseExt, SetAttr},
     fuse_store_ext::FuseStoreExt,
+    handle_table::HandleTable,
+    invalidation::Invalidator,
+    retry::RetryPolicy,
+    tar_import::{ImportError, import_tar},
+    xattr::{XattrReply, virtual_xattr, xattr_reply},
 };
 
+pub mod backup;
 pub mod baybridge_adapter;
-mod fuse_file_ext;
+pub mod fuse_file_ext;
 pub mod fuse_store_ext;
+pub mod handle_table;
+pub mod invalidation;
+#[cfg(feature = "object-store")]
+pub mod object_store_adapter;
+pub mod retry;
+pub mod tar_import;
+pub mod tracing_store;
+pub mod tree_json;
+pub mod xattr;
 
-const TTL: Duration = Duration::ZERO;
+// The kernel asks for the root by this number, so the tree must keep it there
+const _: () = assert!(INode::ROOT.get() == FUSE_ROOT_ID);
+
+/// The order `readdir` lists a directory's entries in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntryOrder {
+    /// Sorted by name, the order entries are stored in
+    #[default]
+    Name,
+    /// In the order entries were added to the directory, whatever birth
+    /// times their creators gave them
+    Creation,
+}
+
+/// Settings for how the filesystem is presented to the kernel
+#[derive(Debug, Clone, Copy)]
+pub struct BridgeFSConfig {
+    pub filename_encoding: FilenameEncoding,
+    /// Reported as `blksize` and `bsize`, and the unit `blocks` is counted in
+    pub block_size: u32,
+    /// Applied to calls to the baybridge backend
+    pub retry_policy: RetryPolicy,
+    /// How long the baybridge root pointer may hold a new root back before
+    /// publishing it; `None` publishes on every mutation
+    pub flush_interval: Option<Duration>,
+    /// Start over from an empty root, rather than refusing to mount, when
+    /// the root index cannot be read
+    pub reinit_on_missing_root: bool,
+    /// Largest size in bytes any single file may grow to
+    pub max_file_size: Option<u64>,
+    pub size_limit_policy: SizeLimitPolicy,
+    pub entry_order: EntryOrder,
+    /// How long the kernel may cache entries and attributes; mutations
+    /// invalidate what they change through the `Invalidator`
+    pub ttl: Duration,
+}
+
+impl Default for BridgeFSConfig {
+    fn default() -> Self {
+        Self {
+            filename_encoding: FilenameEncoding::default(),
+            block_size: 512,
+            retry_policy: RetryPolicy::default(),
+            flush_interval: None,
+            reinit_on_missing_root: false,
+            max_file_size: None,
+            size_limit_policy: SizeLimitPolicy::default(),
+            entry_order: EntryOrder::default(),
+            ttl: Duration::ZERO,
+        }
+    }
+}
 
 pub struct BridgeFSFuse<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>(
     BridgeFS<IndexHashT, StoreT>,
+    BridgeFSConfig,
+    HandleTable,
+    Invalidator,
 );
 
-impl<'a> BridgeFSFuse<BaybridgeHashPointerReference<'a>, BaybridgeContentStore<'a>> {
-    pub fn from_baybridge(adapter: &'a BaybridgeAdapter) -> Self {
-        let mut store = adapter.content_store();
-        let empty_root_dir = store.empty_root_dir();
-        let index_hash = adapter.hash_pointer_reference(empty_root_dir);
-        let bridgefs = BridgeFS::new(index_hash, store);
-        BridgeFSFuse(bridgefs)
+impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
+    BridgeFSFuse<IndexHashT, StoreT>
+{
+    pub fn new(mut bridgefs: BridgeFS<IndexHashT, StoreT>, config: BridgeFSConfig) -> Self {
+        bridgefs.set_max_file_size(config.max_file_size);
+        bridgefs.set_size_limit_policy(config.size_limit_policy);
+        BridgeFSFuse(
+            bridgefs,
+            config,
+            HandleTable::default(),
+            Invalidator::default(),
+        )
+    }
+
+    pub fn with_filename_encoding(mut self, encoding: FilenameEncoding) -> Self {
+        self.1.filename_encoding = encoding;
+        self
+    }
+
+    pub fn with_config(mut self, config: BridgeFSConfig) -> Self {
+        self.0.set_max_file_size(config.max_file_size);
+        self.0.set_size_limit_policy(config.size_limit_policy);
+        self.1 = config;
+        self
+    }
+
+    /// Tells the kernel through `invalidator` about every entry and inode a
+    /// mutation changes, so a nonzero `ttl` never serves stale ones
+    pub fn with_invalidator(mut self, invalidator: Invalidator) -> Self {
+        self.3 = invalidator;
+        self
+    }
+
+    /// Journals mutations so an interrupted one is finished on the next mount
+    pub fn with_journal(mut self, journal: impl Journal + Send + 'static) -> Self {
+        self.0 = self.0.with_journal(journal);
+        self
+    }
+
+    /// Cancels the read or write being served from another thread. fuser
+    /// answers the kernel's FUSE_INTERRUPT itself without telling the
+    /// filesystem, so this is the only way to stop one early.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.0.interrupt_handle()
+    }
+
+    /// Seeds an empty filesystem from a tar archive before it is mounted,
+    /// naming entries with the configured encoding
+    pub fn import_tar(&mut self, archive: impl std::io::Read) -> Result<usize, ImportError> {
+        import_tar(&mut self.0, archive, self.1.filename_encoding)
+    }
+
+    /// Archives the current tree with `backup::backup`, returning the root
+    /// that was backed up
+    pub fn backup(
+        &mut self,
+        out: impl std::io::Write,
+    ) -> std::io::Result<TypedHashPointer<INodeIndex>> {
+        backup::backup(&mut self.0, out)
+    }
+
+    /// Writes the current tree as JSON with `tree_json::write_tree_json`
+    pub fn write_tree_json(&mut self, out: impl std::io::Write) -> std::io::Result<()> {
+        tree_json::write_tree_json(&mut self.0, out)
+    }
+
+    /// Checks the root index can be read before anything else touches it,
+    /// replacing it with an empty root if `reinit_on_missing_root` is set
+    pub fn ensure_root(&mut self) -> Result<(), FSError> {
+        match self.0.check_root() {
+            Err(e) if e == FileOperationError::Corrupt && self.1.reinit_on_missing_root => {
+                warn!("root index is missing, reinitializing an empty filesystem");
+                self.0.reinitialize_root(INode::ROOT)
+            }
+            result => result,
+        }
+    }
+
+    /// What `lookup` resolves `name` in `parent` to. NFS export looks up
+    /// `.` and `..` to turn a bare inode from a file handle back into an
+    /// entry, so those resolve too even though they are not stored.
+    pub fn lookup_entry(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        match name.as_encoded_bytes() {
+            b"." => self.0.lookup_record_by_inode(parent.into()),
+            b".." => self.0.lookup_parent_by_inode(parent.into()),
+            _ => {
+                let name = self.filename(name)?;
+                self.0.lookup_record_by_name(parent.into(), &name)
+            }
+        }
+    }
+
+    /// The entries `readdir` lists for the directory `inode`, in the
+    /// configured `entry_order` with `.` and `..` last
+    pub fn list_entries(&mut self, inode: u64) -> Result<Vec<ListDirectoryEntry>, FSError> {
+        let listing = self.0.list_directory_by_inode(inode.into())?;
+        let mut entries = listing.entries;
+        if self.1.entry_order == EntryOrder::Creation {
+            let directory = &listing.directory.inner;
+            let children = entries.len().saturating_sub(2);
+            entries[..children].sort_by_key(|entry| directory.sequence_of(&entry.name));
+        }
+        Ok(entries)
+    }
+
+    /// The attributes `getattr` reports for `inode`
+    pub fn file_attr(&mut self, inode: u64) -> Result<FileAttr, FSError> {
+        let record = self.0.lookup_record_by_inode(inode.into())?;
+        Ok(record.attrs(self.1.block_size))
+    }
+
+    /// `file_attr`, answered from the record cached in `fh` while nothing
+    /// has changed since it was read, which saves decoding the index
+    pub fn file_attr_with_handle(
+        &mut self,
+        inode: u64,
+        fh: Option<u64>,
+    ) -> Result<FileAttr, FSError> {
+        let Some(fh) = fh else {
+            return self.file_attr(inode);
+        };
+        let root = self.0.current_root();
+        if let Some(record) = self.2.get(fh, inode.into(), &root) {
+            return Ok(record.attrs(inode.into(), self.1.block_size));
+        }
+        let record = self.0.lookup_record_by_inode(inode.into())?;
+        let attr = record.attrs(self.1.block_size);
+        self.2.refresh(fh, inode.into(), root, record.inner);
+        Ok(attr)
+    }
+
+    /// What `setattr` does: truncates `inode` to `size` if one is given,
+    /// then applies `changes` to its attributes
+    pub fn set_attributes(
+        &mut self,
+        inode: u64,
+        size: Option<u64>,
+        changes: &SetAttr,
+    ) -> Result<INodeResponse<Record, Record>, FSError> {
+        if let Some(size) = size {
+            self.0.truncate(inode.into(), size)?;
+        }
+        let record = self.0.lookup_record_by_inode(inode.into())?;
+        let mut attributes = record.inner.common_attrs().clone();
+        attributes.apply_setattr(changes);
+        let record = if changes.crtime.is_some() {
+            self.0
+                .replace_attributes_by_inode(inode.into(), attributes)?
+        } else {
+            self.0
+                .update_attributes_by_inode(inode.into(), attributes)?
+        };
+        self.3.inode(inode);
+        Ok(record)
+    }
+
+    /// The flags `open` replies with when `inode` is opened with `flags`;
+    /// `O_DIRECT` opens bypass the kernel page cache
+    pub fn open_flags(&mut self, inode: u64, flags: i32) -> Result<u32, FSError> {
+        self.0.lookup_record_by_inode(inode.into())?;
+        Ok(open_reply_flags(flags))
+    }
+
+    /// What `open` replies with: a new handle caching the record of
+    /// `inode`, and the flags from `open_flags`
+    pub fn open_handle(&mut self, inode: u64, flags: i32) -> Result<(u64, u32), FSError> {
+        let root = self.0.current_root();
+        let record = self.0.lookup_record_by_inode(inode.into())?;
+        let fh = self.2.open(inode.into(), root, record.inner);
+        Ok((fh, open_reply_flags(flags)))
+    }
+
+    /// Forgets `fh`, releasing its file if it was the last handle on a
+    /// `create_tmpfile` file that was never linked
+    pub fn release_handle(&mut self, fh: u64) {
+        if let Some(inode) = self.2.release(fh)
+            && !self.2.is_open(inode)
+            && let Err(error) = self.0.remove_if_unlinked(inode)
+        {
+            warn!(%error, inode = inode.get(), "failed to release unlinked file");
+        }
+    }
+
+    /// What `destroy` does on unmount: releases every handle the kernel
+    /// never released, as after a forced unmount, then commits the root so
+    /// a held-back one is published before the process exits
+    pub fn shutdown(&mut self) -> Result<TypedHashPointer<INodeIndex>, FSError> {
+        for fh in self.2.open_handles() {
+            self.release_handle(fh);
+        }
+        self.0.commit()
+    }
+
+    /// Creates a file with no name in `parent`, as `O_TMPFILE` does, kept
+    /// alive by the returned handle until `link` names it. The kernel sends
+    /// `O_TMPFILE` through its own tmpfile op, which fuser does not expose,
+    /// so this is only reachable by calling it directly.
+    pub fn create_tmpfile(
+        &mut self,
+        parent: u64,
+        attributes: CommonAttrs,
+    ) -> Result<(INodeResponse<FileRecord, Record>, u64), FSError> {
+        let file = self.0.create_unnamed(parent.into(), attributes)?;
+        let root = self.0.current_root();
+        let fh = self.2.open(file.inode, root, file.inner.clone().into());
+        Ok((file, fh))
+    }
+
+    /// What `rename` does on behalf of `uid`, with `flags` being the
+    /// `RENAME_*` flags the kernel passed through from `renameat2(2)`
+    pub fn rename_entry(
+        &mut self,
+        uid: u32,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+    ) -> Result<(), FSError> {
+        let mode = match flags {
+            0 => RenameMode::Replace,
+            libc::RENAME_NOREPLACE => RenameMode::NoReplace,
+            libc::RENAME_EXCHANGE => RenameMode::Exchange,
+            _ => return Err(FileOperationError::InvalidArgument.into()),
+        };
+        let from = self.filename(name)?;
+        let to = self.filename(newname)?;
+        self.0.check_removal_by(parent.into(), &from, uid)?;
+        self.0.check_removal_by(newparent.into(), &to, uid)?;
+        self.0
+            .rename_with_mode(parent.into(), &from, newparent.into(), &to, mode)?;
+        self.3.entry(parent, name);
+        self.3.entry(newparent, newname);
+        Ok(())
+    }
+
+    /// What `read` replies with for `size` bytes of `inode` from `offset`
+    pub fn read_data(
+        &mut self,
+        inode: u64,
+        offset: i64,
+        size: u32,
+    ) -> Result<ReadFileResponse, FSError> {
+        let _span = debug_span!("read", inode, offset, size).entered();
+        self.0
+            .read_file_data_by_inode(inode.into(), offset as usize, size as usize)
+            .inspect_err(|error| debug!(%error, "failed"))
+    }
+
+    /// What `write` does with `data` at `offset` in `inode`, returning how
+    /// much was written
+    pub fn write_data(&mut self, inode: u64, offset: i64, data: &[u8]) -> Result<usize, FSError> {
+        let written = self.0.write_to_file(inode.into(), offset as usize, data)?;
+        self.3.inode(inode);
+        Ok(written)
+    }
+
+    /// What `link` does, naming `inode` `newname` in `newparent` too
+    pub fn link_entry(
+        &mut self,
+        inode: u64,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> Result<INodeResponse<FileRecord, Record>, FSError> {
+        let filename = self.filename(newname)?;
+        let file = self.0.link(inode.into(), newparent.into(), &filename)?;
+        self.3.entry(newparent, newname);
+        // The link count went up
+        self.3.inode(inode);
+        Ok(file)
+    }
+
+    /// What `getxattr` replies with for `name` on `inode`, given a buffer
+    /// of `size` bytes
+    pub fn xattr_value(
+        &mut self,
+        inode: u64,
+        name: &OsStr,
+        size: u32,
+    ) -> Result<XattrReply, c_int> {
+        let record = self
+            .0
+            .lookup_record_by_inode(inode.into())
+            .map_err(|e| errno(&e))?;
+        let value = virtual_xattr(&record, name).ok_or(libc::ENODATA)?;
+        xattr_reply(value, size)
+    }
+
+    /// What `listxattr` replies with for `inode`, given a buffer of `size`
+    /// bytes. There are no stored attributes, and the virtual ones stay
+    /// hidden, so the list is always empty.
+    pub fn xattr_list(&mut self, inode: u64, size: u32) -> Result<XattrReply, c_int> {
+        self.0
+            .lookup_record_by_inode(inode.into())
+            .map_err(|e| errno(&e))?;
+        xattr_reply(Vec::new(), size)
+    }
+
+    fn filename(&self, name: &OsStr) -> Result<Filename, FSError> {
+        self.1
+            .filename_encoding
+            .encode(name)
+            .map_err(|e| e.with_name(&name.into()))
+    }
+}
+
+impl<'a, ActionsT: BaybridgeActions>
+    BridgeFSFuse<BaybridgeHashPointerReference<'a, ActionsT>, BaybridgeContentStore<'a, ActionsT>>
+{
+    pub fn from_baybridge(adapter: &'a BaybridgeAdapter<ActionsT>, fs_name: &str) -> Self {
+        BridgeFSFuse::from_baybridge_store(adapter, fs_name, adapter.content_store())
+    }
+}
+
+impl<'a, ActionsT: BaybridgeActions, StoreT: ContentStore>
+    BridgeFSFuse<BaybridgeHashPointerReference<'a, ActionsT>, StoreT>
+{
+    /// `from_baybridge`, with the adapter's content store replaced by
+    /// `store`, usually one wrapping it
+    pub fn from_baybridge_store(
+        adapter: &'a BaybridgeAdapter<ActionsT>,
+        fs_name: &str,
+        mut store: StoreT,
+    ) -> Self {
+        let empty_root_dir = if store.is_writable() {
+            store
+                .empty_root_dir()
+                .expect("Failed to store the empty root directory")
+        } else {
+            // Nothing can be stored, so a name nobody has written yet
+            // points at a root that cannot be read back
+            InMemoryContentStore::default()
+                .empty_root_dir()
+                .expect("Failed to hash the empty root directory")
+        };
+        let index_hash = adapter.hash_pointer_reference(fs_name, empty_root_dir);
+        let bridgefs = BridgeFS::new(index_hash, store, ReferenceCounting::Eager);
+        BridgeFSFuse(
+            bridgefs,
+            BridgeFSConfig::default(),
+            HandleTable::default(),
+            Invalidator::default(),
+        )
     }
 }
 
 impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Filesystem
     for BridgeFSFuse<IndexHashT, StoreT>
 {
+    fn init(&mut self, _req: &Request<'_>, _config: &mut KernelConfig) -> Result<(), c_int> {
+        let _span = debug_span!("init").entered();
+        self.ensure_root()
+            .and_then(|_| self.0.recover())
+            .map(|_| ())
+            .map_err(|e| errno(&e))
+    }
+
+    fn destroy(&mut self) {
+        let _span = debug_span!("destroy").entered();
+        if let Err(e) = self.shutdown() {
+            warn!(%e, "failed to commit the root on unmount");
+        }
+    }
+
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        let response = self.0.lookup_record_by_name(parent.into(), &name.into());
-        match response {
+        let _span = debug_span!("lookup", parent, ?name).entered();
+        match self.lookup_entry(parent, name) {
             Ok(record) => {
-                reply.entry(&TTL, &record.attrs(), 0);
+                reply.entry(
+                    &self.1.ttl,
+                    &record.attrs(self.1.block_size),
+                    record.generation(),
+                );
             }
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
             }
         }
     }
 
-    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
-        let response = self.0.lookup_record_by_inode(ino.into());
-        match response {
-            Ok(record) => {
-                reply.attr(&TTL, &record.attrs());
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, fh: Option<u64>, reply: ReplyAttr) {
+        let _span = debug_span!("getattr", inode = ino, fh).entered();
+        match self.file_attr_with_handle(ino, fh) {
+            Ok(attr) => {
+                reply.attr(&self.1.ttl, &attr);
             }
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
             }
         }
     }
 
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let _span = debug_span!("open", inode = ino, flags).entered();
+        match self.open_handle(ino, flags) {
+            Ok((fh, open_flags)) => reply.opened(fh, open_flags),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let _span = debug_span!("release", inode = ino, fh).entered();
+        self.release_handle(fh);
+        reply.ok();
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        let _span = debug_span!("statfs").entered();
+        // The backing store has no fixed capacity to report
+        let block_size = self.1.block_size;
+        match self.0.inode_count() {
+            Ok(files) => reply.statfs(0, 0, 0, files as u64, 0, block_size, 255, block_size),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        let _span = debug_span!("getxattr", inode = ino, ?name, size).entered();
+        reply_xattr(self.xattr_value(ino, name, size), reply);
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        let _span = debug_span!("listxattr", inode = ino, size).entered();
+        reply_xattr(self.xattr_list(ino, size), reply);
+    }
+
     fn read(
         &mut self,
         _req: &Request,
@@ -76,17 +568,15 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Fi
         _lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
-        match self
-            .0
-            .read_file_data_by_inode(ino.into(), offset as usize, size as usize)
-        {
+        let _span = debug_span!("read", inode = ino, offset, size).entered();
+        match self.read_data(ino, offset, size) {
             Ok(response) => {
                 reply.data(&response.datablock.data);
             }
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
             }
-        };
+        }
     }
 
     fn readdir(
@@ -97,20 +587,16 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Fi
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
-        let entries = match self.0.list_directory_by_inode(ino.into()) {
+        let _span = debug_span!("readdir", inode = ino, offset).entered();
+        let entries = match self.list_entries(ino) {
             Ok(entries) => entries,
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
                 return;
             }
         };
 
-        for (i, entry) in entries
-            .entries
-            .into_iter()
-            .enumerate()
-            .skip(offset as usize)
-        {
+        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
             let name: OsString = entry.name.into();
             if reply.add(ino, (i + 1) as i64, entry.record.inner.file_type(), name) {
                 break;
@@ -131,12 +617,13 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Fi
         _lock_owner: Option<u64>,
         reply: ReplyWrite,
     ) {
-        match self.0.write_to_file(ino.into(), offset as usize, data) {
+        let _span = debug_span!("write", inode = ino, offset, size = data.len()).entered();
+        match self.write_data(ino, offset, data) {
             Ok(written) => {
                 reply.written(written as u32);
             }
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
             }
         }
     }
@@ -148,21 +635,37 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Fi
         name: &OsStr,
         mode: u32,
         umask: u32,
-        _flags: i32,
+        flags: i32,
         reply: ReplyCreate,
     ) {
+        let _span = debug_span!("create", parent, ?name, mode, flags).entered();
         let attributes = CommonAttrs::builder()
             .perm(get_permissions(mode, umask))
             .uid(req.uid())
             .gid(req.gid())
             .build();
-        let response = self.0.create_file(parent.into(), name.into(), attributes);
+        let response = self.filename(name).and_then(|name| {
+            if flags & libc::O_EXCL != 0 {
+                self.0.create_file(parent.into(), name, attributes)
+            } else {
+                let (file, created) = self.0.create_or_open(parent.into(), name, attributes)?;
+                debug!(created, "opened");
+                Ok(file)
+            }
+        });
         match response {
             Ok(file) => {
-                reply.created(&TTL, &file.attrs(), 0, 0, 0);
+                self.3.entry(parent, name);
+                reply.created(
+                    &self.1.ttl,
+                    &file.attrs(self.1.block_size),
+                    file.generation(),
+                    0,
+                    0,
+                );
             }
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
             }
         }
     }
@@ -172,9 +675,9 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Fi
         _req: &Request<'_>,
         ino: u64,
         mode: Option<u32>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
-        _size: Option<u64>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
         atime: Option<TimeOrNow>,
         mtime: Option<TimeOrNow>,
         ctime: Option<SystemTime>,
@@ -182,47 +685,24 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Fi
         crtime: Option<SystemTime>,
         _chgtime: Option<SystemTime>,
         _bkuptime: Option<SystemTime>,
-        _flags: Option<u32>,
+        flags: Option<u32>,
         reply: ReplyAttr,
     ) {
-        let record = match self.0.lookup_record_by_inode(ino.into()) {
-            Ok(record) => record,
-            Err(e) => {
-                reply.error(e.to_errno());
-                return;
-            }
+        let _span = debug_span!("setattr", inode = ino, ?mode, ?size, ?flags).entered();
+        let changes = SetAttr {
+            mode,
+            uid,
+            gid,
+            atime,
+            mtime,
+            ctime,
+            crtime,
+            flags,
         };
-        let mut attributes = record.inner.common_attrs().clone();
-
-        if let Some(mode) = mode {
-            attributes.perm = mode as u16;
-        }
-        if let Some(atime) = atime {
-            attributes.atime = match atime {
-                TimeOrNow::SpecificTime(t) => t,
-                TimeOrNow::Now => SystemTime::now(),
-            };
-        }
-        if let Some(mtime) = mtime {
-            attributes.mtime = match mtime {
-                TimeOrNow::SpecificTime(t) => t,
-                TimeOrNow::Now => SystemTime::now(),
-            };
-        }
-        if let Some(ctime) = ctime {
-            attributes.ctime = ctime;
-        }
-        if let Some(crtime) = crtime {
-            attributes.crtime = crtime;
-        }
-
-        match self
-            .0
-            .update_attributes_by_inode(ino.into(), attributes.clone())
-        {
-            Ok(record) => reply.attr(&TTL, &record.attrs()),
+        match self.set_attributes(ino, size, &changes) {
+            Ok(record) => reply.attr(&self.1.ttl, &record.attrs(self.1.block_size)),
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
             }
         }
     }
@@ -236,47 +716,133 @@ impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> Fi
         umask: u32,
         reply: ReplyEntry,
     ) {
+        let _span = debug_span!("mkdir", parent, ?name, mode).entered();
         let attributes = CommonAttrs::builder()
             .perm(get_permissions(mode, umask))
             .uid(req.uid())
             .gid(req.gid())
             .build();
         let response = self
-            .0
-            .create_directory(parent.into(), name.into(), attributes);
+            .filename(name)
+            .and_then(|name| self.0.create_directory(parent.into(), name, attributes));
         match response {
             Ok(directory) => {
-                reply.entry(&TTL, &directory.attrs(), 0);
+                self.3.entry(parent, name);
+                reply.entry(
+                    &self.1.ttl,
+                    &directory.attrs(self.1.block_size),
+                    directory.generation(),
+                );
+            }
+            Err(e) => {
+                reply.error(errno(&e));
+            }
+        }
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let _span = debug_span!("unlink", parent, ?name).entered();
+        let response = self.filename(name).and_then(|name| {
+            self.0
+                .remove_file_by_name_as(parent.into(), &name, req.uid())
+        });
+        match response {
+            Ok(_) => {
+                self.3.entry(parent, name);
+                reply.ok();
             }
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
             }
         }
     }
 
-    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        match self.0.remove_file_by_name(parent.into(), &name.into()) {
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let _span = debug_span!("rmdir", parent, ?name).entered();
+        let response = self.filename(name).and_then(|name| {
+            self.0
+                .remove_directory_by_name_as(parent.into(), &name, req.uid())
+        });
+        match response {
             Ok(_) => {
+                self.3.entry(parent, name);
                 reply.ok();
             }
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
+            }
+        }
+    }
+
+    fn link(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let _span = debug_span!("link", inode = ino, newparent, ?newname).entered();
+        match self.link_entry(ino, newparent, newname) {
+            Ok(file) => {
+                reply.entry(
+                    &self.1.ttl,
+                    &file.attrs(self.1.block_size),
+                    file.generation(),
+                );
+            }
+            Err(e) => {
+                reply.error(errno(&e));
             }
         }
     }
 
-    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        match self.0.remove_directory_by_name(parent.into(), &name.into()) {
+    fn rename(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let _span = debug_span!("rename", parent, ?name, newparent, ?newname, flags).entered();
+        match self.rename_entry(req.uid(), parent, name, newparent, newname, flags) {
             Ok(_) => {
                 reply.ok();
             }
             Err(e) => {
-                reply.error(e.to_errno());
+                reply.error(errno(&e));
             }
         }
     }
 }
 
+/// `O_DIRECT` opens bypass the kernel page cache
+fn open_reply_flags(flags: i32) -> u32 {
+    if flags & libc::O_DIRECT != 0 {
+        FOPEN_DIRECT_IO
+    } else {
+        0
+    }
+}
+
+/// Logs `error` against the current operation and converts it for the kernel
+fn errno(error: &FSError) -> c_int {
+    debug!(%error, "failed");
+    error.to_errno()
+}
+
+/// Answers a `getxattr` or `listxattr` with the size or data asked for
+fn reply_xattr(result: Result<XattrReply, c_int>, reply: ReplyXattr) {
+    match result {
+        Ok(XattrReply::Size(size)) => reply.size(size),
+        Ok(XattrReply::Data(data)) => reply.data(&data),
+        Err(e) => reply.error(e),
+    }
+}
+
 fn get_permissions(mode: u32, umask: u32) -> u16 {
     (mode & 0o7777 & !umask) as u16
 }
@@ -1,28 +1,155 @@
 use baybridge::client::Actions;
 use baybridge::configuration::Configuration;
-use bridgefs_fuse::BridgeFSFuse;
-use bridgefs_fuse::baybridge_adapter::BaybridgeAdapter;
-use fuser::MountOption;
+use bridgefs_core::bridgefs::SizeLimitPolicy;
+use bridgefs_core::content_store::ContentStore;
+use bridgefs_core::filename::FilenameEncoding;
+use bridgefs_core::hash_pointer::{
+    HashPointer, HashPointerReference, TypedHashPointer, TypedHashPointerReference,
+};
+use bridgefs_core::index::INodeIndex;
+use bridgefs_core::journal::FileJournal;
+use bridgefs_fuse::backup::restore;
+use bridgefs_fuse::baybridge_adapter::{
+    BaybridgeActions, BaybridgeAdapter, DEFAULT_FS_NAME, ReadOnlyActions,
+};
+use bridgefs_fuse::invalidation::Invalidator;
+use bridgefs_fuse::tracing_store::TracingContentStore;
+use bridgefs_fuse::{BridgeFSConfig, BridgeFSFuse, EntryOrder};
+use fuser::{MountOption, Session};
 use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tracing::Level;
+
+fn usage() -> ! {
+    let program = env::args().next().unwrap();
+    eprintln!(
+        "Usage: {program} <mountpoint> [--fs-name <name>] [--utf8-names] [--journal <path>] [--read-only] [--block-size <bytes>] [--log-level <level>] [--reinit-on-missing-root] [--max-file-size <bytes>] [--short-writes] [--flush-interval <seconds>] [--import <tar>] [--trace-store] [--trace-store-file <path>] [--creation-order] [--ttl <seconds>]"
+    );
+    eprintln!("       {program} backup --out <tar> [--fs-name <name>]");
+    eprintln!("       {program} restore --in <tar> [--fs-name <name>]");
+    eprintln!("       {program} tree [--fs-name <name>]");
+    std::process::exit(1);
+}
 
 fn main() {
-    let mountpoint = match env::args().nth(1) {
-        Some(path) => path,
-        None => {
-            eprintln!("Usage: {} <mountpoint>", env::args().next().unwrap());
-            std::process::exit(1);
+    match env::args().nth(1).as_deref() {
+        Some("backup") => return backup_command(env::args().skip(2)),
+        Some("restore") => return restore_command(env::args().skip(2)),
+        Some("tree") => return tree_command(env::args().skip(2)),
+        _ => {}
+    }
+    let mut mountpoint = None;
+    let mut fs_name = DEFAULT_FS_NAME.to_string();
+    let mut filename_encoding = FilenameEncoding::Raw;
+    let mut startup = Startup::default();
+    let mut read_only = false;
+    let mut reinit_on_missing_root = false;
+    let mut max_file_size = None;
+    let mut size_limit_policy = SizeLimitPolicy::Strict;
+    let mut flush_interval = None;
+    let mut entry_order = EntryOrder::Name;
+    let mut ttl = Duration::ZERO;
+    let mut block_size = BridgeFSConfig::default().block_size;
+    let mut log_level = env::var("BRIDGEFS_LOG").ok();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fs-name" => fs_name = args.next().unwrap_or_else(|| usage()),
+            "--utf8-names" => filename_encoding = FilenameEncoding::Utf8,
+            "--journal" => startup.journal_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--import" => startup.import_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--trace-store" => startup.trace_store = true,
+            "--trace-store-file" => {
+                startup.trace_store = true;
+                startup.trace_file = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "--read-only" => read_only = true,
+            "--reinit-on-missing-root" => reinit_on_missing_root = true,
+            "--log-level" => log_level = Some(args.next().unwrap_or_else(|| usage())),
+            "--short-writes" => size_limit_policy = SizeLimitPolicy::ShortWrite,
+            "--creation-order" => entry_order = EntryOrder::Creation,
+            "--max-file-size" => {
+                max_file_size = Some(
+                    args.next()
+                        .and_then(|size| size.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--flush-interval" => {
+                flush_interval = Some(Duration::from_secs(
+                    args.next()
+                        .and_then(|seconds| seconds.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                ))
+            }
+            "--ttl" => {
+                ttl = Duration::from_secs(
+                    args.next()
+                        .and_then(|seconds| seconds.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--block-size" => {
+                block_size = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .filter(|&size| size > 0)
+                    .unwrap_or_else(|| usage())
+            }
+            _ if mountpoint.is_none() => mountpoint = Some(arg),
+            _ => usage(),
         }
-    };
+    }
+    let mountpoint = mountpoint.unwrap_or_else(|| usage());
 
-    let options = vec![MountOption::FSName("bridgefs".to_string())];
+    // Each FUSE operation is a span, so `debug` traces every call
+    let log_level: Level = match log_level {
+        Some(level) => level.parse().unwrap_or_else(|_| usage()),
+        None => Level::WARN,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(log_level)
+        .with_writer(std::io::stderr)
+        .init();
 
-    let config = Configuration::default();
-    let actions = Actions::new(config);
-    let adapter = BaybridgeAdapter::new(actions);
-    let bridgefs = BridgeFSFuse::from_baybridge(&adapter);
+    let mut options = vec![MountOption::FSName("bridgefs".to_string())];
+    if read_only {
+        options.push(MountOption::RO);
+    }
 
-    // Mount the filesystem
-    if let Err(e) = fuser::mount2(bridgefs, &mountpoint, &options) {
+    let config = BridgeFSConfig {
+        filename_encoding,
+        block_size,
+        reinit_on_missing_root,
+        max_file_size,
+        size_limit_policy,
+        flush_interval,
+        entry_order,
+        ttl,
+        ..BridgeFSConfig::default()
+    };
+    let actions = Actions::new(Configuration::default());
+    let result = if read_only {
+        let startup = Startup {
+            journal_path: None,
+            ..startup
+        };
+        mount(
+            ReadOnlyActions(actions),
+            &fs_name,
+            config,
+            startup,
+            &mountpoint,
+            &options,
+        )
+    } else {
+        mount(actions, &fs_name, config, startup, &mountpoint, &options)
+    };
+    if let Err(e) = result {
         eprintln!("Failed to mount filesystem: {}", e);
         if e.kind() == std::io::ErrorKind::PermissionDenied {
             eprintln!("Hint: If you need AllowOther, either:");
@@ -32,3 +159,151 @@ fn main() {
         std::process::exit(1);
     }
 }
+
+/// What to set up around the filesystem before it is served
+#[derive(Default)]
+struct Startup {
+    journal_path: Option<String>,
+    import_path: Option<String>,
+    /// Emit every store access as a `trace` event
+    trace_store: bool,
+    /// Also write the store accesses to this file
+    trace_file: Option<String>,
+}
+
+fn mount(
+    actions: impl BaybridgeActions + Sync,
+    fs_name: &str,
+    config: BridgeFSConfig,
+    startup: Startup,
+    mountpoint: &str,
+    options: &[MountOption],
+) -> std::io::Result<()> {
+    let mut adapter = BaybridgeAdapter::new(actions).with_retry_policy(config.retry_policy);
+    if let Some(flush_interval) = config.flush_interval {
+        adapter = adapter.with_flush_interval(flush_interval);
+    }
+    thread::scope(|scope| {
+        // Publishes held-back roots while the mount sits idle; dropping
+        // `stop` once the session ends lets it go
+        let (stop, stopped) = mpsc::channel();
+        let flusher = &adapter;
+        scope.spawn(move || flusher.flush_until(&stopped));
+        let served = (|| {
+            // The wrapper is left out entirely unless asked for, so it costs nothing
+            if startup.trace_store {
+                let mut store = TracingContentStore::new(adapter.content_store());
+                if let Some(trace_file) = &startup.trace_file {
+                    store = store.with_trace_file(BufWriter::new(File::create(trace_file)?));
+                }
+                let bridgefs = BridgeFSFuse::from_baybridge_store(&adapter, fs_name, store);
+                serve(bridgefs, config, startup, mountpoint, options)
+            } else {
+                let bridgefs = BridgeFSFuse::from_baybridge(&adapter, fs_name);
+                serve(bridgefs, config, startup, mountpoint, options)
+            }
+        })();
+        drop(stop);
+        served
+    })
+}
+
+fn serve<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>(
+    bridgefs: BridgeFSFuse<IndexHashT, StoreT>,
+    config: BridgeFSConfig,
+    startup: Startup,
+    mountpoint: &str,
+    options: &[MountOption],
+) -> std::io::Result<()> {
+    let mut bridgefs = bridgefs.with_config(config);
+    if let Some(journal_path) = startup.journal_path {
+        bridgefs = bridgefs.with_journal(FileJournal::new(journal_path));
+    }
+    if let Some(import_path) = startup.import_path {
+        bridgefs.ensure_root().map_err(std::io::Error::other)?;
+        let archive = File::open(&import_path)?;
+        let imported = bridgefs
+            .import_tar(std::io::BufReader::new(archive))
+            .map_err(|e| std::io::Error::other(format!("failed to import {import_path}: {e}")))?;
+        eprintln!("Imported {imported} entries from {import_path}");
+    }
+    let (invalidator, invalidations) = Invalidator::channel();
+    let mut session = Session::new(bridgefs.with_invalidator(invalidator), mountpoint, options)?;
+    Invalidator::forward(invalidations, session.notifier());
+    session.run()
+}
+
+/// Parses `--fs-name` and the one path flag `backup` and `restore` take
+fn archive_args(mut args: impl Iterator<Item = String>, path_flag: &str) -> (String, String) {
+    let mut fs_name = DEFAULT_FS_NAME.to_string();
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fs-name" => fs_name = args.next().unwrap_or_else(|| usage()),
+            flag if flag == path_flag => path = Some(args.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+    (fs_name, path.unwrap_or_else(|| usage()))
+}
+
+/// Archives the filesystem `--fs-name` names to `--out`
+fn backup_command(args: impl Iterator<Item = String>) {
+    let (fs_name, out) = archive_args(args, "--out");
+    let adapter = BaybridgeAdapter::new(Actions::new(Configuration::default()));
+    let backed_up = (|| {
+        let mut bridgefs = BridgeFSFuse::from_baybridge(&adapter, &fs_name);
+        bridgefs.ensure_root().map_err(std::io::Error::other)?;
+        bridgefs.backup(BufWriter::new(File::create(&out)?))
+    })();
+    match backed_up {
+        Ok(root) => eprintln!("Backed up {} to {out}", HashPointer::from(&root)),
+        Err(e) => {
+            eprintln!("Failed to back up {fs_name}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Restores the archive `--in` into the store and points `--fs-name` at
+/// its root, replacing whatever the name held before
+fn restore_command(args: impl Iterator<Item = String>) {
+    let (fs_name, input) = archive_args(args, "--in");
+    let adapter = BaybridgeAdapter::new(Actions::new(Configuration::default()));
+    let restored = (|| -> std::io::Result<TypedHashPointer<INodeIndex>> {
+        let archive = BufReader::new(File::open(&input)?);
+        let root = restore(archive, &mut adapter.content_store()).map_err(std::io::Error::other)?;
+        let mut reference = adapter.hash_pointer_reference(&fs_name, root.clone());
+        reference.set_typed(&root);
+        reference.flush();
+        Ok(root)
+    })();
+    match restored {
+        Ok(root) => eprintln!("Restored {fs_name} to {}", HashPointer::from(&root)),
+        Err(e) => {
+            eprintln!("Failed to restore {input}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints the tree `--fs-name` names to stdout as JSON, without mounting
+fn tree_command(mut args: impl Iterator<Item = String>) {
+    let mut fs_name = DEFAULT_FS_NAME.to_string();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fs-name" => fs_name = args.next().unwrap_or_else(|| usage()),
+            _ => usage(),
+        }
+    }
+    let adapter = BaybridgeAdapter::new(Actions::new(Configuration::default()));
+    let written = (|| {
+        let mut bridgefs = BridgeFSFuse::from_baybridge(&adapter, &fs_name);
+        bridgefs.ensure_root().map_err(std::io::Error::other)?;
+        bridgefs.write_tree_json(BufWriter::new(std::io::stdout().lock()))
+    })();
+    if let Err(e) = written {
+        eprintln!("Failed to list {fs_name}: {e}");
+        std::process::exit(1);
+    }
+}
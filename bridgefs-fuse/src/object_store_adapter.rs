@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use bridgefs_core::{
+    content_store::{ContentStore, ContentStoreError},
+    hash_pointer::{Blake3, HashPointer, Hasher},
+    manifest::Manifest,
+};
+use futures::TryStreamExt;
+use object_store::{ObjectStore, PutMode, PutOptions, PutPayload, path::Path};
+use tracing::warn;
+
+/// Keeps content as objects in an S3-compatible bucket, or anything else
+/// `object_store` speaks. Each block is stored under its hash, spread over
+/// prefixes named after the hash's first two characters so no single
+/// listing grows with the whole store.
+pub struct ObjectStoreContentStore {
+    runtime: tokio::runtime::Runtime,
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+}
+
+impl ObjectStoreContentStore {
+    /// Keeps every block under `prefix`, so one bucket can hold several
+    /// stores
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: impl Into<Path>) -> std::io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            runtime,
+            store,
+            prefix: prefix.into(),
+        })
+    }
+
+    /// Whether the block under `hash` is still in the bucket
+    pub fn contains(&self, hash: &HashPointer) -> Result<bool, ContentStoreError> {
+        match self.runtime.block_on(self.store.head(&self.path(hash))) {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(classify(e)),
+        }
+    }
+
+    /// Deletes the block under `hash`; deleting one that is already gone
+    /// is not an error
+    pub fn remove_content(&mut self, hash: &HashPointer) -> Result<(), ContentStoreError> {
+        match self.runtime.block_on(self.store.delete(&self.path(hash))) {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(classify(e)),
+        }
+    }
+
+    /// Every block in the bucket under this store's prefix
+    pub fn list_content(&self) -> Result<Vec<HashPointer>, ContentStoreError> {
+        let objects: Vec<_> = self
+            .runtime
+            .block_on(self.store.list(Some(&self.prefix)).try_collect())
+            .map_err(classify)?;
+        Ok(objects
+            .iter()
+            .filter_map(|object| object.location.filename())
+            .filter_map(|name| name.replace('-', "+").replace('_', "/").parse().ok())
+            .collect())
+    }
+
+    /// Writes `content` as the object for `hash`, unless one exists already
+    fn put(&self, hash: &HashPointer, content: &[u8]) -> Result<(), ContentStoreError> {
+        // What is stored under a hash never changes, so an object that
+        // already exists holds these bytes or another encoding of them
+        let options = PutOptions {
+            mode: PutMode::Create,
+            ..PutOptions::default()
+        };
+        let path = self.path(hash);
+        let put = self
+            .store
+            .put_opts(&path, PutPayload::from(content.to_vec()), options);
+        match self.runtime.block_on(put) {
+            Ok(_) | Err(object_store::Error::AlreadyExists { .. }) => Ok(()),
+            Err(e) => Err(classify(e)),
+        }
+    }
+
+    /// Base64 as `HashPointer` displays it, made URL-safe so it is a
+    /// single path segment
+    fn path(&self, hash: &HashPointer) -> Path {
+        let name = hash.to_string().replace('+', "-").replace('/', "_");
+        self.prefix.child(&name[..2]).child(name)
+    }
+}
+
+impl ContentStore for ObjectStoreContentStore {
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        let hash = Blake3::hash(content);
+        self.put(&hash, content)?;
+        Ok(hash)
+    }
+
+    fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        self.try_get_content(hash)
+            .expect("Content should exist in the object store")
+    }
+
+    fn try_get_content(&self, hash: &HashPointer) -> Result<Vec<u8>, ContentStoreError> {
+        let bytes = self
+            .runtime
+            .block_on(async {
+                let object = self.store.get(&self.path(hash)).await?;
+                object.bytes().await
+            })
+            .map_err(classify)?;
+        Ok(bytes.to_vec())
+    }
+
+    fn content_hash(&self, content: &[u8]) -> Option<HashPointer> {
+        Some(Blake3::hash(content))
+    }
+
+    fn add_content_under(
+        &mut self,
+        hash: &HashPointer,
+        content: &[u8],
+    ) -> Result<bool, ContentStoreError> {
+        self.put(hash, content)?;
+        Ok(true)
+    }
+
+    /// Eviction only frees space, so a failed listing or delete is logged
+    /// and left for the next attempt rather than failing the write that
+    /// asked for room
+    fn evict_unreferenced(&mut self, manifest: &Manifest) {
+        let hashes = match self.list_content() {
+            Ok(hashes) => hashes,
+            Err(error) => {
+                warn!(%error, "failed to list content to evict");
+                return;
+            }
+        };
+        for hash in hashes {
+            if !manifest.has_reference(&hash)
+                && let Err(error) = self.remove_content(&hash)
+            {
+                warn!(%error, %hash, "failed to evict content");
+            }
+        }
+    }
+}
+
+/// Sorts a failure from the bucket the way `BaybridgeError::classify` does:
+/// `Generic` is what network and server errors surface as, so it is taken to
+/// be transient, while the rest fail the same way every time
+fn classify(error: object_store::Error) -> ContentStoreError {
+    let transient = matches!(
+        error,
+        object_store::Error::Generic { .. } | object_store::Error::JoinError { .. }
+    );
+    warn!(%error, transient, "object store call failed");
+    ContentStoreError::Unavailable { transient }
+}
@@ -0,0 +1,75 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    thread,
+    time::Duration,
+};
+
+use bridgefs_core::content_store::ContentStoreError;
+
+/// Errors that know whether repeating the failed call could help, so only
+/// those are retried and logical errors fail straight away
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+impl Transient for ContentStoreError {
+    fn is_transient(&self) -> bool {
+        ContentStoreError::is_transient(self)
+    }
+}
+
+/// How often, and how patiently, to retry calls that failed transiently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first one included
+    pub max_attempts: u32,
+    /// Delay before the first retry; it doubles for each one after
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Gives up after the first failure
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Exponential backoff before retry number `retry`, jittered to between
+    /// half and all of it so that clients failing together spread out
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let jitter = RandomState::new().hash_one(retry) as f64 / u64::MAX as f64;
+        backoff.mul_f64(0.5 + jitter / 2.0)
+    }
+
+    /// Calls `call` until it succeeds, fails with an error that is not
+    /// transient, or runs out of attempts
+    pub fn retry<T, E: Transient>(&self, mut call: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+        let mut retry = 0;
+        loop {
+            match call() {
+                Err(e) if e.is_transient() && retry + 1 < self.max_attempts => {
+                    thread::sleep(self.delay(retry));
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use bridgefs_core::{
+    bridgefs::BridgeFS,
+    content_store::ContentStore,
+    file_record::{CommonAttrs, DEFAULT_FILE_PERM},
+    filename::{Filename, FilenameEncoding},
+    hash_pointer::TypedHashPointerReference,
+    index::INodeIndex,
+    inode::INode,
+    response::{FSError, FileOperationError},
+};
+use tar::{Archive, EntryType, Header};
+use tracing::warn;
+
+/// Why a tar archive could not be imported
+#[derive(Debug)]
+pub enum ImportError {
+    /// The filesystem already has content, which the archive would be
+    /// merged into
+    NotEmpty,
+    /// The archive itself could not be read
+    Archive(std::io::Error),
+    /// An entry in the archive could not be created
+    Entry { path: PathBuf, source: FSError },
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::NotEmpty => f.write_str("filesystem is not empty"),
+            ImportError::Archive(e) => write!(f, "failed to read archive: {e}"),
+            ImportError::Entry { path, source } => {
+                write!(f, "failed to import {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl Error for ImportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImportError::NotEmpty => None,
+            ImportError::Archive(e) => Some(e),
+            ImportError::Entry { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(error: std::io::Error) -> Self {
+        ImportError::Archive(error)
+    }
+}
+
+/// Seeds an empty filesystem with the directories, regular files and hard
+/// links in a tar `archive`, returning how many entries were imported.
+/// Directories the archive leaves out are created with default attributes,
+/// and entry types the filesystem cannot hold, such as symlinks, are
+/// skipped with a warning.
+pub fn import_tar<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>(
+    bridgefs: &mut BridgeFS<IndexHashT, StoreT>,
+    archive: impl Read,
+    encoding: FilenameEncoding,
+) -> Result<usize, ImportError> {
+    // An empty filesystem holds nothing but its root directory
+    let inode_count = bridgefs
+        .inode_count()
+        .map_err(|source| ImportError::Entry {
+            path: PathBuf::from("/"),
+            source,
+        })?;
+    if inode_count > 1 {
+        return Err(ImportError::NotEmpty);
+    }
+
+    let mut importer = Importer {
+        bridgefs,
+        encoding,
+        inodes: HashMap::from([(PathBuf::new(), INode::ROOT)]),
+    };
+    let mut imported = 0;
+    for entry in Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        let path = normalize(&entry.path()?);
+        let header = entry.header().clone();
+        let result = match header.entry_type() {
+            EntryType::Directory => importer.directory(&path, attributes(&header)).map(|_| ()),
+            EntryType::Regular | EntryType::Continuous => {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                importer.file(&path, &header, &data)
+            }
+            EntryType::Link => {
+                let target = entry.link_name()?.map(|target| normalize(&target));
+                importer.link(&path, target)
+            }
+            entry_type => {
+                warn!(path = %path.display(), ?entry_type, "skipping unsupported tar entry");
+                continue;
+            }
+        };
+        result.map_err(|source| ImportError::Entry { path, source })?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+/// Strips `.` and any leading `/` so every entry is relative to the root;
+/// `..` is dropped too rather than let an entry escape it
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+struct Importer<'a, IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore> {
+    bridgefs: &'a mut BridgeFS<IndexHashT, StoreT>,
+    encoding: FilenameEncoding,
+    /// Every path created so far, so entries and their parents resolve
+    /// without a lookup per component
+    inodes: HashMap<PathBuf, INode>,
+}
+
+impl<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>
+    Importer<'_, IndexHashT, StoreT>
+{
+    /// An existing directory is kept as it is, since its entry may come
+    /// after its children
+    fn directory(&mut self, path: &Path, attributes: CommonAttrs) -> Result<INode, FSError> {
+        if let Some(&inode) = self.inodes.get(path) {
+            return Ok(inode);
+        }
+        let (parent, name) = self.parent_and_name(path)?;
+        let inode = self
+            .bridgefs
+            .create_directory(parent, name, attributes)?
+            .inode;
+        self.inodes.insert(path.to_path_buf(), inode);
+        Ok(inode)
+    }
+
+    fn file(&mut self, path: &Path, header: &Header, data: &[u8]) -> Result<(), FSError> {
+        let (parent, name) = self.parent_and_name(path)?;
+        let inode = self
+            .bridgefs
+            .create_file(parent, name, attributes(header))?
+            .inode;
+        self.bridgefs.write_to_file(inode, 0, data)?;
+        // Writing stamps the current time over the archived one
+        self.bridgefs
+            .update_attributes_by_inode(inode, attributes(header))?;
+        self.inodes.insert(path.to_path_buf(), inode);
+        Ok(())
+    }
+
+    fn link(&mut self, path: &Path, target: Option<PathBuf>) -> Result<(), FSError> {
+        let inode = match target.and_then(|target| self.inodes.get(&target).copied()) {
+            Some(inode) => inode,
+            None => return Err(FileOperationError::NotFound.into()),
+        };
+        let (parent, name) = self.parent_and_name(path)?;
+        self.bridgefs.link(inode, parent, &name)?;
+        self.inodes.insert(path.to_path_buf(), inode);
+        Ok(())
+    }
+
+    /// Creates any missing parent directories, which archives are free to
+    /// leave out
+    fn parent_and_name(&mut self, path: &Path) -> Result<(INode, Filename), FSError> {
+        let name = path.file_name().ok_or(FileOperationError::InvalidName)?;
+        let name = self.encoding.encode(name)?;
+        let parent = match path.parent() {
+            Some(parent) => self.directory(parent, CommonAttrs::directory())?,
+            None => INode::ROOT,
+        };
+        Ok((parent, name))
+    }
+}
+
+fn attributes(header: &Header) -> CommonAttrs {
+    let mtime = header
+        .mtime()
+        .map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime))
+        .unwrap_or(UNIX_EPOCH);
+    CommonAttrs::builder()
+        .perm(
+            header
+                .mode()
+                .map_or(DEFAULT_FILE_PERM, |mode| (mode & 0o7777) as u16),
+        )
+        .uid(header.uid().unwrap_or(0) as u32)
+        .gid(header.gid().unwrap_or(0) as u32)
+        .atime(mtime)
+        .mtime(mtime)
+        .ctime(mtime)
+        .crtime(mtime)
+        .build()
+}
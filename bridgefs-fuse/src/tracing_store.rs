@@ -0,0 +1,91 @@
+use std::{io::Write, sync::Mutex};
+
+use bridgefs_core::{
+    content_store::{ContentStore, ContentStoreError},
+    hash_pointer::HashPointer,
+    manifest::Manifest,
+};
+use tracing::{trace, warn};
+
+/// Where the access trace is written, one line per access
+type TraceSink = Mutex<Box<dyn Write + Send>>;
+
+/// Records every access to the wrapped store, for working out why a
+/// workload is slow rather than how much it did overall.
+///
+/// Each `add_content` and `get_content` is emitted as a `trace` event with
+/// the hash and length, and with `with_trace_file` also written out as a
+/// line like `get <hash> <length>`, in the order the accesses happened so
+/// the workload can be replayed.
+pub struct TracingContentStore<StoreT: ContentStore> {
+    inner: StoreT,
+    sink: Option<TraceSink>,
+}
+
+impl<StoreT: ContentStore> TracingContentStore<StoreT> {
+    pub fn new(inner: StoreT) -> Self {
+        Self { inner, sink: None }
+    }
+
+    /// Also writes each access to `sink`
+    pub fn with_trace_file(mut self, sink: impl Write + Send + 'static) -> Self {
+        self.sink = Some(Mutex::new(Box::new(sink)));
+        self
+    }
+
+    fn record(&self, operation: &str, hash: &HashPointer, length: usize) {
+        trace!(operation, %hash, length, "store access");
+        if let Some(sink) = &self.sink {
+            let mut sink = sink.lock().unwrap();
+            // A trace that stops short is better than failing the access
+            if let Err(e) = writeln!(sink, "{operation} {hash} {length}").and_then(|_| sink.flush())
+            {
+                warn!(%e, "failed to write store access trace");
+            }
+        }
+    }
+}
+
+impl<StoreT: ContentStore> ContentStore for TracingContentStore<StoreT> {
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        let hash = self.inner.add_content(content)?;
+        self.record("add", &hash, content.len());
+        Ok(hash)
+    }
+
+    fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        let content = self.inner.get_content(hash);
+        self.record("get", hash, content.len());
+        content
+    }
+
+    fn try_get_content(&self, hash: &HashPointer) -> Result<Vec<u8>, ContentStoreError> {
+        let content = self.inner.try_get_content(hash)?;
+        self.record("get", hash, content.len());
+        Ok(content)
+    }
+
+    fn content_hash(&self, content: &[u8]) -> Option<HashPointer> {
+        self.inner.content_hash(content)
+    }
+
+    fn add_content_under(
+        &mut self,
+        hash: &HashPointer,
+        content: &[u8],
+    ) -> Result<bool, ContentStoreError> {
+        let stored = self.inner.add_content_under(hash, content)?;
+        if stored {
+            self.record("add", hash, content.len());
+        }
+        Ok(stored)
+    }
+
+    fn evict_unreferenced(&mut self, manifest: &Manifest) {
+        self.inner.evict_unreferenced(manifest)
+    }
+
+    fn is_writable(&self) -> bool {
+        self.inner.is_writable()
+    }
+}
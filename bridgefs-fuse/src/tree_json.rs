@@ -0,0 +1,89 @@
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bridgefs_core::{
+    bridgefs::BridgeFS,
+    content_store::ContentStore,
+    file_record::Record,
+    hash_pointer::{HashPointer, TypedHashPointerReference},
+    index::INodeIndex,
+    response::WalkEntry,
+};
+
+/// Writes the tree to `out` as a JSON array with one object per entry,
+/// in `BridgeFS::walk` order, for scripting and diffing without a mount.
+///
+/// Each object holds the entry's `path`, `inode`, `kind`, `size`, `perm`,
+/// `uid`, `gid`, its times as seconds since the epoch, and for files the
+/// `content_hash` of its data rather than the data itself. Names that are
+/// not UTF-8 are written lossily.
+pub fn write_tree_json<IndexHashT: TypedHashPointerReference<INodeIndex>, StoreT: ContentStore>(
+    bridgefs: &mut BridgeFS<IndexHashT, StoreT>,
+    mut out: impl Write,
+) -> std::io::Result<()> {
+    let entries = bridgefs.walk().map_err(std::io::Error::other)?;
+    out.write_all(b"[")?;
+    for (i, WalkEntry { path, record }) in entries.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        let attrs = record.inner.common_attrs();
+        let (kind, size, content_hash) = match &record.inner {
+            Record::File(file) => (
+                "file",
+                file.size,
+                string(&HashPointer::from(&file.content_hash).to_string()),
+            ),
+            Record::Directory(directory) => ("directory", directory.size() as u64, "null".into()),
+        };
+        write!(
+            out,
+            "\n  {{\"path\": {}, \"inode\": {}, \"kind\": \"{kind}\", \"size\": {size}, \
+             \"perm\": {}, \"uid\": {}, \"gid\": {}, \"atime\": {}, \"mtime\": {}, \
+             \"ctime\": {}, \"crtime\": {}, \"content_hash\": {content_hash}}}",
+            string(&path.to_string_lossy()),
+            record.inode.get(),
+            attrs.perm,
+            attrs.uid,
+            attrs.gid,
+            seconds(attrs.atime),
+            seconds(attrs.mtime),
+            seconds(attrs.ctime),
+            seconds(attrs.crtime),
+        )?;
+    }
+    out.write_all(b"\n]\n")?;
+    out.flush()
+}
+
+/// `value` as a quoted JSON string
+fn string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `time` as a JSON number of seconds, negative before the epoch
+fn seconds(time: SystemTime) -> String {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => format!("{}.{:09}", after.as_secs(), after.subsec_nanos()),
+        Err(before) => {
+            let before = before.duration();
+            format!("-{}.{:09}", before.as_secs(), before.subsec_nanos())
+        }
+    }
+}
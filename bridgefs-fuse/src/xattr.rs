@@ -0,0 +1,44 @@
+use std::ffi::OsStr;
+
+use bridgefs_core::{file_record::Record, hash_pointer::HashPointer, response::INodeResponse};
+use libc::c_int;
+
+pub const CONTENT_HASH_XATTR: &str = "user.bridgefs.content_hash";
+pub const INODE_XATTR: &str = "user.bridgefs.inode";
+
+/// Synthesizes the read-only attributes bridgefs exposes for integrity
+/// tooling; nothing is stored, so these never show up in `listxattr`
+pub fn virtual_xattr(record: &INodeResponse<Record, Record>, name: &OsStr) -> Option<Vec<u8>> {
+    let name = name.to_str()?;
+    match (name, &record.inner) {
+        (CONTENT_HASH_XATTR, Record::File(file_record)) => Some(
+            HashPointer::from(&file_record.content_hash)
+                .to_string()
+                .into_bytes(),
+        ),
+        (INODE_XATTR, _) => Some(record.inode.get().to_string().into_bytes()),
+        _ => None,
+    }
+}
+
+/// What `getxattr` and `listxattr` hand back to the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XattrReply {
+    /// How big a buffer the value needs, for a call probing with no buffer
+    Size(u32),
+    Data(Vec<u8>),
+}
+
+/// Answers a call offering a buffer of `size` bytes for `value`. The kernel
+/// first probes with a size of zero to learn the length, then calls again
+/// with a real buffer, which fails with `ERANGE` if the value has since
+/// outgrown it.
+pub fn xattr_reply(value: Vec<u8>, size: u32) -> Result<XattrReply, c_int> {
+    if size == 0 {
+        Ok(XattrReply::Size(value.len() as u32))
+    } else if value.len() > size as usize {
+        Err(libc::ERANGE)
+    } else {
+        Ok(XattrReply::Data(value))
+    }
+}
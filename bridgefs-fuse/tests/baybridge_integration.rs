@@ -0,0 +1,606 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use baybridge::models::{ContentBlock, Name, Value};
+use bridgefs_core::{
+    bridgefs::BridgeFS,
+    content_store::{ContentStore, ContentStoreError, InMemoryContentStore},
+    file_record::CommonAttrs,
+    hash_pointer::{HashPointer, HashPointerReference},
+    manifest::ReferenceCounting,
+    response::FileOperationError,
+};
+use bridgefs_fuse::{
+    BridgeFSFuse,
+    baybridge_adapter::{BaybridgeActions, BaybridgeAdapter, BaybridgeError, ReadOnlyActions},
+    fuse_file_ext::FuseErrorExt,
+    fuse_store_ext::FuseStoreExt,
+    retry::RetryPolicy,
+};
+use fuser::{FUSE_ROOT_ID, Filesystem};
+
+/// Stands in for a baybridge node, keeping everything in memory; clones
+/// share the same node
+#[derive(Default, Clone)]
+struct FakeActions {
+    immutable: Arc<Mutex<HashMap<HashPointer, Vec<u8>>>>,
+    names: Arc<Mutex<HashMap<Name, Value>>>,
+    /// How many upcoming calls fail as if the network dropped, with a
+    /// message that mentions "not found" without being about the name
+    failures: Arc<AtomicUsize>,
+    calls: Arc<AtomicUsize>,
+    /// A name another writer moves on the next immutable write, as if it
+    /// committed while a mutation here was in flight
+    drift: Arc<Mutex<Option<(Name, Value)>>>,
+}
+
+impl FakeActions {
+    fn call(&self) -> Result<(), BaybridgeError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let failures = self.failures.load(Ordering::SeqCst);
+        if failures > 0 {
+            self.failures.store(failures - 1, Ordering::SeqCst);
+            return Err(BaybridgeError::classify("peer not found"));
+        }
+        Ok(())
+    }
+}
+
+impl BaybridgeActions for FakeActions {
+    async fn set_immutable(
+        &self,
+        content_block: ContentBlock,
+    ) -> Result<HashPointer, BaybridgeError> {
+        self.call()?;
+        if let Some((name, value)) = self.drift.lock().unwrap().take() {
+            self.names.lock().unwrap().insert(name, value);
+        }
+        let hash = InMemoryContentStore::default()
+            .add_content(&content_block.data)
+            .unwrap();
+        self.immutable
+            .lock()
+            .unwrap()
+            .insert(hash.clone(), content_block.data);
+        Ok(hash)
+    }
+
+    async fn get_immutable(&self, hash: &HashPointer) -> Result<ContentBlock, BaybridgeError> {
+        self.call()?;
+        match self.immutable.lock().unwrap().get(hash) {
+            Some(data) => Ok(ContentBlock {
+                data: data.clone(),
+                references: Vec::new(),
+            }),
+            None => Err(BaybridgeError::permanent("content not found")),
+        }
+    }
+
+    async fn set(&self, name: &Name, value: Value) -> Result<(), BaybridgeError> {
+        self.call()?;
+        self.names.lock().unwrap().insert(name.clone(), value);
+        Ok(())
+    }
+
+    async fn get_mine(&self, name: &Name) -> Result<Value, BaybridgeError> {
+        self.call()?;
+        match self.names.lock().unwrap().get(name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(BaybridgeError::not_found("name not found")),
+        }
+    }
+}
+
+#[test]
+fn test_named_references_are_independent() {
+    let adapter = BaybridgeAdapter::new(FakeActions::default());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let other_root: HashPointer = adapter.content_store().add_content(b"other root").unwrap();
+
+    let mut home = adapter.hash_pointer_reference("home", empty_root_dir.clone());
+    let mut projects = adapter.hash_pointer_reference("projects", empty_root_dir.clone());
+
+    home.set(&other_root);
+
+    assert_eq!(home.get(), other_root);
+    assert_eq!(projects.get(), HashPointer::from(&empty_root_dir));
+
+    // A fresh reference to the same name sees the earlier write
+    let mut home_again = adapter.hash_pointer_reference("home", empty_root_dir);
+    assert_eq!(home_again.get(), other_root);
+}
+
+#[test]
+fn test_read_only_actions_refuse_mutations() {
+    let actions = FakeActions::default();
+    let writer = BaybridgeAdapter::new(actions.clone());
+    let empty_root_dir = writer.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        writer.hash_pointer_reference("home", empty_root_dir.clone()),
+        writer.content_store(),
+        ReferenceCounting::Eager,
+    );
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(file.inode, 0, b"Hello, BridgeFS!")
+        .expect("Failed to write data");
+
+    let reader = BaybridgeAdapter::new(ReadOnlyActions(actions));
+    assert!(!reader.content_store().is_writable());
+    let mut bridgefs = BridgeFS::new(
+        reader.hash_pointer_reference("home", empty_root_dir),
+        reader.content_store(),
+        ReferenceCounting::Eager,
+    );
+
+    let read_result = bridgefs.read_file_data_by_inode(file.inode, 0, 1024);
+    assert!(read_result.is_ok());
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+
+    let write_result = bridgefs.write_to_file(file.inode, 0, b"Goodbye");
+    assert!(write_result.is_err());
+    let error = write_result.unwrap_err();
+    assert_eq!(error, FileOperationError::ReadOnly);
+    assert_eq!(error.to_errno(), libc::EROFS);
+    let create_result =
+        bridgefs.create_directory(FUSE_ROOT_ID.into(), "dir".into(), CommonAttrs::directory());
+    assert_eq!(create_result.unwrap_err(), FileOperationError::ReadOnly);
+}
+
+fn quick_retries() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 3,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(10),
+    }
+}
+
+#[test]
+fn test_transient_failures_are_retried() {
+    let actions = FakeActions::default();
+    let adapter = BaybridgeAdapter::new(actions.clone()).with_retry_policy(quick_retries());
+
+    actions.failures.store(2, Ordering::SeqCst);
+    let hash = adapter.content_store().add_content(b"flaky");
+    assert!(hash.is_ok());
+    assert_eq!(actions.failures.load(Ordering::SeqCst), 0);
+    assert_eq!(actions.calls.load(Ordering::SeqCst), 3);
+
+    actions.failures.store(2, Ordering::SeqCst);
+    let content = adapter.content_store().get_content(&hash.unwrap());
+    assert_eq!(content, b"flaky");
+}
+
+#[test]
+fn test_baybridge_errors_are_classified_by_kind() {
+    let timeout = BaybridgeError::classify("request timed out");
+    assert!(timeout.is_transient());
+    let reset = BaybridgeError::classify("Connection reset by peer");
+    assert!(reset.is_transient());
+
+    let missing = BaybridgeError::classify_lookup("Name not found");
+    assert!(missing.is_not_found());
+    assert!(!missing.is_transient());
+    // Only a name lookup can tell the name is missing
+    let unreachable = BaybridgeError::classify("route not found");
+    assert!(unreachable.is_transient());
+    assert!(!unreachable.is_not_found());
+    assert!(BaybridgeError::classify_lookup("connection reset").is_transient());
+    let undecodable = BaybridgeError::classify("failed to decode content block");
+    assert!(!undecodable.is_transient());
+    let unsigned = BaybridgeError::classify("invalid signature");
+    assert!(!unsigned.is_transient());
+
+    // The classification carries over to the content store layer
+    assert!(ContentStoreError::from(timeout).is_transient());
+    assert!(!ContentStoreError::from(undecodable).is_transient());
+    assert!(!ContentStoreError::CapacityExceeded.is_transient());
+    assert!(!ContentStoreError::ReadOnly.is_transient());
+}
+
+#[test]
+fn test_retries_stop_at_logical_errors() {
+    let attempts = |error: ContentStoreError| {
+        let calls = AtomicUsize::new(0);
+        let result: Result<(), _> = quick_retries().retry(|| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(error)
+        });
+        assert_eq!(result, Err(error));
+        calls.into_inner()
+    };
+    assert_eq!(
+        attempts(ContentStoreError::Unavailable { transient: true }),
+        3
+    );
+    assert_eq!(
+        attempts(ContentStoreError::Unavailable { transient: false }),
+        1
+    );
+    assert_eq!(attempts(ContentStoreError::CapacityExceeded), 1);
+}
+
+#[test]
+fn test_missing_name_is_not_retried() {
+    let actions = FakeActions::default();
+    let adapter = BaybridgeAdapter::new(actions.clone()).with_retry_policy(quick_retries());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut reference = adapter.hash_pointer_reference("home", empty_root_dir.clone());
+
+    actions.calls.store(0, Ordering::SeqCst);
+    assert_eq!(reference.get(), HashPointer::from(&empty_root_dir));
+    // One lookup that finds nothing, then the default is written back
+    assert_eq!(actions.calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_failed_name_read_does_not_reset_to_empty() {
+    let actions = FakeActions::default();
+    let adapter = BaybridgeAdapter::new(actions.clone()).with_retry_policy(quick_retries());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let existing_root: HashPointer = adapter.content_store().add_content(b"existing").unwrap();
+    let mut reference = adapter.hash_pointer_reference("home", empty_root_dir);
+    reference.set(&existing_root);
+
+    actions
+        .failures
+        .store(quick_retries().max_attempts as usize, Ordering::SeqCst);
+    let result = reference.try_get();
+    assert_eq!(
+        result,
+        Err(ContentStoreError::Unavailable { transient: true })
+    );
+
+    assert_eq!(actions.failures.load(Ordering::SeqCst), 0);
+    assert_eq!(reference.get(), existing_root);
+}
+
+#[test]
+fn test_unreadable_root_pointer_fails_the_operation() {
+    let actions = FakeActions::default();
+    let adapter = BaybridgeAdapter::new(actions.clone()).with_retry_policy(quick_retries());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        adapter.hash_pointer_reference("home", empty_root_dir),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    let root = bridgefs.current_root();
+
+    actions
+        .failures
+        .store(quick_retries().max_attempts as usize, Ordering::SeqCst);
+    let lookup = bridgefs.lookup_record_by_inode(FUSE_ROOT_ID.into());
+    let error = lookup.unwrap_err();
+    assert_eq!(error, FileOperationError::Unavailable);
+    assert_eq!(error.to_errno(), libc::EIO);
+    assert_eq!(published_root(&actions), Some(root));
+
+    // A value that is not a root pointer at all is an error too
+    actions.names.lock().unwrap().insert(
+        Name::new("home".to_string()),
+        Value::new(b"garbage".to_vec()),
+    );
+    assert!(
+        bridgefs
+            .lookup_record_by_inode(FUSE_ROOT_ID.into())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_rename_filesystem_moves_root_to_new_name() {
+    let adapter = BaybridgeAdapter::new(FakeActions::default()).with_retry_policy(quick_retries());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        adapter.hash_pointer_reference("staging", empty_root_dir.clone()),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(file.inode, 0, b"promoted")
+        .expect("Failed to write data");
+    let staging_root = adapter
+        .hash_pointer_reference("staging", empty_root_dir.clone())
+        .get();
+
+    assert!(adapter.rename_filesystem("staging", "home").is_ok());
+    let mut home = adapter.hash_pointer_reference("home", empty_root_dir.clone());
+    assert_eq!(home.get(), staging_root);
+    let mut bridgefs = BridgeFS::new(home, adapter.content_store(), ReferenceCounting::Eager);
+    assert_eq!(
+        bridgefs.read_entire_file(file.inode),
+        Ok(b"promoted".to_vec())
+    );
+
+    let missing = adapter.rename_filesystem("missing", "elsewhere");
+    assert!(missing.unwrap_err().is_not_found());
+}
+
+#[test]
+fn test_rename_filesystem_takes_the_held_back_root() {
+    let adapter = BaybridgeAdapter::new(FakeActions::default())
+        .with_flush_interval(Duration::from_secs(3600));
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        adapter.hash_pointer_reference("staging", empty_root_dir.clone()),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(file.inode, 0, b"promoted")
+        .expect("Failed to write data");
+    // Nowhere near the interval, so the latest root is still held back
+    let staging_root = bridgefs.current_root();
+
+    assert!(adapter.rename_filesystem("staging", "home").is_ok());
+    let mut home = adapter.hash_pointer_reference("home", empty_root_dir);
+    assert_eq!(home.get(), staging_root);
+    let mut bridgefs = BridgeFS::new(home, adapter.content_store(), ReferenceCounting::Eager);
+    assert_eq!(
+        bridgefs.read_entire_file(file.inode),
+        Ok(b"promoted".to_vec())
+    );
+}
+
+/// The root another client would read from the node right now
+fn published_root(actions: &FakeActions) -> Option<HashPointer> {
+    let value = actions.names.lock().unwrap().values().next().cloned()?;
+    let (root, _) =
+        bincode::decode_from_slice(value.as_bytes(), bincode::config::standard()).unwrap();
+    Some(root)
+}
+
+#[test]
+fn test_root_missing_from_the_node_is_reported_as_corrupt() {
+    let adapter = BaybridgeAdapter::new(FakeActions::default());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    // The name points at an index that was never uploaded
+    let missing = InMemoryContentStore::default()
+        .add_content(b"never uploaded")
+        .unwrap();
+    adapter
+        .hash_pointer_reference("home", empty_root_dir)
+        .set(&missing);
+
+    let mut fuse = BridgeFSFuse::from_baybridge(&adapter, "home");
+    assert_eq!(fuse.ensure_root().unwrap_err(), FileOperationError::Corrupt);
+}
+
+#[test]
+fn test_pending_root_is_published_after_the_flush_interval() {
+    let actions = FakeActions::default();
+    let now = Arc::new(Mutex::new(Instant::now()));
+    let clock = now.clone();
+    let adapter = BaybridgeAdapter::new(actions.clone())
+        .with_flush_interval(Duration::from_secs(30))
+        .with_clock(move || *clock.lock().unwrap());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        adapter.hash_pointer_reference("home", empty_root_dir),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    let initial = bridgefs.current_root();
+    assert_eq!(published_root(&actions), Some(initial.clone()));
+
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    // Held back from the node, but this instance already sees it
+    assert_eq!(published_root(&actions), Some(initial.clone()));
+    assert_ne!(bridgefs.current_root(), initial);
+
+    *now.lock().unwrap() += Duration::from_secs(20);
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "other".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    assert_eq!(published_root(&actions), Some(initial.clone()));
+
+    // The interval counts from the oldest unpublished change
+    *now.lock().unwrap() += Duration::from_secs(10);
+    let latest = bridgefs.current_root();
+    assert_eq!(published_root(&actions), Some(latest));
+}
+
+#[test]
+fn test_failed_flush_holds_the_root_back_for_the_next_tick() {
+    let actions = FakeActions::default();
+    let now = Arc::new(Mutex::new(Instant::now()));
+    let clock = now.clone();
+    let adapter = BaybridgeAdapter::new(actions.clone())
+        .with_retry_policy(quick_retries())
+        .with_flush_interval(Duration::from_secs(30))
+        .with_clock(move || *clock.lock().unwrap());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        adapter.hash_pointer_reference("home", empty_root_dir),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    let initial = bridgefs.current_root();
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let latest = bridgefs.current_root();
+
+    // An outage that outlasts every retry of the flush
+    *now.lock().unwrap() += Duration::from_secs(30);
+    actions.failures.store(3, Ordering::SeqCst);
+    assert_eq!(bridgefs.current_root(), latest);
+    assert_eq!(published_root(&actions), Some(initial));
+
+    // The next tick tries again, and the mount kept working throughout
+    assert_eq!(bridgefs.current_root(), latest);
+    assert_eq!(published_root(&actions), Some(latest));
+}
+
+#[test]
+fn test_idle_mount_publishes_a_pending_root_on_its_own() {
+    let actions = FakeActions::default();
+    let adapter =
+        BaybridgeAdapter::new(actions.clone()).with_flush_interval(Duration::from_millis(20));
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        adapter.hash_pointer_reference("home", empty_root_dir),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    let initial = bridgefs.current_root();
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let latest = bridgefs.current_root();
+    assert_eq!(published_root(&actions), Some(initial));
+
+    // Nothing touches the filesystem again; only the flusher can publish it
+    thread::scope(|scope| {
+        let (stop, stopped) = mpsc::channel();
+        let flusher = &adapter;
+        scope.spawn(move || flusher.flush_until(&stopped));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while published_root(&actions) != Some(latest.clone()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        stop.send(()).unwrap();
+    });
+    assert_eq!(published_root(&actions), Some(latest));
+}
+
+#[test]
+fn test_commit_publishes_a_pending_root() {
+    let actions = FakeActions::default();
+    let adapter =
+        BaybridgeAdapter::new(actions.clone()).with_flush_interval(Duration::from_secs(3600));
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        adapter.hash_pointer_reference("home", empty_root_dir),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let root: HashPointer = bridgefs.commit().unwrap().into();
+
+    assert_eq!(published_root(&actions), Some(root));
+}
+
+#[test]
+fn test_concurrent_add_is_not_clobbered() {
+    let actions = FakeActions::default();
+    let adapter = BaybridgeAdapter::new(actions.clone());
+    let empty_root_dir = adapter.content_store().empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        adapter.hash_pointer_reference("home", empty_root_dir.clone()),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    // Reading the root writes the empty one under the name
+    bridgefs.current_root();
+    let (name, before) = actions
+        .names
+        .lock()
+        .unwrap()
+        .iter()
+        .next()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .unwrap();
+
+    // Another writer adds a file, then the node is wound back so this
+    // instance only sees it land in the middle of its own create. The move
+    // shows up at the compare; one racing the set itself would still be
+    // overwritten, as baybridge has no conditional write
+    let mut other = BridgeFS::new(
+        adapter.hash_pointer_reference("home", empty_root_dir),
+        adapter.content_store(),
+        ReferenceCounting::Eager,
+    );
+    let theirs = other
+        .create_file(FUSE_ROOT_ID.into(), "theirs".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    other.write_to_file(theirs.inode, 0, b"concurrent").unwrap();
+    let moved = actions
+        .names
+        .lock()
+        .unwrap()
+        .insert(name.clone(), before)
+        .unwrap();
+    *actions.drift.lock().unwrap() = Some((name, moved));
+
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "mine".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    assert!(actions.drift.lock().unwrap().is_none());
+
+    let names: Vec<String> = bridgefs
+        .directory_entry_names(FUSE_ROOT_ID.into())
+        .unwrap()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    assert_eq!(names, ["mine", "theirs"]);
+    let theirs = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &"theirs".into())
+        .unwrap();
+    assert_eq!(
+        bridgefs.read_entire_file(theirs.inode),
+        Ok(b"concurrent".to_vec())
+    );
+    assert_eq!(published_root(&actions), Some(bridgefs.current_root()));
+}
+
+#[test]
+fn test_destroy_persists_the_tree_for_the_next_mount() {
+    let actions = FakeActions::default();
+    let adapter =
+        BaybridgeAdapter::new(actions.clone()).with_flush_interval(Duration::from_secs(3600));
+    let mut fuse = BridgeFSFuse::from_baybridge(&adapter, "home");
+    fuse.ensure_root().unwrap();
+    let (file, fh) = fuse
+        .create_tmpfile(FUSE_ROOT_ID, CommonAttrs::default())
+        .expect("Failed to create tmpfile");
+    fuse.write_data(file.inode.get(), 0, b"durable")
+        .expect("Failed to write");
+    fuse.link_entry(file.inode.get(), FUSE_ROOT_ID, OsStr::new("file"))
+        .expect("Failed to link");
+    fuse.release_handle(fh);
+    // Left open at unmount, as a forced unmount would, and never named
+    let (scratch, _) = fuse
+        .create_tmpfile(FUSE_ROOT_ID, CommonAttrs::default())
+        .expect("Failed to create tmpfile");
+    // Still held back from the node
+    let unpublished = published_root(&actions);
+
+    fuse.destroy();
+    assert_ne!(published_root(&actions), unpublished);
+
+    let adapter = BaybridgeAdapter::new(actions.clone());
+    let mut remounted = BridgeFSFuse::from_baybridge(&adapter, "home");
+    let found = remounted
+        .lookup_entry(FUSE_ROOT_ID, OsStr::new("file"))
+        .expect("File should survive the remount");
+    let data = remounted.read_data(found.inode.get(), 0, 64).unwrap();
+    assert_eq!(data.datablock.data, b"durable");
+    assert_eq!(
+        remounted.file_attr(scratch.inode.get()).unwrap_err(),
+        FileOperationError::NotFound
+    );
+}
@@ -1,11 +1,61 @@
-use std::ffi::OsStr;
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeSet,
+    ffi::OsStr,
+    fmt::Debug,
+    io::{Read, Write},
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
 
 use bridgefs_core::{
-    bridgefs::BridgeFS, content_store::InMemoryContentStore, file_record::CommonAttrs,
-    hash_pointer::InMemoryHashPointerReference, response::FileOperationError,
+    bridgefs::{BridgeFS, CloneTimes, SizeLimitPolicy},
+    checkpoint_log::CheckpointLog,
+    compression::Compression,
+    content_store::{
+        BoundedInMemoryContentStore, ContentStore, ContentStoreError, InMemoryContentStore,
+        ParsingContentStoreExt,
+    },
+    data_block::DataBlock,
+    file_record::{
+        CommonAttrs, DEFAULT_DIRECTORY_PERM, DEFAULT_FILE_PERM, DefaultAttrs, DirectoryRecord,
+        Record, UF_IMMUTABLE,
+    },
+    filename::FilenameEncoding,
+    hash_pointer::{
+        Blake3, HashPointer, HashPointerReference, Hasher, InMemoryHashPointerReference,
+        TypedHashPointer,
+    },
+    index::INodeIndex,
+    journal::{InMemoryJournal, Journal},
+    manifest::{ManifestDiscrepancy, ReferenceCounting},
+    response::FileOperationError,
+    snapshot::Snapshot,
+};
+use bridgefs_fuse::{
+    BridgeFSConfig, BridgeFSFuse, EntryOrder,
+    backup::{RestoreError, backup, restore},
+    fuse_file_ext::{FuseAttrsExt, FuseErrorExt, FuseFileResponseExt, SetAttr},
+    fuse_store_ext::FuseStoreExt,
+    invalidation::{Invalidation, Invalidator},
+    tar_import::{ImportError, import_tar},
+    tracing_store::TracingContentStore,
+    tree_json::write_tree_json,
+    xattr::{CONTENT_HASH_XATTR, INODE_XATTR, XattrReply, virtual_xattr},
+};
+use fuser::{FUSE_ROOT_ID, FileType, TimeOrNow, consts::FOPEN_DIRECT_IO};
+use tracing::{
+    Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id},
+};
+use tracing_subscriber::{
+    Registry,
+    layer::{Context, Layer, SubscriberExt},
 };
-use bridgefs_fuse::fuse_store_ext::FuseStoreExt;
-use fuser::FUSE_ROOT_ID;
 
 static EMPTY_FILENAME: &str = "empty_file";
 static FILENAME: &str = "file";
@@ -15,13 +65,158 @@ static EMPTY_DIRNAME: &str = "empty_dir";
 
 fn empty_in_memory_bridgefs() -> BridgeFS<InMemoryHashPointerReference, InMemoryContentStore> {
     let mut store = InMemoryContentStore::default();
-    let initial_index_hash = store.empty_root_dir();
+    let initial_index_hash = store.empty_root_dir().unwrap();
     let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
-    BridgeFS::new(pointer, store)
+    BridgeFS::new(pointer, store, ReferenceCounting::Eager)
 }
 
 fn in_memory_bridgefs() -> BridgeFS<InMemoryHashPointerReference, InMemoryContentStore> {
     let mut bridgefs = empty_in_memory_bridgefs();
+    populate_fixture(&mut bridgefs);
+    bridgefs
+}
+
+type ReadHook = Box<dyn FnMut(&HashPointer)>;
+
+/// Observes the traffic going through an `InstrumentedContentStore`
+#[derive(Default, Clone)]
+struct StoreProbe {
+    reads: Rc<RefCell<Vec<HashPointer>>>,
+    writes: Rc<Cell<usize>>,
+    on_read: Rc<RefCell<Option<ReadHook>>>,
+}
+
+/// Records every hash fetched through `get_content` and counts writes
+#[derive(Default)]
+struct InstrumentedContentStore {
+    inner: InMemoryContentStore,
+    probe: StoreProbe,
+}
+
+impl ContentStore for InstrumentedContentStore {
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        self.probe.writes.set(self.probe.writes.get() + 1);
+        self.inner.add_content(content)
+    }
+
+    fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        self.probe.reads.borrow_mut().push(hash.clone());
+        if let Some(on_read) = self.probe.on_read.borrow_mut().as_mut() {
+            on_read(hash);
+        }
+        self.inner.get_content(hash)
+    }
+
+    fn content_hash(&self, content: &[u8]) -> Option<HashPointer> {
+        self.inner.content_hash(content)
+    }
+}
+
+fn instrumented_bridgefs() -> (
+    BridgeFS<InMemoryHashPointerReference, InstrumentedContentStore>,
+    StoreProbe,
+) {
+    let mut store = InstrumentedContentStore::default();
+    let probe = store.probe.clone();
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    populate_fixture(&mut bridgefs);
+    probe.reads.borrow_mut().clear();
+    (bridgefs, probe)
+}
+
+/// Lets several `BridgeFS` instances share one underlying store
+#[derive(Default, Clone)]
+struct SharedContentStore {
+    inner: Rc<RefCell<InMemoryContentStore>>,
+}
+
+impl ContentStore for SharedContentStore {
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        self.inner.borrow_mut().add_content(content)
+    }
+
+    fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        self.inner.borrow().get_content(hash)
+    }
+
+    fn content_hash(&self, content: &[u8]) -> Option<HashPointer> {
+        self.inner.borrow().content_hash(content)
+    }
+
+    fn add_content_under(
+        &mut self,
+        hash: &HashPointer,
+        content: &[u8],
+    ) -> Result<bool, ContentStoreError> {
+        self.inner.borrow_mut().add_content_under(hash, content)
+    }
+}
+
+/// Acknowledges every write but, once `dropping` is set, keeps none of them
+#[derive(Default)]
+struct LossyContentStore {
+    inner: InMemoryContentStore,
+    dropping: Rc<Cell<bool>>,
+}
+
+impl ContentStore for LossyContentStore {
+    fn add_content(&mut self, content: &[u8]) -> Result<HashPointer, ContentStoreError> {
+        if self.dropping.get() {
+            return Ok(Blake3::hash(content));
+        }
+        self.inner.add_content(content)
+    }
+
+    fn get_content(&self, hash: &HashPointer) -> Vec<u8> {
+        self.inner.get_content(hash)
+    }
+}
+
+/// A root pointer shared between writers, which runs `before_swap` ahead of
+/// the first compare-and-swap to simulate another writer racing in
+struct RacingHashPointerReference {
+    value: Rc<RefCell<HashPointer>>,
+    before_swap: Option<Box<dyn FnOnce()>>,
+    swap_attempts: Rc<Cell<usize>>,
+}
+
+impl RacingHashPointerReference {
+    fn new(value: Rc<RefCell<HashPointer>>) -> Self {
+        Self {
+            value,
+            before_swap: None,
+            swap_attempts: Rc::default(),
+        }
+    }
+}
+
+impl HashPointerReference for RacingHashPointerReference {
+    fn set(&mut self, value: &HashPointer) {
+        *self.value.borrow_mut() = value.clone();
+    }
+
+    fn get(&mut self) -> HashPointer {
+        self.value.borrow().clone()
+    }
+
+    fn set_if(&mut self, expected: &HashPointer, value: &HashPointer) -> bool {
+        self.swap_attempts.set(self.swap_attempts.get() + 1);
+        if let Some(before_swap) = self.before_swap.take() {
+            before_swap();
+        }
+        if *self.value.borrow() != *expected {
+            return false;
+        }
+        self.set(value);
+        true
+    }
+}
+
+fn populate_fixture<StoreT: ContentStore>(
+    bridgefs: &mut BridgeFS<InMemoryHashPointerReference, StoreT>,
+) {
     bridgefs
         .create_file(
             FUSE_ROOT_ID.into(),
@@ -39,7 +234,11 @@ fn in_memory_bridgefs() -> BridgeFS<InMemoryHashPointerReference, InMemoryConten
         .expect("Failed to write data");
 
     let dir = bridgefs
-        .create_directory(FUSE_ROOT_ID.into(), DIRNAME.into(), CommonAttrs::default())
+        .create_directory(
+            FUSE_ROOT_ID.into(),
+            DIRNAME.into(),
+            CommonAttrs::directory(),
+        )
         .expect("Failed to create directory");
     let file_under_dir = bridgefs
         .create_file(dir.inode, FILE_UNDER_DIR.into(), CommonAttrs::default())
@@ -52,10 +251,9 @@ fn in_memory_bridgefs() -> BridgeFS<InMemoryHashPointerReference, InMemoryConten
         .create_directory(
             FUSE_ROOT_ID.into(),
             EMPTY_DIRNAME.into(),
-            CommonAttrs::default(),
+            CommonAttrs::directory(),
         )
         .expect("Failed to create empty directory");
-    bridgefs
 }
 
 #[test]
@@ -68,6 +266,18 @@ fn test_lookup_by_name_missing_file() {
     assert_eq!(record.unwrap_err(), FileOperationError::NotFound);
 }
 
+#[test]
+fn test_lookup_by_name_missing_file_error_names_file() {
+    let mut bridgefs = empty_in_memory_bridgefs();
+    let filename: &OsStr = "nonexistent".as_ref();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &filename.into());
+
+    assert!(record.is_err());
+    let error = record.unwrap_err();
+    assert_eq!(error.kind, FileOperationError::NotFound);
+    assert!(error.to_string().contains("nonexistent"));
+}
+
 #[test]
 fn test_lookup_by_name_in_missing_parent_directory() {
     let mut bridgefs = empty_in_memory_bridgefs();
@@ -78,6 +288,21 @@ fn test_lookup_by_name_in_missing_parent_directory() {
     assert_eq!(record.unwrap_err(), FileOperationError::NotFound);
 }
 
+#[test]
+fn test_lookup_by_name_under_file_is_not_a_directory() {
+    let mut bridgefs = in_memory_bridgefs();
+    let file = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(file.is_ok());
+    let file_inode = file.unwrap().inode;
+
+    let record = bridgefs.lookup_record_by_name(file_inode, &"x".into());
+    assert!(record.is_err());
+    let error = record.unwrap_err();
+    assert_eq!(error, FileOperationError::NotADirectory);
+    assert_eq!(error.context.inode, Some(file_inode));
+    assert_eq!(error.to_errno(), libc::ENOTDIR);
+}
+
 #[test]
 fn test_lookup_by_inode_missing_file() {
     let mut bridgefs = empty_in_memory_bridgefs();
@@ -163,6 +388,76 @@ fn test_list_root_directory() {
     assert_eq!(names.len(), 6);
 }
 
+#[test]
+fn test_root_parent_entry_points_to_root() {
+    let mut bridgefs = in_memory_bridgefs();
+    let list_result = bridgefs.list_directory_by_inode(FUSE_ROOT_ID.into());
+    assert!(list_result.is_ok());
+    let entries = list_result.unwrap().entries;
+    let parent = entries.iter().find(|entry| entry.name == "..".into());
+    assert!(parent.is_some());
+    assert_eq!(parent.unwrap().record.inode, FUSE_ROOT_ID.into());
+}
+
+#[test]
+fn test_deeply_nested_directories() {
+    const DEPTH: usize = 10;
+    let mut bridgefs = empty_in_memory_bridgefs();
+    let mut chain = vec![FUSE_ROOT_ID.into()];
+    for level in 0..DEPTH {
+        let dir = bridgefs
+            .create_directory(
+                *chain.last().unwrap(),
+                format!("level{level}").as_str().into(),
+                CommonAttrs::directory(),
+            )
+            .expect("Failed to create directory");
+        chain.push(dir.inode);
+    }
+    let bottom = *chain.last().unwrap();
+    let file = bridgefs
+        .create_file(bottom, FILENAME.into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(file.inode, 0, b"Hello, BridgeFS!")
+        .expect("Failed to write data");
+
+    for level in 0..DEPTH {
+        let (parent, inode) = (chain[level], chain[level + 1]);
+        let name = format!("level{level}");
+        let record = bridgefs.lookup_record_by_name(parent, &name.as_str().into());
+        assert!(record.is_ok(), "level {level}");
+        assert_eq!(record.unwrap().inode, inode, "level {level}");
+
+        let child = if level + 1 < DEPTH {
+            format!("level{}", level + 1)
+        } else {
+            FILENAME.to_string()
+        };
+        let entries = bridgefs.list_directory_by_inode(inode).unwrap().entries;
+        let mut names: Vec<String> = entries.iter().map(|e| e.name.clone().into()).collect();
+        names.sort();
+        assert_eq!(names, vec![".".to_string(), "..".to_string(), child]);
+        let dotdot = entries.iter().find(|entry| entry.name == "..".into());
+        assert_eq!(dotdot.unwrap().record.inode, parent, "level {level}");
+        let dot = entries.iter().find(|entry| entry.name == ".".into());
+        assert_eq!(dot.unwrap().record.inode, inode, "level {level}");
+    }
+
+    let found = bridgefs.lookup_record_by_name(bottom, &FILENAME.into());
+    assert_eq!(found.unwrap().inode, file.inode);
+    let read_result = bridgefs.read_file_data_by_inode(file.inode, 0, 1024);
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+    let path: PathBuf = (0..DEPTH)
+        .map(|level| format!("level{level}"))
+        .chain([FILENAME.to_string()])
+        .collect();
+    assert_eq!(
+        bridgefs.path_of(file.inode).unwrap(),
+        PathBuf::from("/").join(path)
+    );
+}
+
 #[test]
 fn test_list_subdirectory() {
     let mut bridgefs = in_memory_bridgefs();
@@ -309,10 +604,3034 @@ fn test_create_file_already_exists() {
 }
 
 #[test]
-fn test_create_directory_already_exists() {
+fn test_create_or_open_reports_whether_it_created() {
+    let mut bridgefs = in_memory_bridgefs();
+    let (created, was_created) = bridgefs
+        .create_or_open(FUSE_ROOT_ID.into(), "new".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    assert!(was_created);
+    bridgefs
+        .write_to_file(created.inode, 0, b"kept")
+        .expect("Failed to write data");
+
+    let (opened, was_created) = bridgefs
+        .create_or_open(FUSE_ROOT_ID.into(), "new".into(), CommonAttrs::default())
+        .expect("Failed to open file");
+    assert!(!was_created);
+    assert_eq!(opened.inode, created.inode);
+    assert_eq!(bridgefs.read_entire_file(opened.inode).unwrap(), b"kept");
+}
+
+#[test]
+fn test_create_or_open_on_directory() {
     let mut bridgefs = in_memory_bridgefs();
     let result =
-        bridgefs.create_directory(FUSE_ROOT_ID.into(), DIRNAME.into(), CommonAttrs::default());
+        bridgefs.create_or_open(FUSE_ROOT_ID.into(), DIRNAME.into(), CommonAttrs::default());
+    assert_eq!(result.unwrap_err(), FileOperationError::IsADirectory);
+}
+
+#[test]
+fn test_new_entries_inherit_directory_default_attrs() {
+    let mut bridgefs = in_memory_bridgefs();
+    let shared = bridgefs
+        .create_directory(
+            FUSE_ROOT_ID.into(),
+            "shared".into(),
+            CommonAttrs::directory(),
+        )
+        .expect("Failed to create directory")
+        .inode;
+    let defaults = DefaultAttrs {
+        file_perm: Some(0o660),
+        directory_perm: Some(0o2770),
+        gid: Some(100),
+        ..DefaultAttrs::default()
+    };
+    bridgefs
+        .set_default_attrs(shared, Some(defaults.clone()))
+        .expect("Failed to set default attributes");
+
+    let attributes = CommonAttrs::builder()
+        .perm(0o644)
+        .uid(1000)
+        .gid(1000)
+        .build();
+    let file = bridgefs
+        .create_file(shared, "file".into(), attributes.clone())
+        .expect("Failed to create file");
+    let file_attrs = &file.inner.common_attrs;
+    assert_eq!(
+        (file_attrs.perm, file_attrs.uid, file_attrs.gid),
+        (0o660, 1000, 100)
+    );
+
+    // Subdirectories pass the defaults on in turn
+    let nested = bridgefs
+        .create_directory(shared, "nested".into(), attributes.clone())
+        .expect("Failed to create directory");
+    assert_eq!(nested.inner.common_attrs.perm, 0o2770);
+    assert_eq!(nested.inner.default_attrs, Some(defaults));
+    let nested_file = bridgefs
+        .create_file(nested.inode, "file".into(), attributes.clone())
+        .expect("Failed to create file");
+    assert_eq!(nested_file.inner.common_attrs.gid, 100);
+
+    // Outside the directory nothing changes
+    let outside = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "outside".into(), attributes)
+        .expect("Failed to create file");
+    assert_eq!(outside.inner.common_attrs.perm, 0o644);
+    assert_eq!(outside.inner.common_attrs.gid, 1000);
+}
+
+#[test]
+fn test_creation_order_lists_entries_as_they_were_created() {
+    let listed = |entry_order| -> Vec<String> {
+        let mut bridgefs = empty_in_memory_bridgefs();
+        for (i, name) in ["zeta", "alpha", "mid"].into_iter().enumerate() {
+            // Birth times are the caller's to pick, so they cannot be trusted
+            // to say which came first
+            let born = UNIX_EPOCH + Duration::from_secs(10 - i as u64);
+            let attributes = CommonAttrs::builder().crtime(born).build();
+            bridgefs
+                .create_file(FUSE_ROOT_ID.into(), name.into(), attributes)
+                .expect("Failed to create file");
+        }
+        let config = BridgeFSConfig {
+            entry_order,
+            ..BridgeFSConfig::default()
+        };
+        BridgeFSFuse::new(bridgefs, config)
+            .list_entries(FUSE_ROOT_ID)
+            .expect("Failed to list root")
+            .into_iter()
+            .map(|entry| entry.name.to_string())
+            .collect()
+    };
+
+    assert_eq!(
+        listed(EntryOrder::Name),
+        ["alpha", "mid", "zeta", ".", ".."]
+    );
+    assert_eq!(
+        listed(EntryOrder::Creation),
+        ["zeta", "alpha", "mid", ".", ".."]
+    );
+}
+
+#[test]
+fn test_open_reader_fetches_the_content_once() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .expect("Fixture file should exist");
+    let Record::File(record) = &file.inner else {
+        panic!("Expected a file");
+    };
+    let content_hash = HashPointer::from(&record.content_hash);
+    let mut reader = bridgefs.open_reader(file.inode).expect("Failed to open");
+
+    let mut chunk = [0; 4];
+    let mut content = Vec::new();
+    loop {
+        let read = reader.read(&mut chunk).expect("Failed to read");
+        if read == 0 {
+            break;
+        }
+        content.extend_from_slice(&chunk[..read]);
+    }
+    assert_eq!(content, b"Hello, BridgeFS!");
+    let fetched = probe.reads.borrow();
+    assert_eq!(
+        fetched.iter().filter(|&hash| *hash == content_hash).count(),
+        1
+    );
+}
+
+#[test]
+fn test_open_reader_streams_whole_file() {
+    let mut bridgefs = in_memory_bridgefs();
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .expect("Fixture file should exist");
+    let mut reader = bridgefs.open_reader(file.inode).expect("Failed to open");
+
+    // Small reads exercise the reader keeping its own position
+    let mut chunk = [0; 4];
+    let mut content = Vec::new();
+    loop {
+        let read = reader.read(&mut chunk).expect("Failed to read");
+        if read == 0 {
+            break;
+        }
+        content.extend_from_slice(&chunk[..read]);
+    }
+    assert_eq!(content, b"Hello, BridgeFS!");
+    assert_eq!(reader.position(), content.len() as u64);
+    assert_eq!(reader.read(&mut chunk).unwrap(), 0);
+
+    let directory = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    let result = bridgefs.open_reader(directory.unwrap().inode);
+    assert_eq!(
+        result.err().map(|e| e.kind),
+        Some(FileOperationError::IsADirectory)
+    );
+}
+
+#[test]
+fn test_mutations_invalidate_cached_entries() {
+    let config = BridgeFSConfig {
+        ttl: Duration::from_secs(60),
+        ..BridgeFSConfig::default()
+    };
+    let (invalidator, invalidations) = Invalidator::channel();
+    let mut fuse = BridgeFSFuse::new(in_memory_bridgefs(), config).with_invalidator(invalidator);
+    let file = fuse
+        .lookup_entry(FUSE_ROOT_ID, OsStr::new(FILENAME))
+        .unwrap();
+    let inode = file.inode.get();
+
+    fuse.link_entry(inode, FUSE_ROOT_ID, OsStr::new("alias"))
+        .expect("Failed to link");
+    assert_eq!(
+        invalidations.try_iter().collect::<Vec<_>>(),
+        vec![
+            Invalidation::Entry {
+                parent: FUSE_ROOT_ID,
+                name: "alias".into(),
+            },
+            Invalidation::Inode(FUSE_ROOT_ID),
+            Invalidation::Inode(inode),
+        ]
+    );
+    // Once invalidated, the kernel reads the directory afresh
+    let listed = fuse.list_entries(FUSE_ROOT_ID).unwrap();
+    assert!(listed.iter().any(|entry| entry.name == "alias".into()));
+
+    fuse.write_data(inode, 0, b"Howdy").unwrap();
+    assert_eq!(
+        invalidations.try_iter().collect::<Vec<_>>(),
+        vec![Invalidation::Inode(inode)]
+    );
+    // A failed mutation changes nothing to invalidate
+    assert!(fuse.write_data(12345, 0, b"missing").is_err());
+    assert_eq!(invalidations.try_iter().count(), 0);
+}
+
+#[test]
+fn test_tmpfile_is_linked_in_by_name() {
+    let mut fuse = BridgeFSFuse::new(in_memory_bridgefs(), BridgeFSConfig::default());
+    let (file, fh) = fuse
+        .create_tmpfile(FUSE_ROOT_ID, CommonAttrs::default())
+        .expect("Failed to create tmpfile");
+    assert_eq!(file.inner.nlink, 0);
+    let inode = file.inode.get();
+    fuse.write_data(inode, 0, b"staged")
+        .expect("Failed to write tmpfile");
+    // Nothing names it until it is linked
+    let listed = fuse.list_entries(FUSE_ROOT_ID).unwrap();
+    assert!(listed.iter().all(|entry| entry.record.inode != file.inode));
+
+    let linked = fuse
+        .link_entry(inode, FUSE_ROOT_ID, OsStr::new("published"))
+        .expect("Failed to link tmpfile");
+    assert_eq!(linked.inner.nlink, 1);
+    fuse.release_handle(fh);
+
+    let found = fuse
+        .lookup_entry(FUSE_ROOT_ID, OsStr::new("published"))
+        .expect("Linked tmpfile should be found by name");
+    assert_eq!(found.inode, file.inode);
+    let data = fuse.read_data(found.inode.get(), 0, 64).unwrap();
+    assert_eq!(data.datablock.data, b"staged");
+}
+
+#[test]
+fn test_unlinked_tmpfile_is_released_with_its_last_handle() {
+    let mut fuse = BridgeFSFuse::new(in_memory_bridgefs(), BridgeFSConfig::default());
+    let (file, fh) = fuse
+        .create_tmpfile(FUSE_ROOT_ID, CommonAttrs::default())
+        .expect("Failed to create tmpfile");
+    let inode = file.inode.get();
+    let (second_fh, _) = fuse.open_handle(inode, libc::O_RDWR).unwrap();
+
+    fuse.release_handle(fh);
+    assert!(fuse.file_attr(inode).is_ok());
+    fuse.release_handle(second_fh);
+    assert_eq!(
+        fuse.file_attr(inode).unwrap_err(),
+        FileOperationError::NotFound
+    );
+}
+
+#[test]
+fn test_rewriting_identical_content_leaves_store_alone() {
+    let (bridgefs, probe) = instrumented_bridgefs();
+    let mut bridgefs = bridgefs.with_fixed_time(UNIX_EPOCH + Duration::from_secs(1_000_000));
+    let file = bridgefs
+        .create_file(
+            FUSE_ROOT_ID.into(),
+            "rewritten".into(),
+            CommonAttrs::default(),
+        )
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(file.inode, 0, b"same bytes")
+        .unwrap();
+    let manifest = bridgefs.manifest().clone();
+    let writes = probe.writes.get();
+    let root = bridgefs.current_root();
+
+    assert_eq!(bridgefs.write_to_file(file.inode, 0, b"same bytes"), Ok(10));
+    assert_eq!(probe.writes.get(), writes);
+    assert_eq!(bridgefs.manifest(), &manifest);
+    assert_eq!(bridgefs.current_root(), root);
+}
+
+#[test]
+fn test_open_writer_copies_content_in() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "copied".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let content: Vec<u8> = (0..1 << 20).map(|i: u32| (i % 251) as u8).collect();
+
+    let writes = probe.writes.get();
+    let mut writer = bridgefs.open_writer(file.inode).expect("Failed to open");
+    let copied = std::io::copy(&mut content.as_slice(), &mut writer).expect("Failed to copy");
+    assert_eq!(copied, content.len() as u64);
+    drop(writer);
+    // One commit for the whole copy: the block, record and index
+    assert_eq!(probe.writes.get() - writes, 3);
+
+    let record = bridgefs.lookup_file_by_inode(file.inode).unwrap();
+    assert_eq!(record.inner.size, content.len() as u64);
+    assert!(record.inner.common_attrs.mtime >= file.inner.common_attrs.mtime);
+    assert_eq!(bridgefs.read_entire_file(file.inode), Ok(content));
+}
+
+#[test]
+fn test_write_verified_detects_dropped_writes() {
+    let mut store = LossyContentStore::default();
+    let dropping = store.dropping.clone();
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    populate_fixture(&mut bridgefs);
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+
+    assert_eq!(bridgefs.write_verified(file.inode, 0, b"Kept"), Ok(4));
+    dropping.set(true);
+    assert!(bridgefs.write_verified(file.inode, 0, b"Lost").is_err());
+}
+
+#[test]
+fn test_create_directory_already_exists() {
+    let mut bridgefs = in_memory_bridgefs();
+    let result = bridgefs.create_directory(
+        FUSE_ROOT_ID.into(),
+        DIRNAME.into(),
+        CommonAttrs::directory(),
+    );
     assert!(result.is_err());
     assert_eq!(result.unwrap_err(), FileOperationError::AlreadyExists);
 }
+
+#[test]
+fn test_snapshot_read_is_not_torn_by_write() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode;
+
+    let snapshot = bridgefs.snapshot();
+    let first_half = bridgefs.read_file_data_in_snapshot(&snapshot, inode, 0, 7);
+    assert!(first_half.is_ok());
+    assert_eq!(first_half.unwrap().datablock.data, b"Hello, ");
+
+    bridgefs
+        .write_to_file(inode, 0, b"Goodbye, world!!")
+        .expect("Failed to write data");
+
+    let second_half = bridgefs.read_file_data_in_snapshot(&snapshot, inode, 7, 1024);
+    assert!(second_half.is_ok());
+    assert_eq!(second_half.unwrap().datablock.data, b"BridgeFS!");
+
+    let live = bridgefs.read_file_data_by_inode(inode, 0, 1024);
+    assert!(live.is_ok());
+    assert_eq!(live.unwrap().datablock.data, b"Goodbye, world!!");
+}
+
+#[test]
+fn test_write_to_directory() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir_record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir_record.is_ok());
+    let dir_record = dir_record.unwrap();
+
+    let write_result = bridgefs.write_to_file(dir_record.inode, 0, b"not a file");
+    assert!(write_result.is_err());
+    assert_eq!(write_result.unwrap_err(), FileOperationError::IsADirectory);
+
+    // Ensure the directory record is untouched
+    let dir_record_after = bridgefs.lookup_record_by_inode(dir_record.inode);
+    assert!(dir_record_after.is_ok());
+    assert_eq!(dir_record_after.unwrap().inner, dir_record.inner);
+}
+
+#[test]
+fn test_zero_length_write_is_noop() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+    let index_before: HashPointer = bridgefs.snapshot().index_hash().into();
+
+    let write_result = bridgefs.write_to_file(record.inode, 3, b"");
+    assert_eq!(write_result, Ok(0));
+
+    let index_after: HashPointer = bridgefs.snapshot().index_hash().into();
+    assert_eq!(index_before, index_after);
+    let record_after = bridgefs.lookup_record_by_inode(record.inode);
+    assert!(record_after.is_ok());
+    assert_eq!(
+        record_after.unwrap().inner.common_attrs().mtime,
+        record.inner.common_attrs().mtime
+    );
+}
+
+#[test]
+fn test_zero_length_read_skips_content_fetch() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+    let Record::File(file) = &record.inner else {
+        panic!("Expected a file record");
+    };
+    let content_hash: HashPointer = (&file.content_hash).into();
+
+    probe.reads.borrow_mut().clear();
+    let read_result = bridgefs.read_file_data_by_inode(record.inode, 0, 0);
+    assert!(read_result.is_ok());
+    assert!(read_result.unwrap().datablock.is_empty());
+    assert!(!probe.reads.borrow().contains(&content_hash));
+}
+
+#[test]
+fn test_directory_entry_names_skip_child_records() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let root = bridgefs
+        .lookup_record_by_inode(FUSE_ROOT_ID.into())
+        .unwrap();
+    let root_hash: HashPointer = (&root.source).into();
+    let expected = bridgefs
+        .list_directory_by_inode(FUSE_ROOT_ID.into())
+        .unwrap();
+
+    probe.reads.borrow_mut().clear();
+    let names = bridgefs.directory_entry_names(FUSE_ROOT_ID.into()).unwrap();
+    let count = bridgefs.directory_entry_count(FUSE_ROOT_ID.into()).unwrap();
+    assert!(!probe.reads.borrow().is_empty());
+    assert!(probe.reads.borrow().iter().all(|hash| *hash == root_hash));
+
+    let mut expected_names: Vec<_> = expected
+        .entries
+        .into_iter()
+        .map(|entry| entry.name)
+        .filter(|name| *name != ".".into() && *name != "..".into())
+        .collect();
+    expected_names.sort();
+    assert_eq!(names, expected_names);
+    assert_eq!(count, names.len());
+}
+
+#[test]
+fn test_identical_directories_compare_equal_by_hash() {
+    let (bridgefs, probe) = instrumented_bridgefs();
+    let time = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let mut bridgefs = bridgefs.with_fixed_time(time);
+    let attributes = CommonAttrs::builder()
+        .perm(DEFAULT_DIRECTORY_PERM)
+        .atime(time)
+        .mtime(time)
+        .ctime(time)
+        .crtime(time)
+        .build();
+    let mut create = |name: &str| {
+        bridgefs
+            .create_directory(FUSE_ROOT_ID.into(), name.into(), attributes.clone())
+            .expect("Failed to create directory")
+            .inode
+    };
+    let (first, second) = (create("first"), create("second"));
+
+    probe.reads.borrow_mut().clear();
+    assert_eq!(bridgefs.records_equal(first, second), Ok(true));
+    assert_eq!(
+        bridgefs.records_equal(first, FUSE_ROOT_ID.into()),
+        Ok(false)
+    );
+    assert!(probe.reads.borrow().is_empty());
+}
+
+#[test]
+fn test_content_hashes_of_file_and_directory() {
+    let mut bridgefs = in_memory_bridgefs();
+    let file_record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(file_record.is_ok());
+    let file_record = file_record.unwrap().inner;
+    let Record::File(file) = &file_record else {
+        panic!("Expected a file record");
+    };
+    let content_hash: HashPointer = (&file.content_hash).into();
+    assert_eq!(file_record.content_hashes(), vec![content_hash]);
+
+    let dir_record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir_record.is_ok());
+    assert!(dir_record.unwrap().inner.content_hashes().is_empty());
+}
+
+#[test]
+fn test_root_attributes_survive_remount() {
+    let mut store = SharedContentStore::default();
+    let root = Rc::new(RefCell::new(store.empty_root_dir().unwrap().into()));
+    let mount = || {
+        let reference = RacingHashPointerReference::new(root.clone());
+        let bridgefs = BridgeFS::new(reference, store.clone(), ReferenceCounting::Eager);
+        let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+        fuse.ensure_root().expect("Failed to mount");
+        fuse
+    };
+
+    let touched = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let mut fuse = mount();
+    fuse.set_attributes(
+        FUSE_ROOT_ID,
+        None,
+        &SetAttr {
+            mode: Some(0o700),
+            uid: Some(1234),
+            gid: Some(5678),
+            mtime: Some(TimeOrNow::SpecificTime(touched)),
+            ..SetAttr::default()
+        },
+    )
+    .expect("Failed to set root attributes");
+    let before = fuse.file_attr(FUSE_ROOT_ID).unwrap();
+    drop(fuse);
+
+    let after = mount().file_attr(FUSE_ROOT_ID).unwrap();
+    assert_eq!(after, before);
+    assert_eq!((after.perm, after.uid, after.gid), (0o700, 1234, 5678));
+    assert_eq!(after.mtime, touched);
+}
+
+#[test]
+fn test_audit_manifest_flags_a_miscounted_hash() {
+    let mut bridgefs = in_memory_bridgefs();
+    bridgefs
+        .clone_file(FUSE_ROOT_ID.into(), &FILENAME.into(), "copy".into())
+        .expect("Failed to clone file");
+    bridgefs
+        .remove_file_by_name(FUSE_ROOT_ID.into(), &EMPTY_FILENAME.into())
+        .expect("Failed to remove file");
+    assert_eq!(bridgefs.audit_manifest(), Ok(Vec::new()));
+
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    let content_hash = file.inner.content_hashes().remove(0);
+    let mut corrupted = bridgefs.manifest().clone();
+    corrupted.add_reference(content_hash.clone());
+    let mut bridgefs = bridgefs.with_manifest(corrupted);
+
+    assert_eq!(
+        bridgefs.audit_manifest(),
+        Ok(vec![ManifestDiscrepancy {
+            hash: content_hash,
+            recorded: 3,
+            actual: 2,
+        }])
+    );
+}
+
+#[test]
+fn test_racing_update_is_retried() {
+    let mut store = SharedContentStore::default();
+    let root = Rc::new(RefCell::new(store.empty_root_dir().unwrap().into()));
+
+    let mut racer = BridgeFS::new(
+        RacingHashPointerReference::new(root.clone()),
+        store.clone(),
+        ReferenceCounting::Eager,
+    );
+    let mut reference = RacingHashPointerReference::new(root.clone());
+    let swap_attempts = reference.swap_attempts.clone();
+    reference.before_swap = Some(Box::new(move || {
+        racer
+            .create_file(FUSE_ROOT_ID.into(), "racer".into(), CommonAttrs::default())
+            .expect("Failed to create racing file");
+    }));
+    let mut bridgefs = BridgeFS::new(reference, store, ReferenceCounting::Eager);
+
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "writer".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    assert_eq!(swap_attempts.get(), 2);
+
+    let racer_file = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &"racer".into());
+    assert!(racer_file.is_ok());
+    let writer_file = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &"writer".into());
+    assert!(writer_file.is_ok());
+    assert_ne!(racer_file.unwrap().inode, writer_file.unwrap().inode);
+}
+
+#[test]
+fn test_interrupted_write_is_not_committed() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode;
+    let index_before: HashPointer = bridgefs.snapshot().index_hash().into();
+
+    // Simulate the kernel interrupting the request while content is being fetched
+    let interrupt = bridgefs.interrupt_handle();
+    *probe.on_read.borrow_mut() = Some(Box::new(move |_| interrupt.interrupt()));
+
+    let write_result = bridgefs.write_to_file(inode, 0, b"Goodbye");
+    assert!(write_result.is_err());
+    assert_eq!(write_result.unwrap_err(), FileOperationError::Interrupted);
+
+    *probe.on_read.borrow_mut() = None;
+    let index_after: HashPointer = bridgefs.snapshot().index_hash().into();
+    assert_eq!(index_before, index_after);
+
+    // The interrupt only cancels the operation it arrived during
+    let read_result = bridgefs.read_file_data_by_inode(inode, 0, 1024);
+    assert!(read_result.is_ok());
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+}
+
+#[test]
+fn test_interrupted_read() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode;
+
+    let interrupt = bridgefs.interrupt_handle();
+    *probe.on_read.borrow_mut() = Some(Box::new(move |_| interrupt.interrupt()));
+
+    let read_result = bridgefs.read_file_data_by_inode(inode, 0, 1024);
+    assert!(read_result.is_err());
+    assert_eq!(read_result.unwrap_err(), FileOperationError::Interrupted);
+}
+
+#[test]
+fn test_interrupt_stops_a_read_before_the_content_is_fetched() {
+    let shared = SharedContentStore::default();
+    let mut store = shared.clone();
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    populate_fixture(&mut bridgefs);
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    let Record::File(record) = &file.inner else {
+        panic!("Expected a file");
+    };
+    let content_hash = HashPointer::from(&record.content_hash);
+    let root = bridgefs.current_root();
+    drop(bridgefs);
+
+    // A fresh mount over the same content, so the index has to be fetched
+    let store = InstrumentedContentStore {
+        inner: shared.inner.take(),
+        probe: StoreProbe::default(),
+    };
+    let probe = store.probe.clone();
+    let mut fuse = BridgeFSFuse::new(
+        BridgeFS::new(
+            InMemoryHashPointerReference::new(root),
+            store,
+            ReferenceCounting::Eager,
+        ),
+        BridgeFSConfig::default(),
+    );
+    let interrupt = fuse.interrupt_handle();
+    *probe.on_read.borrow_mut() = Some(Box::new(move |_| interrupt.interrupt()));
+
+    let read_result = fuse.read_data(file.inode.get(), 0, 1024);
+    assert_eq!(read_result.unwrap_err(), FileOperationError::Interrupted);
+    assert!(!probe.reads.borrow().contains(&content_hash));
+}
+
+#[test]
+fn test_rename_file() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir.is_ok());
+    let dir_inode = dir.unwrap().inode;
+
+    let rename_result = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &FILENAME.into(),
+        dir_inode,
+        &"moved".into(),
+    );
+    assert!(rename_result.is_ok());
+
+    let old_record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(old_record.is_err());
+    assert_eq!(old_record.unwrap_err(), FileOperationError::NotFound);
+
+    let moved = bridgefs.lookup_record_by_name(dir_inode, &"moved".into());
+    assert!(moved.is_ok());
+    let read_result = bridgefs.read_file_data_by_inode(moved.unwrap().inode, 0, 1024);
+    assert!(read_result.is_ok());
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+}
+
+#[test]
+fn test_rename_over_existing_file() {
+    let mut bridgefs = in_memory_bridgefs();
+    let replaced = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(replaced.is_ok());
+    let replaced = replaced.unwrap();
+    let replaced_content = replaced.inner.content_hashes()[0].clone();
+    let replaced_record: HashPointer = (&replaced.source).into();
+    assert!(bridgefs.manifest().has_reference(&replaced_content));
+
+    let renamed = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &EMPTY_FILENAME.into());
+    assert!(renamed.is_ok());
+    let renamed_inode = renamed.unwrap().inode;
+
+    let rename_result = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &EMPTY_FILENAME.into(),
+        FUSE_ROOT_ID.into(),
+        &FILENAME.into(),
+    );
+    assert!(rename_result.is_ok());
+
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    assert_eq!(record.unwrap().inode, renamed_inode);
+    let old_name = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &EMPTY_FILENAME.into());
+    assert!(old_name.is_err());
+
+    // Nothing refers to the replaced file anymore, so it can be collected
+    assert!(!bridgefs.manifest().has_reference(&replaced_content));
+    assert!(!bridgefs.manifest().has_reference(&replaced_record));
+}
+
+#[test]
+fn test_rename_over_non_empty_directory() {
+    let mut bridgefs = in_memory_bridgefs();
+    let rename_result = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &EMPTY_DIRNAME.into(),
+        FUSE_ROOT_ID.into(),
+        &DIRNAME.into(),
+    );
+    assert!(rename_result.is_err());
+    assert_eq!(
+        rename_result.unwrap_err(),
+        FileOperationError::DirectoryNotEmpty
+    );
+}
+
+#[test]
+fn test_rename_directory_into_itself() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir.is_ok());
+    let dir_inode = dir.unwrap().inode;
+
+    let rename_result = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &DIRNAME.into(),
+        dir_inode,
+        &"inner".into(),
+    );
+    assert!(rename_result.is_err());
+    assert_eq!(
+        rename_result.unwrap_err(),
+        FileOperationError::InvalidArgument
+    );
+}
+
+#[test]
+fn test_rename_onto_itself_is_a_noop() {
+    let mut bridgefs = in_memory_bridgefs();
+    let root_before = HashPointer::from(&bridgefs.commit().unwrap());
+    let rename_result = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &FILENAME.into(),
+        FUSE_ROOT_ID.into(),
+        &FILENAME.into(),
+    );
+    assert!(rename_result.is_ok());
+    assert_eq!(HashPointer::from(&bridgefs.commit().unwrap()), root_before);
+
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    let read_result = bridgefs.read_file_data_by_inode(record.unwrap().inode, 0, 1024);
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+}
+
+#[test]
+fn test_rename_onto_itself_is_not_logged() {
+    let mut bridgefs = in_memory_bridgefs().with_checkpoint_log(CheckpointLog::default());
+    let rename_result = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &DIRNAME.into(),
+        FUSE_ROOT_ID.into(),
+        &DIRNAME.into(),
+    );
+    assert!(rename_result.is_ok());
+    assert!(bridgefs.history().unwrap().is_empty());
+}
+
+#[test]
+fn test_rename_hard_link_over_its_sibling() {
+    let mut bridgefs = in_memory_bridgefs();
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    let dir = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into())
+        .unwrap();
+    bridgefs
+        .link(file.inode, dir.inode, &"sibling".into())
+        .expect("Failed to link file");
+    let root_before = HashPointer::from(&bridgefs.commit().unwrap());
+
+    let rename_result = bridgefs.rename(
+        dir.inode,
+        &"sibling".into(),
+        FUSE_ROOT_ID.into(),
+        &FILENAME.into(),
+    );
+    assert!(rename_result.is_ok());
+    assert_eq!(HashPointer::from(&bridgefs.commit().unwrap()), root_before);
+
+    // Both names are left in place, still sharing the one file
+    let original = bridgefs
+        .lookup_file_by_inode(file.inode)
+        .expect("File should still exist");
+    assert_eq!(original.inner.nlink, 2);
+    let sibling = bridgefs.lookup_record_by_name(dir.inode, &"sibling".into());
+    assert_eq!(sibling.unwrap().inode, file.inode);
+    let renamed = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert_eq!(renamed.unwrap().inode, file.inode);
+}
+
+#[test]
+fn test_rename_missing_source() {
+    let mut bridgefs = in_memory_bridgefs();
+    let rename_result = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &"missing".into(),
+        FUSE_ROOT_ID.into(),
+        &FILENAME.into(),
+    );
+    assert!(rename_result.is_err());
+    let error = rename_result.unwrap_err();
+    assert_eq!(error, FileOperationError::NotFound);
+    assert_eq!(error.to_errno(), libc::ENOENT);
+
+    // The would-be destination is left alone
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+}
+
+#[test]
+fn test_rename_entry_flags() {
+    let bridgefs = in_memory_bridgefs();
+    let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+    let root = FUSE_ROOT_ID;
+    let file = OsStr::new(FILENAME);
+    let empty_file = OsStr::new(EMPTY_FILENAME);
+
+    let error = fuse
+        .rename_entry(0, root, empty_file, root, file, libc::RENAME_NOREPLACE)
+        .unwrap_err();
+    assert_eq!(error.to_errno(), libc::EEXIST);
+
+    let error = fuse
+        .rename_entry(0, root, empty_file, root, file, 1 << 7)
+        .unwrap_err();
+    assert_eq!(error.to_errno(), libc::EINVAL);
+
+    let error = fuse
+        .rename_entry(0, root, OsStr::new("missing"), root, file, 0)
+        .unwrap_err();
+    assert_eq!(error.to_errno(), libc::ENOENT);
+
+    assert!(
+        fuse.rename_entry(0, root, file, root, file, libc::RENAME_NOREPLACE)
+            .is_ok()
+    );
+
+    let before = fuse.lookup_entry(root, file).unwrap();
+    let before_empty = fuse.lookup_entry(root, empty_file).unwrap();
+    assert!(
+        fuse.rename_entry(0, root, file, root, empty_file, libc::RENAME_EXCHANGE)
+            .is_ok()
+    );
+    assert_eq!(
+        fuse.lookup_entry(root, file).unwrap().inode,
+        before_empty.inode
+    );
+    assert_eq!(
+        fuse.lookup_entry(root, empty_file).unwrap().inode,
+        before.inode
+    );
+
+    let error = fuse
+        .rename_entry(
+            0,
+            root,
+            file,
+            root,
+            OsStr::new("missing"),
+            libc::RENAME_EXCHANGE,
+        )
+        .unwrap_err();
+    assert_eq!(error.to_errno(), libc::ENOENT);
+
+    assert!(
+        fuse.rename_entry(0, root, file, root, empty_file, 0)
+            .is_ok()
+    );
+    assert_eq!(
+        fuse.lookup_entry(root, empty_file).unwrap().inode,
+        before_empty.inode
+    );
+    assert_eq!(
+        fuse.lookup_entry(root, file).unwrap_err().to_errno(),
+        libc::ENOENT
+    );
+}
+
+#[test]
+fn test_pinned_snapshot_survives_gc() {
+    let mut store = BoundedInMemoryContentStore::new(1 << 20);
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    bridgefs.rebuild_manifest().unwrap();
+    populate_fixture(&mut bridgefs);
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    let pinned = bridgefs.snapshot();
+    let unpinned_index: HashPointer = pinned.index_hash().into();
+    bridgefs.pin_snapshot(&pinned).unwrap();
+    assert!(bridgefs.pins().has_reference(&unpinned_index));
+
+    for name in [FILENAME, EMPTY_FILENAME] {
+        bridgefs
+            .remove_file_by_name(FUSE_ROOT_ID.into(), &name.into())
+            .expect("Failed to remove file");
+    }
+    for name in [DIRNAME, EMPTY_DIRNAME] {
+        bridgefs
+            .remove_tree(FUSE_ROOT_ID.into(), &name.into())
+            .expect("Failed to remove directory");
+    }
+    bridgefs.gc();
+
+    // The old index, its records and their content are all still there
+    let read_result = bridgefs.read_file_data_in_snapshot(&pinned, file.inode, 0, 1024);
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+    assert!(
+        bridgefs
+            .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+            .is_err()
+    );
+
+    bridgefs.unpin_snapshot(&pinned).unwrap();
+    assert!(!bridgefs.pins().has_reference(&unpinned_index));
+    bridgefs.gc();
+    assert!(bridgefs.read_block(&unpinned_index).is_empty());
+}
+
+#[test]
+fn test_full_store_gives_short_write() {
+    let mut store = BoundedInMemoryContentStore::new(4096);
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    bridgefs.rebuild_manifest().unwrap();
+    bridgefs.set_size_limit_policy(SizeLimitPolicy::ShortWrite);
+    let file = bridgefs
+        .create_file(
+            FUSE_ROOT_ID.into(),
+            "partial".into(),
+            CommonAttrs::default(),
+        )
+        .expect("Failed to create file");
+    bridgefs.write_to_file(file.inode, 0, &[1; 1024]).unwrap();
+
+    let written = bridgefs
+        .write_to_file(file.inode, 512, &[2; 4096])
+        .expect("Write should be short rather than fail");
+    assert!(written > 0 && written < 4096);
+    let record = bridgefs.lookup_file_by_inode(file.inode).unwrap();
+    assert_eq!(record.inner.size, 512 + written as u64);
+    let content = bridgefs.read_entire_file(file.inode).unwrap();
+    assert_eq!(content.len(), 512 + written);
+    assert!(content[..512].iter().all(|&byte| byte == 1));
+    assert!(content[512..].iter().all(|&byte| byte == 2));
+}
+
+#[test]
+fn test_bounded_store_evicts_unreferenced_content() {
+    let mut store = BoundedInMemoryContentStore::new(4096);
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    bridgefs.rebuild_manifest().unwrap();
+
+    let first = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "first".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let second = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "second".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let write_result = bridgefs.write_to_file(first.inode, 0, &[1; 2048]);
+    assert!(write_result.is_ok());
+
+    // Everything stored so far is still referenced, so nothing can be evicted
+    let write_result = bridgefs.write_to_file(second.inode, 0, &[2; 2048]);
+    assert!(write_result.is_err());
+    assert_eq!(write_result.unwrap_err(), FileOperationError::NoSpace);
+
+    let remove_result = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &"first".into());
+    assert!(remove_result.is_ok());
+
+    let write_result = bridgefs.write_to_file(second.inode, 0, &[2; 2048]);
+    assert!(write_result.is_ok());
+    let read_result = bridgefs.read_file_data_by_inode(second.inode, 0, 4096);
+    assert!(read_result.is_ok());
+    assert_eq!(read_result.unwrap().datablock.data, vec![2; 2048]);
+}
+
+#[test]
+fn test_rewrite_and_setattr_keep_content_referenced() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+    let content_hash = record.inner.content_hashes()[0].clone();
+
+    let write_result = bridgefs.write_to_file(record.inode, 0, b"Hello");
+    assert!(write_result.is_ok());
+    let update_result = bridgefs.update_attributes_by_inode(record.inode, CommonAttrs::default());
+    assert!(update_result.is_ok());
+    assert!(bridgefs.manifest().has_reference(&content_hash));
+
+    let remove_result = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(remove_result.is_ok());
+    assert!(!bridgefs.manifest().has_reference(&content_hash));
+}
+
+#[test]
+fn test_reused_inode_gets_higher_generation() {
+    let mut bridgefs = in_memory_bridgefs();
+    let removed = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(removed.is_ok());
+    let removed = removed.unwrap();
+    let remove_result = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(remove_result.is_ok());
+
+    let created = bridgefs.create_file(FUSE_ROOT_ID.into(), "new".into(), CommonAttrs::default());
+    assert!(created.is_ok());
+    let created = created.unwrap();
+    assert_eq!(created.inode, removed.inode);
+    assert!(created.inner.common_attrs.generation > removed.inner.common_attrs().generation);
+
+    let looked_up = bridgefs.lookup_record_by_inode(created.inode);
+    assert!(looked_up.is_ok());
+    assert_eq!(
+        looked_up.unwrap().inner.common_attrs().generation,
+        created.inner.common_attrs.generation
+    );
+}
+
+#[test]
+fn test_immutable_file_refuses_writes_and_unlink() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    let mut attributes = record.inner.common_attrs().clone();
+    attributes.flags |= UF_IMMUTABLE;
+    bridgefs
+        .update_attributes_by_inode(record.inode, attributes.clone())
+        .expect("Failed to set the immutable flag");
+
+    let write = bridgefs.write_to_file(record.inode, 0, b"Howdy");
+    assert_eq!(write.unwrap_err().to_errno(), libc::EPERM);
+    let truncate = bridgefs.truncate(record.inode, 0);
+    assert_eq!(truncate.unwrap_err().to_errno(), libc::EPERM);
+    let unlink = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert_eq!(unlink.unwrap_err().to_errno(), libc::EPERM);
+    let rename = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &FILENAME.into(),
+        FUSE_ROOT_ID.into(),
+        &"moved".into(),
+    );
+    assert_eq!(rename.unwrap_err().to_errno(), libc::EPERM);
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "other".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let replace = bridgefs.rename(
+        FUSE_ROOT_ID.into(),
+        &"other".into(),
+        FUSE_ROOT_ID.into(),
+        &FILENAME.into(),
+    );
+    assert_eq!(replace.unwrap_err().to_errno(), libc::EPERM);
+    let dir = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into())
+        .unwrap();
+    let pinned = bridgefs
+        .lookup_record_by_name(dir.inode, &FILE_UNDER_DIR.into())
+        .unwrap();
+    let mut pinned_attributes = pinned.inner.common_attrs().clone();
+    pinned_attributes.flags |= UF_IMMUTABLE;
+    bridgefs
+        .update_attributes_by_inode(pinned.inode, pinned_attributes)
+        .expect("Failed to set the immutable flag");
+    let remove_tree = bridgefs.remove_tree(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert_eq!(remove_tree.unwrap_err().to_errno(), libc::EPERM);
+    assert!(bridgefs.lookup_record_by_inode(pinned.inode).is_ok());
+    let sealed = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &EMPTY_DIRNAME.into())
+        .unwrap();
+    let mut sealed_attributes = sealed.inner.common_attrs().clone();
+    sealed_attributes.flags |= UF_IMMUTABLE;
+    bridgefs
+        .update_attributes_by_inode(sealed.inode, sealed_attributes)
+        .expect("Failed to set the immutable flag");
+    let rmdir = bridgefs.remove_directory_by_name(FUSE_ROOT_ID.into(), &EMPTY_DIRNAME.into());
+    assert_eq!(rmdir.unwrap_err().to_errno(), libc::EPERM);
+    let mut chmod = attributes.clone();
+    chmod.perm = 0o600;
+    let chmod = bridgefs.update_attributes_by_inode(record.inode, chmod);
+    assert_eq!(chmod.unwrap_err().to_errno(), libc::EPERM);
+    assert_eq!(
+        bridgefs.read_entire_file(record.inode),
+        Ok(b"Hello, BridgeFS!".to_vec())
+    );
+
+    // Clearing the flag is still allowed, and lifts the restrictions
+    attributes.flags &= !UF_IMMUTABLE;
+    bridgefs
+        .update_attributes_by_inode(record.inode, attributes)
+        .expect("Failed to clear the immutable flag");
+    assert!(
+        bridgefs
+            .remove_file_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_update_flags_round_trips() {
+    const UF_HIDDEN: u32 = 0x8000;
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+
+    let mut attributes = record.inner.common_attrs().clone();
+    attributes.flags = UF_HIDDEN;
+    let update_result = bridgefs.update_attributes_by_inode(record.inode, attributes);
+    assert!(update_result.is_ok());
+
+    let record_after = bridgefs.lookup_record_by_inode(record.inode);
+    assert!(record_after.is_ok());
+    let record_after = record_after.unwrap();
+    assert_eq!(record_after.inner.common_attrs().flags, UF_HIDDEN);
+    assert_eq!(
+        record_after.inner.common_attrs().generation,
+        record.inner.common_attrs().generation
+    );
+}
+
+#[test]
+fn test_write_keeps_crtime() {
+    let mut bridgefs = in_memory_bridgefs();
+    let created_at = UNIX_EPOCH + Duration::from_secs(1_000);
+    let attributes = CommonAttrs {
+        mtime: created_at,
+        crtime: created_at,
+        ..CommonAttrs::default()
+    };
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "born".into(), attributes)
+        .unwrap();
+
+    bridgefs.write_to_file(file.inode, 0, b"later").unwrap();
+    let record = bridgefs.lookup_record_by_inode(file.inode).unwrap();
+    let attrs = record.inner.common_attrs();
+    assert_eq!(attrs.crtime, created_at);
+    assert!(attrs.mtime > created_at);
+}
+
+#[test]
+fn test_update_attributes_keeps_crtime_unless_replaced() {
+    let mut bridgefs = in_memory_bridgefs();
+    let created_at = UNIX_EPOCH + Duration::from_secs(1_000);
+    let attributes = CommonAttrs {
+        crtime: created_at,
+        ..CommonAttrs::default()
+    };
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "born".into(), attributes)
+        .unwrap();
+
+    // Attributes built from scratch carry a birth time of now
+    let updated = bridgefs
+        .update_attributes_by_inode(file.inode, CommonAttrs::default())
+        .unwrap();
+    assert_eq!(updated.inner.common_attrs().crtime, created_at);
+
+    let reborn_at = UNIX_EPOCH + Duration::from_secs(2_000);
+    let replaced = bridgefs
+        .replace_attributes_by_inode(
+            file.inode,
+            CommonAttrs {
+                crtime: reborn_at,
+                ..CommonAttrs::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(replaced.inner.common_attrs().crtime, reborn_at);
+}
+
+#[test]
+fn test_recover_replays_uncommitted_operation() {
+    let mut store = SharedContentStore::default();
+    let root = Rc::new(RefCell::new(store.empty_root_dir().unwrap().into()));
+    let journal = InMemoryJournal::default();
+
+    // The process dies after journaling the write but before the root moves
+    let mut reference = RacingHashPointerReference::new(root.clone());
+    reference.before_swap = Some(Box::new(|| panic!("simulated crash")));
+    let mut crashing = BridgeFS::new(reference, store.clone(), ReferenceCounting::Eager)
+        .with_journal(journal.clone());
+    let crash = catch_unwind(AssertUnwindSafe(|| {
+        crashing.create_file(FUSE_ROOT_ID.into(), FILENAME.into(), CommonAttrs::default())
+    }));
+    assert!(crash.is_err());
+    assert_eq!(journal.pending().len(), 1);
+
+    let mut bridgefs = BridgeFS::new(
+        RacingHashPointerReference::new(root),
+        store,
+        ReferenceCounting::Eager,
+    )
+    .with_journal(journal.clone());
+    let lookup_result = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(lookup_result.is_err());
+
+    let recover_result = bridgefs.recover();
+    assert_eq!(recover_result, Ok(1));
+    assert!(journal.pending().is_empty());
+    let lookup_result = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(lookup_result.is_ok());
+}
+
+#[test]
+fn test_recover_skips_committed_operation() {
+    let journal = InMemoryJournal::default();
+    let mut bridgefs = in_memory_bridgefs().with_journal(journal.clone());
+    bridgefs
+        .create_file(
+            FUSE_ROOT_ID.into(),
+            "journaled".into(),
+            CommonAttrs::default(),
+        )
+        .expect("Failed to create file");
+    assert!(journal.pending().is_empty());
+
+    let recover_result = bridgefs.recover();
+    assert_eq!(recover_result, Ok(0));
+}
+
+#[test]
+fn test_store_report_counts_shared_block() {
+    let mut bridgefs = empty_in_memory_bridgefs();
+    let shared = b"shared";
+    let mut content_hash = None;
+    for (uid, name) in ["one", "two", "three"].into_iter().enumerate() {
+        let attributes = CommonAttrs::builder().uid(uid as u32).build();
+        let file = bridgefs
+            .create_file(FUSE_ROOT_ID.into(), name.into(), attributes)
+            .expect("Failed to create file");
+        bridgefs
+            .write_to_file(file.inode, 0, shared)
+            .expect("Failed to write data");
+        let record = bridgefs.lookup_record_by_inode(file.inode).unwrap();
+        content_hash = Some(record.inner.content_hashes()[0].clone());
+    }
+
+    let report = bridgefs.store_report();
+    // The index, the root directory, three file records and the shared block
+    assert_eq!(report.referenced_blocks, 6);
+    assert_eq!(report.total_references, 8);
+    assert_eq!(report.most_referenced, Some((content_hash.unwrap(), 3)));
+    // Two extra copies of the encoded block: a length prefix plus the data
+    assert_eq!(report.dedup_savings, 2 * (1 + shared.len()));
+}
+
+#[test]
+fn test_deferred_counting_skips_bookkeeping() {
+    let mut store = InMemoryContentStore::default();
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Deferred);
+    populate_fixture(&mut bridgefs);
+
+    let remove_result = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &EMPTY_FILENAME.into());
+    assert!(remove_result.is_ok());
+    assert_eq!(bridgefs.manifest().reference_counts().count(), 0);
+
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let read_result = bridgefs.read_file_data_by_inode(record.unwrap().inode, 0, 1024);
+    assert!(read_result.is_ok());
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+}
+
+#[test]
+fn test_deferred_counting_never_collects_the_live_tree() {
+    let mut store = BoundedInMemoryContentStore::new(1 << 20);
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Deferred);
+    populate_fixture(&mut bridgefs);
+
+    bridgefs.gc();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    assert_eq!(
+        bridgefs.read_entire_file(record.unwrap().inode),
+        Ok(b"Hello, BridgeFS!".to_vec())
+    );
+}
+
+#[test]
+fn test_read_entire_file() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let read_result = bridgefs.read_entire_file(record.unwrap().inode);
+    assert!(read_result.is_ok());
+    assert_eq!(read_result.unwrap(), b"Hello, BridgeFS!");
+}
+
+#[test]
+fn test_read_entire_file_empty() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &EMPTY_FILENAME.into());
+    assert!(record.is_ok());
+    let read_result = bridgefs.read_entire_file(record.unwrap().inode);
+    assert!(read_result.is_ok());
+    assert!(read_result.unwrap().is_empty());
+}
+
+#[test]
+fn test_read_entire_file_on_directory() {
+    let mut bridgefs = in_memory_bridgefs();
+    let read_result = bridgefs.read_entire_file(FUSE_ROOT_ID.into());
+    assert!(read_result.is_err());
+    assert_eq!(read_result.unwrap_err(), FileOperationError::IsADirectory);
+}
+
+#[test]
+fn test_zero_length_write_touches_no_content() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+    let content_hash = record.inner.content_hashes()[0].clone();
+
+    probe.reads.borrow_mut().clear();
+    probe.writes.set(0);
+    let write_result = bridgefs.write_to_file(record.inode, 0, b"");
+    assert_eq!(write_result, Ok(0));
+    assert_eq!(probe.writes.get(), 0);
+    assert!(!probe.reads.borrow().contains(&content_hash));
+}
+
+#[test]
+fn test_read_past_end_skips_content_fetch() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+    let content_hash = record.inner.content_hashes()[0].clone();
+
+    probe.reads.borrow_mut().clear();
+    let read_result = bridgefs.read_file_data_by_inode(record.inode, 1024, 16);
+    assert!(read_result.is_ok());
+    assert!(read_result.unwrap().datablock.is_empty());
+    assert!(!probe.reads.borrow().contains(&content_hash));
+}
+
+#[test]
+fn test_content_hash_xattr_matches_store_hash() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+
+    let expected: HashPointer = (&InMemoryContentStore::default()
+        .add_parsed(&DataBlock {
+            data: b"Hello, BridgeFS!".to_vec(),
+        })
+        .unwrap())
+        .into();
+    let value = virtual_xattr(&record, OsStr::new(CONTENT_HASH_XATTR));
+    assert_eq!(value, Some(expected.to_string().into_bytes()));
+    assert_eq!(
+        virtual_xattr(&record, OsStr::new(INODE_XATTR)),
+        Some(record.inode.get().to_string().into_bytes())
+    );
+    assert_eq!(virtual_xattr(&record, OsStr::new("user.other")), None);
+}
+
+#[test]
+fn test_content_hash_xattr_missing_on_directory() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+    assert_eq!(virtual_xattr(&record, OsStr::new(CONTENT_HASH_XATTR)), None);
+}
+
+#[test]
+fn test_getxattr_size_probe() {
+    let bridgefs = in_memory_bridgefs();
+    let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+    let record = fuse
+        .lookup_entry(FUSE_ROOT_ID, OsStr::new(FILENAME))
+        .unwrap();
+    let expected = record.inode.get().to_string().into_bytes();
+    let name = OsStr::new(INODE_XATTR);
+
+    let probe = fuse.xattr_value(record.inode.get(), name, 0);
+    assert_eq!(probe, Ok(XattrReply::Size(expected.len() as u32)));
+    let exact = fuse.xattr_value(record.inode.get(), name, expected.len() as u32);
+    assert_eq!(exact, Ok(XattrReply::Data(expected.clone())));
+    let roomy = fuse.xattr_value(record.inode.get(), name, 4096);
+    assert_eq!(roomy, Ok(XattrReply::Data(expected)));
+
+    // A probe for an attribute that does not exist still fails
+    let missing = fuse.xattr_value(record.inode.get(), OsStr::new("user.other"), 0);
+    assert_eq!(missing, Err(libc::ENODATA));
+    assert_eq!(
+        fuse.xattr_list(record.inode.get(), 0),
+        Ok(XattrReply::Size(0))
+    );
+    assert_eq!(
+        fuse.xattr_list(record.inode.get(), 64),
+        Ok(XattrReply::Data(Vec::new()))
+    );
+}
+
+#[test]
+fn test_getxattr_buffer_too_small() {
+    let bridgefs = in_memory_bridgefs();
+    let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+    let record = fuse
+        .lookup_entry(FUSE_ROOT_ID, OsStr::new(FILENAME))
+        .unwrap();
+    let Ok(XattrReply::Size(needed)) =
+        fuse.xattr_value(record.inode.get(), OsStr::new(CONTENT_HASH_XATTR), 0)
+    else {
+        panic!("Probe should return the needed size");
+    };
+    let result = fuse.xattr_value(
+        record.inode.get(),
+        OsStr::new(CONTENT_HASH_XATTR),
+        needed - 1,
+    );
+    assert_eq!(result, Err(libc::ERANGE));
+}
+
+#[test]
+fn test_checkpoint_log_appends_each_commit() {
+    let mut bridgefs = empty_in_memory_bridgefs().with_checkpoint_log(CheckpointLog::default());
+    assert!(bridgefs.history().unwrap().is_empty());
+
+    let file = bridgefs.create_file(FUSE_ROOT_ID.into(), FILENAME.into(), CommonAttrs::default());
+    assert!(file.is_ok());
+    let first_root: HashPointer = bridgefs.snapshot().index_hash().into();
+    let write_result = bridgefs.write_to_file(file.unwrap().inode, 0, b"Hello, BridgeFS!");
+    assert!(write_result.is_ok());
+    let second_root: HashPointer = bridgefs.snapshot().index_hash().into();
+
+    let history = bridgefs.history().unwrap();
+    assert_eq!(history.len(), 2);
+    assert!(history[0].0 <= history[1].0);
+    assert_eq!(HashPointer::from(&history[0].1), first_root);
+    assert_eq!(HashPointer::from(&history[1].1), second_root);
+}
+
+#[test]
+fn test_checkpoint_log_skips_failed_operations() {
+    let mut bridgefs = in_memory_bridgefs().with_checkpoint_log(CheckpointLog::default());
+    let result = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &"missing".into());
+    assert!(result.is_err());
+    assert!(bridgefs.history().unwrap().is_empty());
+}
+
+#[test]
+fn test_block_size_is_reported_in_attrs() {
+    let mut bridgefs = empty_in_memory_bridgefs();
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), FILENAME.into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(file.inode, 0, &[7; 5000])
+        .expect("Failed to write data");
+
+    let config = BridgeFSConfig {
+        block_size: 4096,
+        ..BridgeFSConfig::default()
+    };
+    let mut fuse = BridgeFSFuse::new(bridgefs, config);
+    let attr = fuse.file_attr(file.inode.get());
+    assert!(attr.is_ok());
+    let attr = attr.unwrap();
+    assert_eq!(attr.size, 5000);
+    assert_eq!(attr.blocks, 2);
+    assert_eq!(attr.blksize, 4096);
+}
+
+#[test]
+fn test_open_with_o_direct_requests_direct_io() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode.get();
+    let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+
+    let direct = fuse.open_flags(inode, libc::O_RDWR | libc::O_DIRECT);
+    assert_eq!(direct, Ok(FOPEN_DIRECT_IO));
+    let cached = fuse.open_flags(inode, libc::O_RDWR);
+    assert_eq!(cached, Ok(0));
+    let missing = fuse.open_flags(9999, libc::O_DIRECT);
+    assert_eq!(missing.unwrap_err(), FileOperationError::NotFound);
+}
+
+#[test]
+fn test_unlink_one_hardlink_keeps_content() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let record = record.unwrap();
+    let content_hash = record.inner.content_hashes()[0].clone();
+
+    let linked = bridgefs.link(record.inode, FUSE_ROOT_ID.into(), &"link".into());
+    assert!(linked.is_ok());
+    assert_eq!(linked.unwrap().inner.nlink, 2);
+
+    let remove_result = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(remove_result.is_ok());
+    assert!(bridgefs.manifest().has_reference(&content_hash));
+    let remaining = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &"link".into());
+    assert!(remaining.is_ok());
+    let remaining = remaining.unwrap();
+    assert_eq!(remaining.inode, record.inode);
+    let read_result = bridgefs.read_file_data_by_inode(remaining.inode, 0, 1024);
+    assert!(read_result.is_ok());
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+
+    let remove_result = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &"link".into());
+    assert!(remove_result.is_ok());
+    assert!(!bridgefs.manifest().has_reference(&content_hash));
+}
+
+#[test]
+fn test_link_directory_not_permitted() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir.is_ok());
+    let result = bridgefs.link(dir.unwrap().inode, FUSE_ROOT_ID.into(), &"link".into());
+    assert!(result.is_err());
+    assert_eq!(result.unwrap_err(), FileOperationError::NotPermitted);
+}
+
+#[test]
+fn test_remove_tree() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir.is_ok());
+    let dir = dir.unwrap();
+    let child = bridgefs.lookup_record_by_name(dir.inode, &FILE_UNDER_DIR.into());
+    assert!(child.is_ok());
+    let child = child.unwrap();
+    let child_content = child.inner.content_hashes()[0].clone();
+
+    let remove_result = bridgefs.remove_tree(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(remove_result.is_ok());
+
+    let lookup_result = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert_eq!(lookup_result.unwrap_err(), FileOperationError::NotFound);
+    let lookup_result = bridgefs.lookup_record_by_inode(dir.inode);
+    assert_eq!(lookup_result.unwrap_err(), FileOperationError::NotFound);
+    let lookup_result = bridgefs.lookup_record_by_inode(child.inode);
+    assert_eq!(lookup_result.unwrap_err(), FileOperationError::NotFound);
+
+    let manifest = bridgefs.manifest();
+    assert!(!manifest.has_reference(&(&dir.source).into()));
+    assert!(!manifest.has_reference(&(&child.source).into()));
+    assert!(!manifest.has_reference(&child_content));
+}
+
+#[test]
+fn test_remove_tree_on_file() {
+    let mut bridgefs = in_memory_bridgefs();
+    let result = bridgefs.remove_tree(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(result.is_err());
+    assert_eq!(result.unwrap_err(), FileOperationError::NotADirectory);
+    let lookup_result = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(lookup_result.is_ok());
+}
+
+/// A span's name and its `inode` field, if it has one
+type CapturedSpan = (&'static str, Option<u64>);
+
+/// Records every span that is opened
+#[derive(Clone, Default)]
+struct SpanCapture {
+    spans: Arc<Mutex<Vec<CapturedSpan>>>,
+}
+
+struct InodeVisitor(Option<u64>);
+
+impl Visit for InodeVisitor {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "inode" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn Debug) {}
+}
+
+impl<S: Subscriber> Layer<S> for SpanCapture {
+    fn on_new_span(&self, attributes: &Attributes<'_>, _id: &Id, _context: Context<'_, S>) {
+        let mut visitor = InodeVisitor(None);
+        attributes.record(&mut visitor);
+        let name = attributes.metadata().name();
+        self.spans.lock().unwrap().push((name, visitor.0));
+    }
+}
+
+#[test]
+fn test_read_emits_span_with_inode() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode.get();
+    let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+
+    let capture = SpanCapture::default();
+    let subscriber = Registry::default().with(capture.clone());
+    let read_result =
+        tracing::subscriber::with_default(subscriber, || fuse.read_data(inode, 0, 1024));
+    assert!(read_result.is_ok());
+    assert!(
+        capture
+            .spans
+            .lock()
+            .unwrap()
+            .contains(&("read", Some(inode)))
+    );
+}
+
+#[test]
+fn test_truncate_to_zero_skips_content_fetch() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode;
+    bridgefs
+        .write_to_file(inode, 0, &[7; 64 * 1024])
+        .expect("Failed to write data");
+    let record = bridgefs.lookup_record_by_inode(inode).unwrap();
+    let content_hash = record.inner.content_hashes()[0].clone();
+
+    probe.reads.borrow_mut().clear();
+    let truncate_result = bridgefs.truncate(inode, 0);
+    assert!(truncate_result.is_ok());
+    assert_eq!(truncate_result.unwrap().inner.size, 0);
+    assert!(!probe.reads.borrow().contains(&content_hash));
+    assert!(!bridgefs.manifest().has_reference(&content_hash));
+
+    let read_result = bridgefs.read_entire_file(inode);
+    assert_eq!(read_result, Ok(Vec::new()));
+}
+
+#[test]
+fn test_truncate_shrinks_and_extends() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode;
+
+    assert!(bridgefs.truncate(inode, 5).is_ok());
+    assert_eq!(bridgefs.read_entire_file(inode), Ok(b"Hello".to_vec()));
+    assert!(bridgefs.truncate(inode, 7).is_ok());
+    assert_eq!(bridgefs.read_entire_file(inode), Ok(b"Hello\0\0".to_vec()));
+}
+
+#[test]
+fn test_write_within_truncated_file_keeps_size() {
+    let mut bridgefs = in_memory_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode;
+
+    assert!(bridgefs.truncate(inode, 5).is_ok());
+    let write_result = bridgefs.write_to_file(inode, 0, b"Je");
+    assert_eq!(write_result, Ok(2));
+
+    let file = bridgefs.lookup_file_by_inode(inode);
+    assert!(file.is_ok());
+    assert_eq!(file.unwrap().inner.size, 5);
+    assert_eq!(bridgefs.read_entire_file(inode), Ok(b"Jello".to_vec()));
+}
+
+#[test]
+fn test_subtree_physical_size_counts_shared_content_once() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir.is_ok());
+    let dir = dir.unwrap().inode;
+    let copy = bridgefs
+        .create_file(dir, "copy".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(copy.inode, 0, b"File under directory")
+        .expect("Failed to write data");
+
+    let stored_size = |data: &[u8]| {
+        let datablock = DataBlock {
+            data: data.to_vec(),
+        };
+        bincode::encode_to_vec(&datablock, bincode::config::standard())
+            .unwrap()
+            .len() as u64
+    };
+    let physical_size = bridgefs.subtree_physical_size(dir);
+    assert_eq!(physical_size, Ok(stored_size(b"File under directory")));
+
+    let logical_size = 2 * b"File under directory".len() as u64;
+    assert!(physical_size.unwrap() < logical_size);
+}
+
+#[test]
+fn test_with_root_at_mounts_a_subdirectory() {
+    let mut bridgefs = in_memory_bridgefs();
+    let snapshot = bridgefs.snapshot();
+    let mut subtree = bridgefs
+        .with_root_at(snapshot.index_hash().clone(), Path::new(DIRNAME))
+        .expect("Failed to mount the subdirectory");
+
+    let file = subtree
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILE_UNDER_DIR.into())
+        .expect("file_under_dir should be at the new root");
+    assert_eq!(
+        subtree.read_entire_file(file.inode),
+        Ok(b"File under directory".to_vec())
+    );
+    let root = subtree.lookup_parent_by_inode(FUSE_ROOT_ID.into()).unwrap();
+    assert_eq!(root.inode, FUSE_ROOT_ID.into());
+    let outside = subtree.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert_eq!(outside.unwrap_err(), FileOperationError::NotFound);
+}
+
+#[test]
+fn test_overlay_copies_up_without_touching_lower_root() {
+    let (mut bridgefs, lower_probe) = instrumented_bridgefs();
+    let lower = bridgefs.snapshot();
+    let file = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(file.is_ok());
+    let file = file.unwrap();
+    let lower_writes = lower_probe.writes.get();
+
+    let upper = InstrumentedContentStore::default();
+    let upper_probe = upper.probe.clone();
+    let mut overlay = bridgefs.overlay(lower.index_hash().clone(), upper).unwrap();
+    let write_result = overlay.write_to_file(file.inode, 0, b"Howdy");
+    assert!(write_result.is_ok());
+    let remove_result = overlay.remove_file_by_name(FUSE_ROOT_ID.into(), &EMPTY_FILENAME.into());
+    assert!(remove_result.is_ok());
+
+    assert_eq!(lower_probe.writes.get(), lower_writes);
+    assert!(upper_probe.writes.get() > 0);
+    assert_ne!(
+        HashPointer::from(overlay.snapshot().index_hash()),
+        HashPointer::from(lower.index_hash())
+    );
+    assert_eq!(
+        overlay.read_entire_file(file.inode),
+        Ok(b"Howdy, BridgeFS!".to_vec())
+    );
+
+    let lower_read = overlay.read_file_data_in_snapshot(&lower, file.inode, 0, 1024);
+    assert!(lower_read.is_ok());
+    assert_eq!(lower_read.unwrap().datablock.data, b"Hello, BridgeFS!");
+    let lower_root = overlay.lookup_record_in_snapshot(
+        &Snapshot::new(lower.index_hash().clone()),
+        FUSE_ROOT_ID.into(),
+    );
+    assert!(lower_root.is_ok());
+    let Record::Directory(lower_root) = lower_root.unwrap().inner else {
+        panic!("root should be a directory");
+    };
+    assert!(lower_root.get(&EMPTY_FILENAME.into()).is_some());
+}
+
+#[test]
+fn test_overlay_reads_upper_content_the_upper_store_kept() {
+    let mut bridgefs = in_memory_bridgefs();
+    let lower = bridgefs.snapshot();
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+
+    // The upper store never evicts, so gc leaves everything in it
+    let mut overlay = bridgefs
+        .overlay(lower.index_hash().clone(), InMemoryContentStore::default())
+        .unwrap();
+    overlay.write_to_file(file.inode, 0, b"Howdy").unwrap();
+    let copied_up = overlay.snapshot();
+    overlay.write_to_file(file.inode, 0, b"Hiya!").unwrap();
+    overlay.gc();
+
+    let read = overlay.read_file_data_in_snapshot(&copied_up, file.inode, 0, 1024);
+    assert!(read.is_ok());
+    assert_eq!(read.unwrap().datablock.data, b"Howdy, BridgeFS!");
+}
+
+fn dangling_root_bridgefs(
+    reinit_on_missing_root: bool,
+) -> BridgeFSFuse<InMemoryHashPointerReference, InMemoryContentStore> {
+    // The index only exists in a store the filesystem never sees
+    let dangling = InMemoryContentStore::default().empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(dangling.into());
+    let bridgefs = BridgeFS::new(
+        pointer,
+        InMemoryContentStore::default(),
+        ReferenceCounting::Eager,
+    );
+    let config = BridgeFSConfig {
+        reinit_on_missing_root,
+        ..BridgeFSConfig::default()
+    };
+    BridgeFSFuse::new(bridgefs, config)
+}
+
+#[test]
+fn test_record_of_the_wrong_type_is_reported_as_corrupt() {
+    let mut store = InMemoryContentStore::default();
+    let block = store
+        .add_parsed(&DataBlock {
+            data: b"Hello, BridgeFS!".to_vec(),
+        })
+        .unwrap();
+    let misread = TypedHashPointer::<Record>::new(block.into());
+    assert!(store.try_get_parsed(&misread).is_err());
+
+    let root = DirectoryRecord::builder()
+        .children([("file".into(), 2.into())].into())
+        .parent(FUSE_ROOT_ID.into())
+        .build();
+    let root_hash = store.add_parsed(&Record::Directory(root)).unwrap();
+    let mut index = INodeIndex::new(FUSE_ROOT_ID.into(), root_hash);
+    index.update_inode(2.into(), misread);
+    let index = store.add_parsed(&index).unwrap();
+    let mut bridgefs = BridgeFS::new(
+        InMemoryHashPointerReference::new(index.into()),
+        store,
+        ReferenceCounting::Eager,
+    );
+
+    let lookup_result = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &"file".into());
+    assert!(lookup_result.is_err());
+    let error = lookup_result.unwrap_err();
+    assert_eq!(error, FileOperationError::Corrupt);
+    assert_eq!(error.to_errno(), libc::EIO);
+    // The rest of the tree is still usable
+    assert!(bridgefs.lookup_record_by_inode(FUSE_ROOT_ID.into()).is_ok());
+}
+
+#[test]
+fn test_undecodable_index_is_reported_as_corrupt_rather_than_panicking() {
+    let mut store = InMemoryContentStore::default();
+    let garbage = store.add_content(b"not an index").unwrap();
+    let mut bridgefs = BridgeFS::new(
+        InMemoryHashPointerReference::new(garbage.clone()),
+        store,
+        ReferenceCounting::Eager,
+    );
+
+    let count_result = bridgefs.inode_count();
+    assert!(count_result.is_err());
+    let error = count_result.unwrap_err();
+    assert_eq!(error, FileOperationError::Corrupt);
+    // The decode failure is kept for whoever logs the error
+    let message = error.to_string();
+    assert!(message.contains(&garbage.to_string()));
+    assert!(message.contains("does not decode"));
+
+    assert_eq!(
+        bridgefs.rebuild_manifest().unwrap_err(),
+        FileOperationError::Corrupt
+    );
+    let snapshot = bridgefs.snapshot();
+    assert_eq!(
+        bridgefs.pin_snapshot(&snapshot).unwrap_err(),
+        FileOperationError::Corrupt
+    );
+}
+
+#[test]
+fn test_missing_root_index_is_reported_as_corrupt() {
+    let mut bridgefs = dangling_root_bridgefs(false);
+    let result = bridgefs.ensure_root();
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert_eq!(error, FileOperationError::Corrupt);
+    assert_eq!(error.to_errno(), libc::EIO);
+}
+
+#[test]
+fn test_missing_root_index_is_reinitialized_when_requested() {
+    let mut bridgefs = dangling_root_bridgefs(true);
+    assert!(bridgefs.ensure_root().is_ok());
+    let root = bridgefs.file_attr(FUSE_ROOT_ID);
+    assert!(root.is_ok());
+    assert_eq!(root.unwrap().kind, FileType::Directory);
+    assert!(bridgefs.ensure_root().is_ok());
+}
+
+#[test]
+fn test_common_attrs_round_trip_through_file_attr() {
+    let attrs = CommonAttrs::builder()
+        .perm(0o640)
+        .uid(1000)
+        .gid(100)
+        .atime(UNIX_EPOCH + Duration::from_secs(1))
+        .mtime(UNIX_EPOCH + Duration::from_secs(2))
+        .ctime(UNIX_EPOCH + Duration::from_secs(3))
+        .crtime(UNIX_EPOCH + Duration::from_secs(4))
+        .flags(0x8000)
+        .build();
+    let file_attr = attrs.to_file_attr(7.into(), FileType::RegularFile, 1025, 1, 512);
+    assert_eq!(file_attr.ino, 7);
+    assert_eq!(file_attr.size, 1025);
+    assert_eq!(file_attr.blocks, 3);
+    assert_eq!(file_attr.perm, 0o640);
+    assert_eq!(CommonAttrs::from_file_attr(&file_attr), attrs);
+}
+
+#[test]
+fn test_apply_setattr_only_changes_requested_fields() {
+    let original = CommonAttrs::builder()
+        .perm(0o644)
+        .mtime(UNIX_EPOCH)
+        .ctime(UNIX_EPOCH)
+        .flags(1)
+        .build();
+    let mut attrs = original.clone();
+    attrs.apply_setattr(&SetAttr::default());
+    assert_eq!(attrs, original);
+
+    let atime = UNIX_EPOCH + Duration::from_secs(60);
+    attrs.apply_setattr(&SetAttr {
+        mode: Some(0o600),
+        atime: Some(TimeOrNow::SpecificTime(atime)),
+        mtime: Some(TimeOrNow::Now),
+        ..SetAttr::default()
+    });
+    assert_eq!(attrs.perm, 0o600);
+    assert_eq!(attrs.atime, atime);
+    assert!(attrs.mtime > UNIX_EPOCH);
+    assert_eq!(attrs.ctime, original.ctime);
+    assert_eq!(attrs.flags, original.flags);
+}
+
+#[test]
+fn test_read_raw_record_decodes_to_directory() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir.is_ok());
+    let dir = dir.unwrap();
+
+    let raw = bridgefs.read_raw_record(dir.inode);
+    assert!(raw.is_ok());
+    let raw = raw.unwrap();
+    let (decoded, _): (Record, usize) =
+        bincode::decode_from_slice(&raw, bincode::config::standard()).unwrap();
+    let Record::Directory(directory) = decoded else {
+        panic!("dir should decode to a directory record");
+    };
+    assert!(directory.get(&FILE_UNDER_DIR.into()).is_some());
+    assert_eq!(Record::Directory(directory), dir.inner);
+    assert_eq!(
+        InMemoryContentStore::default().add_content(&raw),
+        Ok(HashPointer::from(&dir.source))
+    );
+
+    let missing = bridgefs.read_raw_record(1000.into());
+    assert_eq!(missing.unwrap_err(), FileOperationError::NotFound);
+}
+
+#[test]
+fn test_max_file_size_refuses_growth_past_limit() {
+    let mut bridgefs = in_memory_bridgefs();
+    bridgefs.set_max_file_size(Some(1024));
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode;
+
+    let full = bridgefs.write_to_file(inode, 0, &[1; 1024]);
+    assert_eq!(full, Ok(1024));
+    let past = bridgefs.write_to_file(inode, 1000, &[2; 25]);
+    assert!(past.is_err());
+    let error = past.unwrap_err();
+    assert_eq!(error, FileOperationError::FileTooLarge);
+    assert_eq!(error.to_errno(), libc::EFBIG);
+    let truncate_result = bridgefs.truncate(inode, 1025);
+    assert_eq!(
+        truncate_result.unwrap_err(),
+        FileOperationError::FileTooLarge
+    );
+
+    assert_eq!(bridgefs.read_entire_file(inode), Ok(vec![1; 1024]));
+
+    let overflowing = bridgefs.write_to_file(inode, usize::MAX, &[3; 2]);
+    assert_eq!(overflowing.unwrap_err(), FileOperationError::FileTooLarge);
+}
+
+#[test]
+fn test_prove_path_verifies_against_root() {
+    let mut bridgefs = in_memory_bridgefs();
+    let root = bridgefs.snapshot();
+    let path = [DIRNAME.into(), FILE_UNDER_DIR.into()];
+    let proof = bridgefs.prove_path(FUSE_ROOT_ID.into(), &path);
+    assert!(proof.is_ok());
+    let proof = proof.unwrap();
+
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    let file = bridgefs.lookup_record_by_name(dir.unwrap().inode, &FILE_UNDER_DIR.into());
+    assert!(file.is_ok());
+    assert_eq!(proof.verify(root.index_hash()), Some(file.unwrap().inner));
+
+    let mut tampered = proof.clone();
+    tampered.path[1] = FILENAME.into();
+    assert_eq!(tampered.verify(root.index_hash()), None);
+
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "other".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    assert_eq!(proof.verify(bridgefs.snapshot().index_hash()), None);
+
+    let missing = bridgefs.prove_path(FUSE_ROOT_ID.into(), &["missing".into()]);
+    assert_eq!(missing.unwrap_err(), FileOperationError::NotFound);
+}
+
+#[test]
+fn test_default_attrs_keep_new_files_non_executable() {
+    let mut bridgefs = in_memory_bridgefs();
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "new".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    assert_eq!(file.inner.common_attrs.perm, DEFAULT_FILE_PERM);
+    assert!(!file.inner.is_executable());
+
+    let directory = bridgefs
+        .create_directory(
+            FUSE_ROOT_ID.into(),
+            "new_dir".into(),
+            CommonAttrs::directory(),
+        )
+        .expect("Failed to create directory");
+    assert_eq!(directory.inner.common_attrs.perm, DEFAULT_DIRECTORY_PERM);
+    let root = bridgefs
+        .lookup_record_by_inode(FUSE_ROOT_ID.into())
+        .unwrap();
+    assert_eq!(root.inner.common_attrs().perm, DEFAULT_DIRECTORY_PERM);
+}
+
+#[test]
+fn test_path_of_nested_file() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir.is_ok());
+    let nested = bridgefs
+        .create_directory(
+            dir.unwrap().inode,
+            "nested".into(),
+            CommonAttrs::directory(),
+        )
+        .expect("Failed to create directory");
+    let file = bridgefs
+        .create_file(nested.inode, FILENAME.into(), CommonAttrs::default())
+        .expect("Failed to create file");
+
+    let path = bridgefs.path_of(file.inode);
+    assert_eq!(path, Ok(PathBuf::from("/dir/nested/file")));
+    let path = bridgefs.path_of(nested.inode);
+    assert_eq!(path, Ok(PathBuf::from("/dir/nested")));
+    let path = bridgefs.path_of(FUSE_ROOT_ID.into());
+    assert_eq!(path, Ok(PathBuf::from("/")));
+    let missing = bridgefs.path_of(1000.into());
+    assert_eq!(missing.unwrap_err(), FileOperationError::NotFound);
+}
+
+#[test]
+fn test_commit_returns_a_reconstructible_root() {
+    let store = SharedContentStore::default();
+    let mut bridgefs = BridgeFS::new(
+        InMemoryHashPointerReference::new(store.clone().empty_root_dir().unwrap().into()),
+        store.clone(),
+        ReferenceCounting::Eager,
+    );
+    populate_fixture(&mut bridgefs);
+    let committed = bridgefs.commit();
+    assert!(committed.is_ok());
+    let committed = committed.unwrap();
+
+    let mut reopened = BridgeFS::new(
+        InMemoryHashPointerReference::new(committed.into()),
+        store,
+        ReferenceCounting::Eager,
+    );
+    let listed = [&mut bridgefs, &mut reopened].map(|bridgefs| {
+        let root = bridgefs.list_directory_by_inode(FUSE_ROOT_ID.into());
+        assert!(root.is_ok());
+        root.unwrap().directory.inner
+    });
+    assert_eq!(listed[0], listed[1]);
+    let file = reopened.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(file.is_ok());
+    assert_eq!(
+        reopened.read_entire_file(file.unwrap().inode),
+        Ok(b"Hello, BridgeFS!".to_vec())
+    );
+    assert_eq!(
+        HashPointer::from(&reopened.commit().unwrap()),
+        HashPointer::from(&bridgefs.commit().unwrap())
+    );
+}
+
+#[test]
+fn test_sticky_directory_denies_removal_by_others() {
+    let mut bridgefs = in_memory_bridgefs();
+    let shared = bridgefs
+        .create_directory(
+            FUSE_ROOT_ID.into(),
+            "shared".into(),
+            CommonAttrs::builder().perm(0o1777).uid(0).build(),
+        )
+        .expect("Failed to create directory");
+    bridgefs
+        .create_file(
+            shared.inode,
+            FILENAME.into(),
+            CommonAttrs::builder().uid(1000).build(),
+        )
+        .expect("Failed to create file");
+
+    let denied = bridgefs.check_removal_by(shared.inode, &FILENAME.into(), 2000);
+    assert!(denied.is_err());
+    let error = denied.unwrap_err();
+    assert_eq!(error, FileOperationError::NotPermitted);
+    assert_eq!(error.to_errno(), libc::EPERM);
+    assert!(
+        bridgefs
+            .check_removal_by(shared.inode, &FILENAME.into(), 1000)
+            .is_ok()
+    );
+    assert!(
+        bridgefs
+            .check_removal_by(shared.inode, &"missing".into(), 2000)
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_sticky_directory_removal_as_requester() {
+    let mut bridgefs = in_memory_bridgefs();
+    let shared = bridgefs
+        .create_directory(
+            FUSE_ROOT_ID.into(),
+            "shared".into(),
+            CommonAttrs::builder().perm(0o1777).uid(0).build(),
+        )
+        .expect("Failed to create directory");
+    bridgefs
+        .create_file(
+            shared.inode,
+            FILENAME.into(),
+            CommonAttrs::builder().uid(1000).build(),
+        )
+        .expect("Failed to create file");
+    bridgefs
+        .create_directory(
+            shared.inode,
+            DIRNAME.into(),
+            CommonAttrs::builder().perm(0o755).uid(1000).build(),
+        )
+        .expect("Failed to create directory");
+
+    let denied = bridgefs.remove_file_by_name_as(shared.inode, &FILENAME.into(), 2000);
+    assert_eq!(denied.unwrap_err(), FileOperationError::NotPermitted);
+    let denied = bridgefs.remove_directory_by_name_as(shared.inode, &DIRNAME.into(), 2000);
+    assert_eq!(denied.unwrap_err(), FileOperationError::NotPermitted);
+    assert!(
+        bridgefs
+            .lookup_record_by_name(shared.inode, &FILENAME.into())
+            .is_ok()
+    );
+
+    // The owner of the entry, and the owner of the directory, may remove it
+    assert!(
+        bridgefs
+            .remove_file_by_name_as(shared.inode, &FILENAME.into(), 1000)
+            .is_ok()
+    );
+    assert!(
+        bridgefs
+            .remove_directory_by_name_as(shared.inode, &DIRNAME.into(), 0)
+            .is_ok()
+    );
+    let listing = bridgefs.list_directory_by_inode(shared.inode).unwrap();
+    assert_eq!(listing.entries.len(), 2);
+}
+
+#[test]
+fn test_write_clears_setuid_and_setgid() {
+    let mut bridgefs = in_memory_bridgefs();
+    let file = bridgefs
+        .create_file(
+            FUSE_ROOT_ID.into(),
+            "setuid".into(),
+            CommonAttrs::builder().perm(0o6755).build(),
+        )
+        .expect("Failed to create file");
+
+    assert!(bridgefs.write_to_file(file.inode, 0, b"changed").is_ok());
+    let written = bridgefs.lookup_file_by_inode(file.inode);
+    assert!(written.is_ok());
+    assert_eq!(written.unwrap().inner.common_attrs.perm, 0o755);
+}
+
+#[test]
+fn test_short_write_fills_up_to_max_file_size() {
+    let mut bridgefs = in_memory_bridgefs();
+    bridgefs.set_max_file_size(Some(1024));
+    bridgefs.set_size_limit_policy(SizeLimitPolicy::ShortWrite);
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let inode = record.unwrap().inode;
+
+    assert_eq!(bridgefs.write_to_file(inode, 0, &[1; 1000]), Ok(1000));
+    assert_eq!(bridgefs.write_to_file(inode, 1000, &[2; 100]), Ok(24));
+    let full = bridgefs.write_to_file(inode, 1024, &[3; 10]);
+    assert_eq!(full.unwrap_err(), FileOperationError::FileTooLarge);
+
+    let data = bridgefs.read_entire_file(inode);
+    assert!(data.is_ok());
+    let data = data.unwrap();
+    assert_eq!(data.len(), 1024);
+    assert_eq!(&data[1000..], &[2; 24]);
+}
+
+#[test]
+fn test_compressible_file_is_stored_compressed() {
+    let mut bridgefs = in_memory_bridgefs().with_compression(Compression::Lz4);
+    let file = bridgefs
+        .create_file(
+            FUSE_ROOT_ID.into(),
+            "log.txt".into(),
+            CommonAttrs::default(),
+        )
+        .expect("Failed to create file");
+    let data = "Hello, BridgeFS!\n".repeat(1024).into_bytes();
+    bridgefs
+        .write_to_file(file.inode, 0, &data)
+        .expect("Failed to write data");
+
+    let record = bridgefs.lookup_file_by_inode(file.inode).unwrap().inner;
+    assert_eq!(record.compression, Some(Compression::Lz4));
+    assert_eq!(record.size, data.len() as u64);
+    let stored_size = bridgefs.subtree_physical_size(file.inode).unwrap();
+    assert!(stored_size < data.len() as u64 / 10);
+
+    let read_result = bridgefs.read_file_data_by_inode(file.inode, 0, data.len());
+    assert_eq!(read_result.unwrap().datablock.data, data);
+    let read_result = bridgefs.read_file_data_by_inode(file.inode, 17, 5);
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello");
+
+    // Too small to be worth it
+    let small = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    bridgefs
+        .write_to_file(small.inode, 0, b"Howdy")
+        .expect("Failed to write data");
+    let record = bridgefs.lookup_file_by_inode(small.inode).unwrap().inner;
+    assert_eq!(record.compression, None);
+}
+
+#[test]
+fn test_compressed_and_plain_copies_share_one_hash() {
+    let store = SharedContentStore::default();
+    let data = "Hello, BridgeFS!\n".repeat(1024).into_bytes();
+    let write = |compression: Option<Compression>| {
+        let mut store = store.clone();
+        let pointer = InMemoryHashPointerReference::new(store.empty_root_dir().unwrap().into());
+        let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+        if let Some(compression) = compression {
+            bridgefs = bridgefs.with_compression(compression);
+        }
+        let file = bridgefs
+            .create_file(
+                FUSE_ROOT_ID.into(),
+                "log.txt".into(),
+                CommonAttrs::default(),
+            )
+            .expect("Failed to create file");
+        bridgefs
+            .write_to_file(file.inode, 0, &data)
+            .expect("Failed to write data");
+        let record = bridgefs.lookup_file_by_inode(file.inode).unwrap();
+        (bridgefs, record)
+    };
+
+    let (mut compressed, compressed_file) = write(Some(Compression::Lz4));
+    assert_eq!(compressed_file.inner.compression, Some(Compression::Lz4));
+    let used = store.inner.borrow().used();
+    let (mut plain, plain_file) = write(None);
+    assert_eq!(
+        plain_file.inner.content_hash,
+        compressed_file.inner.content_hash
+    );
+    // The plain copy found the compressed block already stored
+    assert!(store.inner.borrow().used() - used < data.len());
+    assert_eq!(plain.read_entire_file(plain_file.inode), Ok(data.clone()));
+
+    let mut archive = Vec::new();
+    let root = backup(&mut compressed, &mut archive).expect("Failed to back up");
+    let mut fresh = InMemoryContentStore::default();
+    restore(archive.as_slice(), &mut fresh).expect("Failed to restore");
+    let pointer = InMemoryHashPointerReference::new(root.into());
+    let mut restored = BridgeFS::new(pointer, fresh, ReferenceCounting::Eager);
+    assert_eq!(restored.read_entire_file(compressed_file.inode), Ok(data));
+}
+
+#[test]
+fn test_getattr_with_handle_skips_the_index() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let inode = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap()
+        .inode
+        .get();
+    let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+    let (fh, _) = fuse.open_handle(inode, libc::O_RDONLY).unwrap();
+    let expected = fuse.file_attr(inode).unwrap();
+
+    probe.reads.borrow_mut().clear();
+    let attr = fuse.file_attr_with_handle(inode, Some(fh)).unwrap();
+    assert_eq!(attr, expected);
+    assert!(probe.reads.borrow().is_empty());
+
+    // A handle for some other inode is not trusted
+    assert!(fuse.file_attr_with_handle(FUSE_ROOT_ID, Some(fh)).is_ok());
+    assert!(!probe.reads.borrow().is_empty());
+
+    // Any change moves the root, so the cached record is read again once
+    let root = FUSE_ROOT_ID;
+    fuse.rename_entry(0, root, OsStr::new(DIRNAME), root, OsStr::new("moved"), 0)
+        .unwrap();
+    probe.reads.borrow_mut().clear();
+    assert_eq!(
+        fuse.file_attr_with_handle(inode, Some(fh)).unwrap(),
+        expected
+    );
+    assert!(!probe.reads.borrow().is_empty());
+    probe.reads.borrow_mut().clear();
+    assert_eq!(
+        fuse.file_attr_with_handle(inode, Some(fh)).unwrap(),
+        expected
+    );
+    assert!(probe.reads.borrow().is_empty());
+
+    fuse.release_handle(fh);
+    assert_eq!(
+        fuse.file_attr_with_handle(inode, Some(fh)).unwrap(),
+        expected
+    );
+    assert!(!probe.reads.borrow().is_empty());
+}
+
+#[test]
+fn test_block_cache_fetches_shared_content_once() {
+    let (bridgefs, probe) = instrumented_bridgefs();
+    let mut bridgefs = bridgefs.with_block_cache(16);
+    let mut inodes = Vec::new();
+    for name in ["first", "second"] {
+        let file = bridgefs
+            .create_file(FUSE_ROOT_ID.into(), name.into(), CommonAttrs::default())
+            .expect("Failed to create file");
+        bridgefs
+            .write_to_file(file.inode, 0, b"shared content")
+            .expect("Failed to write data");
+        inodes.push(file.inode);
+    }
+    let content_hash = bridgefs
+        .lookup_file_by_inode(inodes[0])
+        .unwrap()
+        .inner
+        .content_hash;
+    assert_eq!(
+        bridgefs
+            .lookup_file_by_inode(inodes[1])
+            .unwrap()
+            .inner
+            .content_hash,
+        content_hash
+    );
+
+    probe.reads.borrow_mut().clear();
+    for inode in inodes {
+        let read_result = bridgefs.read_file_data_by_inode(inode, 0, 1024);
+        assert!(read_result.is_ok());
+        assert_eq!(read_result.unwrap().datablock.data, b"shared content");
+    }
+    let content_hash = HashPointer::from(&content_hash);
+    let fetches = probe
+        .reads
+        .borrow()
+        .iter()
+        .filter(|&hash| *hash == content_hash)
+        .count();
+    assert_eq!(fetches, 1);
+}
+
+#[test]
+fn test_sequential_reads_fetch_content_once() {
+    let (bridgefs, probe) = instrumented_bridgefs();
+    let mut bridgefs = bridgefs.with_block_cache(16);
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "large".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let data: Vec<u8> = (0..64 * 1024).map(|i| i as u8).collect();
+    bridgefs
+        .write_to_file(file.inode, 0, &data)
+        .expect("Failed to write data");
+    let content_hash = HashPointer::from(
+        &bridgefs
+            .lookup_file_by_inode(file.inode)
+            .unwrap()
+            .inner
+            .content_hash,
+    );
+
+    // A file is one block, so the first read already brings in what every
+    // later chunk is served from
+    probe.reads.borrow_mut().clear();
+    let mut read_back = Vec::new();
+    for offset in (0..data.len()).step_by(4096) {
+        let chunk = bridgefs
+            .read_file_data_by_inode(file.inode, offset, 4096)
+            .expect("Failed to read data");
+        read_back.extend(chunk.datablock.data);
+    }
+    assert_eq!(read_back, data);
+    let fetches = probe
+        .reads
+        .borrow()
+        .iter()
+        .filter(|&hash| *hash == content_hash)
+        .count();
+    assert_eq!(fetches, 1);
+}
+
+#[test]
+fn test_missing_blocks_for_peer_with_only_the_root() {
+    let mut bridgefs = in_memory_bridgefs();
+    let root = HashPointer::from(&bridgefs.commit().unwrap());
+    let mut peer = InMemoryContentStore::default();
+    peer.add_content(&bridgefs.read_block(&root)).unwrap();
+
+    let have = BTreeSet::from([root.clone()]);
+    let missing = bridgefs.missing_blocks(&have).unwrap();
+    // Six records, plus the content of the empty file and the two written ones
+    assert_eq!(missing.len(), 9);
+    assert!(!missing.contains(&root));
+    for inode in [FUSE_ROOT_ID, 2, 3, 4, 5, 6] {
+        let record = bridgefs.lookup_record_by_inode(inode.into()).unwrap();
+        assert!(missing.contains(&(&record.source).into()));
+        for content_hash in record.inner.content_hashes() {
+            assert!(missing.contains(&content_hash));
+        }
+    }
+
+    let mut have = have;
+    for hash in missing {
+        let stored = peer.add_content(&bridgefs.read_block(&hash)).unwrap();
+        assert_eq!(stored, hash);
+        have.insert(hash);
+    }
+    assert!(bridgefs.missing_blocks(&have).unwrap().is_empty());
+
+    let mut synced = BridgeFS::new(
+        InMemoryHashPointerReference::new(root),
+        peer,
+        ReferenceCounting::Eager,
+    );
+    let file = synced
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    let read_result = synced.read_file_data_by_inode(file.inode, 0, 1024);
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+}
+
+#[test]
+fn test_dotdot_of_orphaned_directory_leads_to_root() {
+    let mut store = InMemoryContentStore::default();
+    // The recorded parent was never in the index
+    let orphan = DirectoryRecord::builder().parent(42.into()).build();
+    let orphan_hash = store.add_parsed(&Record::Directory(orphan)).unwrap();
+    let root = DirectoryRecord::builder()
+        .children([("orphan".into(), 2.into())].into())
+        .parent(FUSE_ROOT_ID.into())
+        .build();
+    let root_hash = store.add_parsed(&Record::Directory(root)).unwrap();
+    let mut index = INodeIndex::new(FUSE_ROOT_ID.into(), root_hash);
+    index.update_inode(2.into(), orphan_hash);
+    let index = store.add_parsed(&index).unwrap();
+    let mut bridgefs = BridgeFS::new(
+        InMemoryHashPointerReference::new(index.into()),
+        store,
+        ReferenceCounting::Eager,
+    );
+
+    let listing = bridgefs.list_directory_by_inode(2.into());
+    assert!(listing.is_ok());
+    let dotdot = listing
+        .unwrap()
+        .entries
+        .into_iter()
+        .find(|entry| entry.name == "..".into())
+        .unwrap();
+    assert_eq!(dotdot.record.inode, FUSE_ROOT_ID.into());
+
+    let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+    let entry = fuse.lookup_entry(2, OsStr::new(".."));
+    assert_eq!(entry.unwrap().inode, FUSE_ROOT_ID.into());
+}
+
+fn build_reproducible_tree() -> HashPointer {
+    let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let attrs = CommonAttrs::builder()
+        .atime(time)
+        .mtime(time)
+        .ctime(time)
+        .crtime(time)
+        .build();
+    let root = DirectoryRecord::builder()
+        .common_attrs(CommonAttrs {
+            perm: DEFAULT_DIRECTORY_PERM,
+            ..attrs.clone()
+        })
+        .parent(FUSE_ROOT_ID.into())
+        .build();
+    let mut store = InMemoryContentStore::default();
+    let root_hash = store.add_parsed(&Record::Directory(root)).unwrap();
+    let index = store
+        .add_parsed(&INodeIndex::new(FUSE_ROOT_ID.into(), root_hash))
+        .unwrap();
+    let mut bridgefs = BridgeFS::new(
+        InMemoryHashPointerReference::new(index.into()),
+        store,
+        ReferenceCounting::Eager,
+    )
+    .with_fixed_time(time);
+
+    for name in ["b", "a", "c", "e", "d"] {
+        let file = bridgefs
+            .create_file(FUSE_ROOT_ID.into(), name.into(), attrs.clone())
+            .expect("Failed to create file");
+        bridgefs
+            .write_to_file(file.inode, 0, name.as_bytes())
+            .expect("Failed to write data");
+    }
+    let dir = bridgefs
+        .create_directory(FUSE_ROOT_ID.into(), DIRNAME.into(), attrs.clone())
+        .expect("Failed to create directory");
+    bridgefs
+        .rename(FUSE_ROOT_ID.into(), &"c".into(), dir.inode, &"c".into())
+        .expect("Failed to rename file");
+    bridgefs
+        .remove_file_by_name(FUSE_ROOT_ID.into(), &"a".into())
+        .expect("Failed to remove file");
+    (&bridgefs.commit().unwrap()).into()
+}
+
+#[test]
+fn test_same_operations_produce_same_root_hash() {
+    assert_eq!(build_reproducible_tree(), build_reproducible_tree());
+}
+
+#[test]
+fn test_in_memory_budget_reports_no_space() {
+    let mut store = InMemoryContentStore::with_budget(4096);
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), FILENAME.into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let write_result = bridgefs.write_to_file(file.inode, 0, &[1; 2048]);
+    assert!(write_result.is_ok());
+
+    let write_result = bridgefs.write_to_file(file.inode, 2048, &[2; 2048]);
+    assert!(write_result.is_err());
+    let error = write_result.unwrap_err();
+    assert_eq!(error, FileOperationError::NoSpace);
+    assert_eq!(error.to_errno(), libc::ENOSPC);
+
+    let read_result = bridgefs.read_entire_file(file.inode);
+    assert_eq!(read_result, Ok(vec![1; 2048]));
+}
+
+#[test]
+fn test_in_memory_budget_is_freed_by_deleting_files() {
+    let mut store = InMemoryContentStore::with_budget(4096);
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    bridgefs.rebuild_manifest().unwrap();
+    let first = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "first".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    assert!(bridgefs.write_to_file(first.inode, 0, &[1; 3072]).is_ok());
+
+    let remove_result = bridgefs.remove_file_by_name(FUSE_ROOT_ID.into(), &"first".into());
+    assert!(remove_result.is_ok());
+
+    let second = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "second".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    let write_result = bridgefs.write_to_file(second.inode, 0, &[2; 3072]);
+    assert!(write_result.is_ok());
+    assert_eq!(bridgefs.read_entire_file(second.inode), Ok(vec![2; 3072]));
+}
+
+#[test]
+fn test_generation_is_stable_until_inode_reuse() {
+    let mut bridgefs = in_memory_bridgefs();
+    let dir = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &DIRNAME.into());
+    assert!(dir.is_ok());
+    let dir = dir.unwrap();
+    let file = bridgefs.lookup_record_by_name(dir.inode, &FILE_UNDER_DIR.into());
+    assert!(file.is_ok());
+    let file = file.unwrap();
+    let generation = file.inner.common_attrs().generation;
+
+    assert!(bridgefs.write_to_file(file.inode, 0, b"changed").is_ok());
+    assert!(
+        bridgefs
+            .update_attributes_by_inode(file.inode, CommonAttrs::default())
+            .is_ok()
+    );
+    let rename_result = bridgefs.rename(
+        dir.inode,
+        &FILE_UNDER_DIR.into(),
+        FUSE_ROOT_ID.into(),
+        &"moved".into(),
+    );
+    assert!(rename_result.is_ok());
+    let link_result = bridgefs.link(file.inode, dir.inode, &"linked".into());
+    assert!(link_result.is_ok());
+
+    let mut fuse = BridgeFSFuse::new(bridgefs, BridgeFSConfig::default());
+    let moved = fuse.lookup_entry(FUSE_ROOT_ID, OsStr::new("moved"));
+    assert!(moved.is_ok());
+    let moved = moved.unwrap();
+    assert_eq!(moved.inode, file.inode);
+    assert_eq!(moved.generation(), generation);
+
+    // A handle holding only the directory inode can still reach its parent
+    let this = fuse.lookup_entry(dir.inode.into(), OsStr::new("."));
+    assert_eq!(this.unwrap().inode, dir.inode);
+    let parent = fuse.lookup_entry(dir.inode.into(), OsStr::new(".."));
+    assert_eq!(parent.unwrap().inode, FUSE_ROOT_ID.into());
+    let not_a_directory = fuse.lookup_entry(file.inode.into(), OsStr::new(".."));
+    assert_eq!(
+        not_a_directory.unwrap_err(),
+        FileOperationError::NotADirectory
+    );
+}
+
+#[test]
+fn test_clone_file_shares_content_until_written() {
+    let (mut bridgefs, probe) = instrumented_bridgefs();
+    let record = bridgefs.lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into());
+    assert!(record.is_ok());
+    let original = bridgefs
+        .lookup_file_by_inode(record.unwrap().inode)
+        .unwrap();
+    let content_hash = HashPointer::from(&original.inner.content_hash);
+
+    let writes = probe.writes.get();
+    let clone = bridgefs.clone_file(FUSE_ROOT_ID.into(), &FILENAME.into(), "clone".into());
+    assert!(clone.is_ok());
+    let clone = clone.unwrap();
+    assert_ne!(clone.inode, original.inode);
+    assert_eq!(HashPointer::from(&clone.inner.content_hash), content_hash);
+    // Only the new record, the directory and the index are stored
+    assert_eq!(probe.writes.get() - writes, 3);
+
+    assert!(bridgefs.write_to_file(clone.inode, 0, b"Howdy").is_ok());
+    assert_eq!(
+        bridgefs.read_entire_file(clone.inode),
+        Ok(b"Howdy, BridgeFS!".to_vec())
+    );
+    assert_eq!(
+        bridgefs.read_entire_file(original.inode),
+        Ok(b"Hello, BridgeFS!".to_vec())
+    );
+    assert!(bridgefs.manifest().has_reference(&content_hash));
+
+    let directory = bridgefs.clone_file(FUSE_ROOT_ID.into(), &DIRNAME.into(), "dir_clone".into());
+    assert_eq!(directory.unwrap_err(), FileOperationError::IsADirectory);
+}
+
+#[test]
+fn test_clone_file_keeps_source_timestamps() {
+    let cloned_at = UNIX_EPOCH + Duration::from_secs(2_000_000);
+    let mut bridgefs = in_memory_bridgefs().with_fixed_time(cloned_at);
+    let attributes = CommonAttrs::builder()
+        .atime(UNIX_EPOCH + Duration::from_secs(1_000))
+        .mtime(UNIX_EPOCH + Duration::from_secs(2_000))
+        .ctime(UNIX_EPOCH + Duration::from_secs(3_000))
+        .crtime(UNIX_EPOCH + Duration::from_secs(500))
+        .build();
+    bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "archived".into(), attributes.clone())
+        .expect("Failed to create file");
+
+    let clone = bridgefs
+        .clone_file(FUSE_ROOT_ID.into(), &"archived".into(), "clone".into())
+        .expect("Failed to clone file");
+    let times = &clone.inner.common_attrs;
+    assert_eq!(times.atime, attributes.atime);
+    assert_eq!(times.mtime, attributes.mtime);
+    assert_eq!(times.crtime, attributes.crtime);
+    // The change time records the clone itself
+    assert_eq!(times.ctime, cloned_at);
+
+    let fresh = bridgefs
+        .clone_file_with_times(
+            FUSE_ROOT_ID.into(),
+            &"archived".into(),
+            "fresh".into(),
+            CloneTimes::Reset,
+        )
+        .expect("Failed to clone file");
+    let times = &fresh.inner.common_attrs;
+    assert_eq!(
+        [times.atime, times.mtime, times.ctime, times.crtime],
+        [cloned_at; 4]
+    );
+}
+
+#[test]
+fn test_backup_restores_the_same_tree_into_a_fresh_store() {
+    let mut bridgefs = in_memory_bridgefs();
+    // Content that is no longer live should be left out of the backup
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    bridgefs.write_to_file(file.inode, 0, b"Howdy").unwrap();
+
+    let mut archive = Vec::new();
+    let root = backup(&mut bridgefs, &mut archive).expect("Failed to back up");
+    assert_eq!(HashPointer::from(&root), bridgefs.current_root());
+    let live_blocks = bridgefs.missing_blocks(&BTreeSet::new()).unwrap().len();
+
+    let mut store = InMemoryContentStore::default();
+    let restored_root = restore(archive.as_slice(), &mut store).expect("Failed to restore");
+    assert_eq!(HashPointer::from(&restored_root), HashPointer::from(&root));
+    let pointer = InMemoryHashPointerReference::new(restored_root.into());
+    let mut restored = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    assert_eq!(
+        restored.missing_blocks(&BTreeSet::new()).unwrap().len(),
+        live_blocks
+    );
+    assert_eq!(
+        restored.read_entire_file(file.inode),
+        Ok(b"Howdy, BridgeFS!".to_vec())
+    );
+    let names = |bridgefs: &mut BridgeFS<_, _>| -> Vec<String> {
+        let listing = bridgefs
+            .list_directory_by_inode(FUSE_ROOT_ID.into())
+            .unwrap();
+        listing.entries.iter().map(|e| e.name.to_string()).collect()
+    };
+    assert_eq!(names(&mut restored), names(&mut bridgefs));
+
+    // Without its root the blocks cannot be put back together
+    let mut rootless = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(5);
+    rootless
+        .append_data(&mut header, "blocks/0", &b"block"[..])
+        .unwrap();
+    let rootless = rootless.into_inner().unwrap();
+    let result = restore(rootless.as_slice(), &mut InMemoryContentStore::default());
+    assert!(matches!(result, Err(RestoreError::MissingRoot)));
+}
+
+#[test]
+fn test_tree_json_lists_fixture_paths_and_hashes() {
+    let mut bridgefs = in_memory_bridgefs();
+    let mut json = Vec::new();
+    write_tree_json(&mut bridgefs, &mut json).expect("Failed to write tree");
+    let json = String::from_utf8(json).unwrap();
+
+    assert!(json.starts_with('[') && json.trim_end().ends_with(']'));
+    for path in [
+        "/".to_string(),
+        format!("/{EMPTY_FILENAME}"),
+        format!("/{FILENAME}"),
+        format!("/{DIRNAME}"),
+        format!("/{DIRNAME}/{FILE_UNDER_DIR}"),
+    ] {
+        assert!(json.contains(&format!("\"path\": \"{path}\"")), "{path}");
+    }
+    let file = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &FILENAME.into())
+        .unwrap();
+    let Record::File(record) = file.inner else {
+        panic!("Expected a file");
+    };
+    let hash = HashPointer::from(&record.content_hash).to_string();
+    assert!(json.contains(&format!("\"content_hash\": \"{hash}\"")));
+    // Content is represented by its hash, never inlined
+    assert!(!json.contains("Hello, BridgeFS!"));
+    // Directories come before their children, each in name order
+    let position = |path: &str| json.find(&format!("\"path\": \"{path}\"")).unwrap();
+    assert!(position(&format!("/{DIRNAME}")) < position(&format!("/{DIRNAME}/{FILE_UNDER_DIR}")));
+    assert!(position(&format!("/{DIRNAME}/{FILE_UNDER_DIR}")) < position(&format!("/{FILENAME}")));
+}
+
+/// A small archive with an explicit directory, a file whose parents are
+/// left out, a hard link and a symlink
+fn fixture_tar() -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o750);
+    header.set_mtime(1_000_000);
+    header.set_size(0);
+    builder
+        .append_data(&mut header, "./docs/", std::io::empty())
+        .unwrap();
+
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o600);
+    header.set_uid(1000);
+    header.set_mtime(2_000_000);
+    header.set_size(5);
+    builder
+        .append_data(&mut header, "./docs/readme", &b"hello"[..])
+        .unwrap();
+    header.set_size(4);
+    builder
+        .append_data(&mut header, "nested/deep/file", &b"deep"[..])
+        .unwrap();
+
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Link);
+    header.set_size(0);
+    builder
+        .append_link(&mut header, "docs/alias", "./docs/readme")
+        .unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    builder
+        .append_link(&mut header, "docs/symlink", "readme")
+        .unwrap();
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn test_import_tar_into_empty_filesystem() {
+    let mut bridgefs = empty_in_memory_bridgefs();
+    let imported = import_tar(
+        &mut bridgefs,
+        fixture_tar().as_slice(),
+        FilenameEncoding::Raw,
+    )
+    .expect("Failed to import archive");
+    // The symlink is skipped
+    assert_eq!(imported, 4);
+
+    let docs = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &"docs".into())
+        .unwrap();
+    assert!(matches!(docs.inner, Record::Directory(_)));
+    assert_eq!(docs.inner.common_attrs().perm, 0o750);
+
+    let readme = bridgefs
+        .lookup_record_by_name(docs.inode, &"readme".into())
+        .unwrap();
+    let attrs = readme.inner.common_attrs();
+    assert_eq!(attrs.perm, 0o600);
+    assert_eq!(attrs.uid, 1000);
+    assert_eq!(attrs.mtime, UNIX_EPOCH + Duration::from_secs(2_000_000));
+    assert_eq!(bridgefs.read_entire_file(readme.inode).unwrap(), b"hello");
+
+    let alias = bridgefs
+        .lookup_record_by_name(docs.inode, &"alias".into())
+        .unwrap();
+    assert_eq!(alias.inode, readme.inode);
+    assert!(
+        bridgefs
+            .lookup_record_by_name(docs.inode, &"symlink".into())
+            .is_err()
+    );
+
+    let nested = bridgefs
+        .lookup_record_by_name(FUSE_ROOT_ID.into(), &"nested".into())
+        .unwrap();
+    assert_eq!(nested.inner.common_attrs().perm, DEFAULT_DIRECTORY_PERM);
+    let deep = bridgefs
+        .lookup_record_by_name(nested.inode, &"deep".into())
+        .unwrap();
+    let file = bridgefs
+        .lookup_record_by_name(deep.inode, &"file".into())
+        .unwrap();
+    assert_eq!(bridgefs.read_entire_file(file.inode).unwrap(), b"deep");
+}
+
+#[test]
+fn test_import_tar_refuses_filesystem_with_content() {
+    let mut bridgefs = in_memory_bridgefs();
+    let root = bridgefs.current_root();
+    let result = import_tar(
+        &mut bridgefs,
+        fixture_tar().as_slice(),
+        FilenameEncoding::Raw,
+    );
+    assert!(matches!(result, Err(ImportError::NotEmpty)));
+    assert_eq!(bridgefs.current_root(), root);
+}
+
+/// Collects the access trace a `TracingContentStore` writes
+#[derive(Default, Clone)]
+struct TraceBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl TraceBuffer {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Write for TraceBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tracing_store_records_accesses_in_order() {
+    let trace = TraceBuffer::default();
+    let mut store =
+        TracingContentStore::new(InMemoryContentStore::default()).with_trace_file(trace.clone());
+    let hello = store.add_content(b"hello").unwrap();
+    let other = store.add_content(b"other content").unwrap();
+    assert_eq!(store.get_content(&hello), b"hello");
+    assert_eq!(
+        trace.lines(),
+        vec![
+            format!("add {hello} 5"),
+            format!("add {other} 13"),
+            format!("get {hello} 5"),
+        ]
+    );
+}
+
+#[test]
+fn test_tracing_store_traces_a_file_write() {
+    let trace = TraceBuffer::default();
+    let mut store =
+        TracingContentStore::new(InMemoryContentStore::default()).with_trace_file(trace.clone());
+    let initial_index_hash = store.empty_root_dir().unwrap();
+    let pointer = InMemoryHashPointerReference::new(initial_index_hash.into());
+    let mut bridgefs = BridgeFS::new(pointer, store, ReferenceCounting::Eager);
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "traced".into(), CommonAttrs::default())
+        .unwrap();
+    trace.0.lock().unwrap().clear();
+
+    let root_before = bridgefs.current_root();
+    bridgefs.write_to_file(file.inode, 0, b"traced").unwrap();
+    let lines = trace.lines();
+
+    // The write starts from the index the create left decoded, without
+    // fetching it again, and ends by storing a new one
+    assert!(
+        !lines
+            .iter()
+            .any(|line| line.starts_with(&format!("get {root_before} ")))
+    );
+    let datablock = DataBlock {
+        data: b"traced".to_vec(),
+    };
+    let encoded = bincode::encode_to_vec(&datablock, bincode::config::standard()).unwrap();
+    let datablock_hash: HashPointer = (&InMemoryContentStore::default()
+        .add_parsed(&datablock)
+        .unwrap())
+        .into();
+    assert!(lines.contains(&format!("add {datablock_hash} {}", encoded.len())));
+    let root_after = bridgefs.current_root();
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.starts_with(&format!("add {root_after} ")))
+    );
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "object-store")]
+
+use std::sync::Arc;
+
+use bridgefs_core::{
+    bridgefs::BridgeFS,
+    content_store::{ContentStore, ContentStoreError, InMemoryContentStore},
+    file_record::CommonAttrs,
+    hash_pointer::InMemoryHashPointerReference,
+    manifest::Manifest,
+    manifest::ReferenceCounting,
+};
+use bridgefs_fuse::{fuse_store_ext::FuseStoreExt, object_store_adapter::ObjectStoreContentStore};
+use fuser::FUSE_ROOT_ID;
+use object_store::{ObjectStore, memory::InMemory};
+
+fn in_memory_object_store() -> ObjectStoreContentStore {
+    ObjectStoreContentStore::new(Arc::new(InMemory::new()), "bridgefs").unwrap()
+}
+
+#[test]
+fn test_object_store_round_trips_content() {
+    let mut store = in_memory_object_store();
+    let hash = store.add_content(b"Hello, BridgeFS!").unwrap();
+    assert_eq!(store.get_content(&hash), b"Hello, BridgeFS!");
+
+    // Adding the same bytes again is a no-op rather than a conflict
+    assert_eq!(store.add_content(b"Hello, BridgeFS!").unwrap(), hash);
+    assert_eq!(store.list_content().unwrap(), vec![hash]);
+}
+
+#[test]
+fn test_object_store_reports_missing_content_instead_of_panicking() {
+    let store = in_memory_object_store();
+    let missing = InMemoryContentStore::default()
+        .add_content(b"never stored")
+        .unwrap();
+    assert_eq!(
+        store.try_get_content(&missing),
+        Err(ContentStoreError::Unavailable { transient: false })
+    );
+}
+
+#[test]
+fn test_object_store_shards_blocks_by_hash_prefix() {
+    let objects: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let mut store = ObjectStoreContentStore::new(objects.clone(), "bridgefs").unwrap();
+    let hash = store.add_content(b"Hello, BridgeFS!").unwrap();
+
+    let name = hash.to_string().replace('+', "-").replace('/', "_");
+    let path = format!("bridgefs/{}/{}", &name[..2], name);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    assert!(runtime.block_on(objects.head(&path.into())).is_ok());
+}
+
+#[test]
+fn test_object_store_deletes_content() {
+    let mut store = in_memory_object_store();
+    let kept = store.add_content(b"kept").unwrap();
+    let removed = store.add_content(b"removed").unwrap();
+
+    store.remove_content(&removed).unwrap();
+    assert!(!store.contains(&removed).unwrap());
+    assert!(store.contains(&kept).unwrap());
+    // Removing it twice is harmless
+    store.remove_content(&removed).unwrap();
+
+    let mut manifest = Manifest::default();
+    manifest.add_reference(kept.clone());
+    let unreferenced = store.add_content(b"unreferenced").unwrap();
+    store.evict_unreferenced(&manifest);
+    assert!(!store.contains(&unreferenced).unwrap());
+    assert_eq!(store.get_content(&kept), b"kept");
+}
+
+#[test]
+fn test_bridgefs_over_object_store() {
+    let mut store = in_memory_object_store();
+    let root = store.empty_root_dir().unwrap();
+    let mut bridgefs = BridgeFS::new(
+        InMemoryHashPointerReference::new(root.into()),
+        store,
+        ReferenceCounting::Eager,
+    );
+    let file = bridgefs
+        .create_file(FUSE_ROOT_ID.into(), "file".into(), CommonAttrs::default())
+        .expect("Failed to create file");
+    bridgefs
+        .write_to_file(file.inode, 0, b"Hello, BridgeFS!")
+        .expect("Failed to write data");
+    let read_result = bridgefs.read_file_data_by_inode(file.inode, 0, 1024);
+    assert_eq!(read_result.unwrap().datablock.data, b"Hello, BridgeFS!");
+}